@@ -0,0 +1,216 @@
+-- Consolidated schema matching db::User, db::Idea, db::Rate, db::Transaction and the
+-- market, payout and ledger tables. Token amounts, prices and cash values are exact
+-- NUMERIC(20, 8) read as decimal::Decimal; ledger and escrow amounts are integer smallest units.
+
+CREATE OR REPLACE FUNCTION update_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    username TEXT NOT NULL,
+    wallet_address TEXT NOT NULL UNIQUE,
+    category VARCHAR(50), -- optional category for user classification
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Trade parties are users, or 'amm' for the market maker, which is a system account rather
+-- than a user
+CREATE OR REPLACE FUNCTION check_trade_parties()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF (NEW.buyer_id <> 'amm' AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.buyer_id))
+        OR (NEW.seller_id <> 'amm' AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.seller_id))
+    THEN
+        RAISE EXCEPTION 'Trade party is neither a user nor the market maker'
+            USING ERRCODE = 'foreign_key_violation';
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TABLE ideas (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    creator_id TEXT NOT NULL REFERENCES users(id),
+    category TEXT NOT NULL,
+    initial_price NUMERIC(20, 8) NOT NULL,
+    target_price NUMERIC(20, 8) NOT NULL,
+    timeframe TEXT NOT NULL,
+    risk_level INTEGER NOT NULL,
+    market_size TEXT NOT NULL,
+    competitive_advantage TEXT NOT NULL,
+    pricing_mode TEXT NOT NULL DEFAULT 'order_book', -- order_book or constant_product
+    resolver_id TEXT REFERENCES users(id), -- may resolve the idea besides its creator
+    token_mint TEXT UNIQUE, -- SPL mint mirroring holdings on-chain
+    status TEXT NOT NULL DEFAULT 'active', -- active, closed, resolved or voided
+    resolution_outcome TEXT, -- null until resolved
+    settlement_price NUMERIC(20, 8), -- price every token was settled at, null until settled
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    resolved_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Price history of each idea, one row per trade
+CREATE TABLE rates (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    rate NUMERIC(20, 8) NOT NULL,
+    volume NUMERIC(20, 8) NOT NULL DEFAULT 0, -- tokens traded at this rate
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE transactions (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    buyer_id TEXT NOT NULL, -- a user or 'amm', see check_trade_parties
+    seller_id TEXT NOT NULL,
+    amount NUMERIC(20, 8) NOT NULL, -- idea tokens
+    rate NUMERIC(20, 8) NOT NULL,
+    total_value NUMERIC(20, 8) NOT NULL, -- amount * rate
+    status TEXT NOT NULL DEFAULT 'pending', -- pending, completed or failed
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Reserves of the constant-product market maker backing curve-priced ideas
+CREATE TABLE amm_pools (
+    idea_id TEXT PRIMARY KEY REFERENCES ideas(id),
+    token_reserve NUMERIC(20, 8) NOT NULL CHECK (token_reserve > 0),
+    cash_reserve NUMERIC(20, 8) NOT NULL CHECK (cash_reserve > 0),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Amount owed to each holder once an idea is resolved or voided
+CREATE TABLE payouts (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    amount NUMERIC(20, 8) NOT NULL, -- net tokens held at resolution
+    payout NUMERIC(20, 8) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(idea_id, user_id)
+);
+
+-- Multi-outcome prediction markets settled with parimutuel payouts
+CREATE TABLE markets (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    event_name TEXT NOT NULL,
+    creator_id TEXT NOT NULL REFERENCES users(id),
+    mint TEXT, -- SPL mint the market is denominated in, null for SOL
+    status TEXT NOT NULL DEFAULT 'open', -- open, closed or resolved
+    resolved_outcome SMALLINT, -- null until resolved
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    closed_at TIMESTAMP WITH TIME ZONE,
+    resolved_at TIMESTAMP WITH TIME ZONE
+);
+
+-- One row per outcome, holding the total staked on it in smallest units
+CREATE TABLE market_outcomes (
+    market_id TEXT NOT NULL REFERENCES markets(id),
+    outcome_index SMALLINT NOT NULL,
+    label TEXT NOT NULL,
+    pool BIGINT NOT NULL DEFAULT 0 CHECK (pool >= 0),
+    PRIMARY KEY (market_id, outcome_index)
+);
+
+CREATE TABLE market_bets (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    market_id TEXT NOT NULL REFERENCES markets(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    outcome_index SMALLINT NOT NULL,
+    amount BIGINT NOT NULL CHECK (amount > 0),
+    payout BIGINT, -- null until the market is resolved
+    tx_signature TEXT UNIQUE, -- escrow deposit backing the bet
+    -- A winner's claim on the escrow, kept while the withdrawal is in flight; claim_signature
+    -- is set once it lands
+    claimed_at TIMESTAMP WITH TIME ZONE,
+    claim_signature TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (market_id, outcome_index) REFERENCES market_outcomes(market_id, outcome_index)
+);
+
+-- Double-entry ledger of user funds. Amounts are in the currency's smallest unit
+-- (lamports for SOL, 6 decimals for USDC) and the lines of every entry sum to zero.
+CREATE TABLE ledger_accounts (
+    id TEXT PRIMARY KEY, -- user:<user_id>:<currency>, hold:<user_id>:<currency> or system:<name>:<currency>
+    user_id TEXT REFERENCES users(id), -- null for system accounts
+    currency TEXT NOT NULL, -- SOL or USDC
+    balance BIGINT NOT NULL DEFAULT 0, -- running sum of the account's lines
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    -- User funds can never go negative; of the system accounts only deposits does, which
+    -- post_entry enforces
+    CHECK (user_id IS NULL OR balance >= 0)
+);
+
+CREATE TABLE journal_entries (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    kind TEXT NOT NULL, -- deposit, withdrawal, withdrawal_reversal, trade, order_hold or order_release
+    currency TEXT NOT NULL,
+    reference TEXT UNIQUE, -- deposit signature, trade ID, ... to post each event once
+    memo TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE journal_lines (
+    id BIGSERIAL PRIMARY KEY,
+    entry_id TEXT NOT NULL REFERENCES journal_entries(id),
+    account_id TEXT NOT NULL REFERENCES ledger_accounts(id),
+    amount BIGINT NOT NULL CHECK (amount <> 0) -- positive credits the account
+);
+
+-- Last signature processed per watched deposit address. Reconciliation starts from the newest
+-- signature of an address when it first runs; a null signature marks an address that had
+-- received nothing by then, so everything after it is credited.
+CREATE TABLE deposit_cursors (
+    address TEXT PRIMARY KEY,
+    last_signature TEXT,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_users_username ON users(username);
+CREATE INDEX idx_users_category ON users(category);
+CREATE INDEX idx_ideas_creator ON ideas(creator_id);
+CREATE INDEX idx_ideas_status ON ideas(status);
+CREATE INDEX idx_rates_idea_created ON rates(idea_id, created_at);
+CREATE INDEX idx_transactions_idea ON transactions(idea_id);
+CREATE INDEX idx_transactions_buyer ON transactions(buyer_id);
+CREATE INDEX idx_transactions_seller ON transactions(seller_id);
+CREATE INDEX idx_transactions_status ON transactions(status);
+CREATE INDEX idx_payouts_user ON payouts(user_id);
+CREATE INDEX idx_markets_status ON markets(status);
+CREATE INDEX idx_market_bets_market ON market_bets(market_id);
+CREATE INDEX idx_market_bets_user ON market_bets(user_id);
+CREATE INDEX idx_market_bets_unclaimed ON market_bets(market_id, user_id)
+    WHERE claimed_at IS NULL AND payout > 0;
+CREATE INDEX idx_ledger_accounts_user ON ledger_accounts(user_id);
+CREATE INDEX idx_journal_lines_entry ON journal_lines(entry_id);
+CREATE INDEX idx_journal_lines_account ON journal_lines(account_id);
+
+CREATE TRIGGER update_users_updated_at
+    BEFORE UPDATE ON users
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+CREATE TRIGGER update_ideas_updated_at
+    BEFORE UPDATE ON ideas
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+CREATE TRIGGER check_transactions_parties
+    BEFORE INSERT OR UPDATE OF buyer_id, seller_id ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION check_trade_parties();
+
+CREATE TRIGGER update_transactions_updated_at
+    BEFORE UPDATE ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,15 @@
+-- Archived ideas are hidden from listings and closed to trading but keep their history.
+ALTER TABLE ideas ADD COLUMN archived_at TIMESTAMP WITH TIME ZONE;
+
+-- Audit trail of every change made to an idea
+CREATE TABLE idea_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    editor_id TEXT NOT NULL REFERENCES users(id),
+    action TEXT NOT NULL, -- create, update or archive
+    changes JSONB NOT NULL, -- {"field": {"from": ..., "to": ...}} per changed field
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_idea_revisions_idea ON idea_revisions(idea_id, id);
+CREATE INDEX idx_ideas_archived ON ideas(archived_at);
@@ -0,0 +1,90 @@
+-- OHLCV rollup of each idea's price history, one row per idea, resolution and time bucket.
+-- Prices come from rates and volumes from completed transactions; triggers keep the rollup
+-- current so charts never scan the raw tables.
+CREATE TABLE candles (
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    resolution TEXT NOT NULL, -- 1m, 5m, 1h or 1d
+    bucket TIMESTAMP WITH TIME ZONE NOT NULL, -- start of the bucket
+    open NUMERIC(20, 8), -- null while the bucket only has trades
+    high NUMERIC(20, 8),
+    low NUMERIC(20, 8),
+    close NUMERIC(20, 8),
+    open_at TIMESTAMP WITH TIME ZONE,
+    close_at TIMESTAMP WITH TIME ZONE,
+    volume NUMERIC(20, 8) NOT NULL DEFAULT 0, -- idea tokens
+    quote_volume NUMERIC(20, 8) NOT NULL DEFAULT 0, -- cash
+    trades INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (idea_id, resolution, bucket)
+);
+
+CREATE TABLE candle_resolutions (
+    resolution TEXT PRIMARY KEY,
+    seconds INTEGER NOT NULL
+);
+
+INSERT INTO candle_resolutions (resolution, seconds) VALUES
+    ('1m', 60),
+    ('5m', 300),
+    ('1h', 3600),
+    ('1d', 86400);
+
+CREATE OR REPLACE FUNCTION candle_bucket(ts TIMESTAMP WITH TIME ZONE, seconds INTEGER)
+RETURNS TIMESTAMP WITH TIME ZONE AS $$
+    SELECT to_timestamp(floor(extract(epoch FROM ts) / seconds) * seconds);
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION roll_up_rate()
+RETURNS TRIGGER AS $$
+DECLARE
+    traded_at TIMESTAMP WITH TIME ZONE := COALESCE(NEW.created_at, CURRENT_TIMESTAMP);
+BEGIN
+    INSERT INTO candles (idea_id, resolution, bucket, open, high, low, close, open_at, close_at)
+    SELECT NEW.idea_id, r.resolution, candle_bucket(traded_at, r.seconds),
+           NEW.rate, NEW.rate, NEW.rate, NEW.rate, traded_at, traded_at
+    FROM candle_resolutions r
+    ON CONFLICT (idea_id, resolution, bucket) DO UPDATE SET
+        -- GREATEST and LEAST skip nulls, so trade-only buckets pick up their first price
+        high = GREATEST(candles.high, EXCLUDED.high),
+        low = LEAST(candles.low, EXCLUDED.low),
+        -- Rates committed out of order must not move the open or close
+        open = CASE WHEN candles.open_at IS NULL OR EXCLUDED.open_at < candles.open_at
+                    THEN EXCLUDED.open ELSE candles.open END,
+        open_at = LEAST(candles.open_at, EXCLUDED.open_at),
+        close = CASE WHEN candles.close_at IS NULL OR EXCLUDED.close_at >= candles.close_at
+                     THEN EXCLUDED.close ELSE candles.close END,
+        close_at = GREATEST(candles.close_at, EXCLUDED.close_at);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION roll_up_trade()
+RETURNS TRIGGER AS $$
+DECLARE
+    traded_at TIMESTAMP WITH TIME ZONE := COALESCE(NEW.completed_at, NEW.created_at, CURRENT_TIMESTAMP);
+BEGIN
+    IF NEW.status <> 'completed'
+        OR (TG_OP = 'UPDATE' AND OLD.status = 'completed') THEN
+        RETURN NEW;
+    END IF;
+
+    INSERT INTO candles (idea_id, resolution, bucket, volume, quote_volume, trades)
+    SELECT NEW.idea_id, r.resolution, candle_bucket(traded_at, r.seconds),
+           NEW.amount, NEW.total_value, 1
+    FROM candle_resolutions r
+    ON CONFLICT (idea_id, resolution, bucket) DO UPDATE SET
+        volume = candles.volume + EXCLUDED.volume,
+        quote_volume = candles.quote_volume + EXCLUDED.quote_volume,
+        trades = candles.trades + EXCLUDED.trades;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER roll_up_rates
+    AFTER INSERT ON rates
+    FOR EACH ROW
+    EXECUTE FUNCTION roll_up_rate();
+
+CREATE TRIGGER roll_up_transactions
+    AFTER INSERT OR UPDATE OF status ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION roll_up_trade();
@@ -0,0 +1,10 @@
+-- Full-text search over idea titles and descriptions, titles ranking first.
+ALTER TABLE ideas ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'B')
+) STORED;
+
+CREATE INDEX idx_ideas_search ON ideas USING GIN (search_vector);
+CREATE INDEX idx_ideas_category ON ideas(lower(category));
+CREATE INDEX idx_ideas_created ON ideas(created_at, id);
+CREATE INDEX idx_transactions_idea_created ON transactions(idea_id, created_at);
@@ -0,0 +1,12 @@
+-- One up or down vote per user and idea.
+CREATE TABLE votes (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    direction SMALLINT NOT NULL CHECK (direction IN (-1, 1)), -- 1 up, -1 down
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (idea_id, user_id)
+);
+
+CREATE INDEX idx_votes_idea_created ON votes(idea_id, created_at);
+CREATE INDEX idx_votes_user ON votes(user_id);
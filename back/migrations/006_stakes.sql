@@ -0,0 +1,21 @@
+-- Idea tokens locked behind an idea for a lockup period. They stay in the staker's holding but
+-- cannot be sold or staked again until unstaked, when the reward is paid in cash.
+CREATE TABLE stakes (
+    id TEXT PRIMARY KEY DEFAULT gen_random_uuid()::text,
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    amount NUMERIC(20, 8) NOT NULL CHECK (amount > 0), -- idea tokens locked
+    lockup_days INTEGER NOT NULL CHECK (lockup_days > 0),
+    entry_price NUMERIC(20, 8) NOT NULL, -- idea price when staked, for the performance reward
+    status TEXT NOT NULL DEFAULT 'active', -- active or unstaked
+    reward NUMERIC(20, 8), -- paid on unstake
+    -- Largest reward the stake can earn, moved out of the staking rewards pool when it is made
+    -- so the pool never promises more than it holds
+    reserved_reward NUMERIC(20, 8) NOT NULL DEFAULT 0 CHECK (reserved_reward >= 0),
+    staked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    unlock_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    unstaked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_stakes_idea ON stakes(idea_id, staked_at);
+CREATE INDEX idx_stakes_user ON stakes(user_id);
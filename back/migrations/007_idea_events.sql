@@ -0,0 +1,61 @@
+-- Publishes new rates and completed trades on the `idea_events` channel, so every backend
+-- instance that LISTENs sees them regardless of which instance wrote them. Payloads are
+-- `{"event": "rate" | "trade", "idea_id": ..., "data": {...}}` with `data` shaped like the API's
+-- Rate and Transaction. Decimals are strings as in the API; `trim_scale` drops the trailing zeros
+-- NUMERIC(20, 8) pads with, giving "1.75" rather than "1.75000000".
+CREATE OR REPLACE FUNCTION notify_rate()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('idea_events', json_build_object(
+        'event', 'rate',
+        'idea_id', NEW.idea_id,
+        'data', json_build_object(
+            'id', NEW.id,
+            'idea_id', NEW.idea_id,
+            'rate', trim_scale(NEW.rate)::text,
+            'volume', trim_scale(NEW.volume)::text,
+            'created_at', NEW.created_at
+        )
+    )::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION notify_trade()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.status <> 'completed'
+        OR (TG_OP = 'UPDATE' AND OLD.status = 'completed') THEN
+        RETURN NEW;
+    END IF;
+
+    PERFORM pg_notify('idea_events', json_build_object(
+        'event', 'trade',
+        'idea_id', NEW.idea_id,
+        'data', json_build_object(
+            'id', NEW.id,
+            'idea_id', NEW.idea_id,
+            'buyer_id', NEW.buyer_id,
+            'seller_id', NEW.seller_id,
+            'amount', trim_scale(NEW.amount)::text,
+            'rate', trim_scale(NEW.rate)::text,
+            'total_value', trim_scale(NEW.total_value)::text,
+            'status', NEW.status,
+            'created_at', NEW.created_at,
+            'updated_at', NEW.updated_at,
+            'completed_at', NEW.completed_at
+        )
+    )::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_rates
+    AFTER INSERT ON rates
+    FOR EACH ROW
+    EXECUTE FUNCTION notify_rate();
+
+CREATE TRIGGER notify_transactions
+    AFTER INSERT OR UPDATE OF status ON transactions
+    FOR EACH ROW
+    EXECUTE FUNCTION notify_trade();
@@ -0,0 +1,18 @@
+-- Order books live in the memory of the server instance that placed their orders, so every
+-- resting order records here what it reserves: cash moved into its owner's hold account and,
+-- for sell orders, tokens that cannot be sold or staked twice. An instance that restarts has
+-- lost its orders and releases the rows it owned; other instances' rows are left alone.
+CREATE SEQUENCE order_ids;
+
+CREATE TABLE order_holds (
+    order_id BIGINT PRIMARY KEY, -- from order_ids, unique across instances and restarts
+    instance_id TEXT NOT NULL, -- INSTANCE_ID of the server whose book the order rests on
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    cash BIGINT NOT NULL DEFAULT 0 CHECK (cash >= 0), -- units still in the owner's hold account
+    tokens NUMERIC(20, 8) NOT NULL DEFAULT 0 CHECK (tokens >= 0), -- tokens still reserved
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_order_holds_holder ON order_holds(idea_id, user_id);
+CREATE INDEX idx_order_holds_instance ON order_holds(instance_id);
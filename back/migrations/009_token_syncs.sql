@@ -0,0 +1,14 @@
+-- Holdings waiting to be mirrored on-chain. A trade in an idea with a mint queues both parties
+-- in the transaction that records it, and the token syncer works through the queue until each
+-- holder's token account matches, so a failed or interrupted sync is retried instead of lost.
+CREATE TABLE token_syncs (
+    idea_id TEXT NOT NULL REFERENCES ideas(id),
+    user_id TEXT NOT NULL REFERENCES users(id),
+    generation BIGINT NOT NULL DEFAULT 0, -- bumped whenever another trade queues it again
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (idea_id, user_id)
+);
+
+CREATE INDEX idx_token_syncs_due ON token_syncs(next_attempt_at);
@@ -0,0 +1,10 @@
+-- Pending Sign-In With Solana challenges, one per wallet, shared by every server instance so
+-- a challenge can be verified by any of them. Rows are single use and expire after a few
+-- minutes; expired ones are swept whenever a new challenge is issued.
+CREATE TABLE auth_challenges (
+    wallet_address TEXT PRIMARY KEY,
+    message TEXT NOT NULL, -- exact text the wallet must sign
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX idx_auth_challenges_expires ON auth_challenges(expires_at);
@@ -1,128 +1,272 @@
-use actix_web::{web, get, post, HttpResponse, Responder};
-use serde::{Deserialize, Serialize};
-use serde_json;
-
-// We will likely need to use the SolanaService here
-// use crate::services::solana_service::SolanaService;
+use crate::db::repo::PgRepo;
+use crate::db::PgPool;
+use crate::error::error_response;
+use crate::services::auth::Session;
+use crate::services::markets;
+use crate::services::solana_service::SolanaService;
+use crate::utils;
+use crate::validation::{self, Valid};
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use validator::Validate;
 
 // --- Request Structs ---
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct CreateMarketPayload {
-   event_name: String,
-   // Example: ["Yes", "No"] or ["Team A wins", "Team B wins"]
-   outcomes: Vec<String>,
-   // You might add other details like market end time, resolution source, etc.
-   //  oracle_pubkey: Option<String>, 
-   //  end_timestamp: Option<i64>,
+    #[validate(custom(function = validation::not_blank), length(max = 200))]
+    event_name: String,
+    // Example: ["Yes", "No"] or ["Team A wins", "Team B wins"]
+    // Bounds mirror markets::MIN_OUTCOMES and markets::MAX_OUTCOMES
+    #[validate(length(min = 2, max = 16, message = "Must have between 2 and 16 outcomes"))]
+    outcomes: Vec<String>,
+    #[validate(custom(function = validation::pubkey))]
+    mint: Option<String>, // SPL mint bets are placed in, SOL when omitted
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct PlaceBetPayload {
-   outcome_index: u8, // Index into the market's outcomes array
-   amount: u64,         // Amount of tokens to bet (smallest unit, e.g., lamports)
-   user_pubkey: String, // Public key of the user placing the bet (for now, later could be from auth)
+    outcome_index: u8, // Index into the market's outcomes array
+    #[validate(range(min = 1))]
+    amount: u64, // Amount to bet in the smallest unit (lamports or raw token amount)
 }
 
-// --- Response Structs ---
-#[derive(Serialize, Debug)]
-pub struct MarketResponse {
-    id: String, // This would be an on-chain account address or unique ID
-    event_name: String,
-    outcomes: Vec<String>,
-    is_open: bool, // Is the market currently accepting bets?
-    //  resolved_outcome: Option<u8>,
-    //  total_pot: u64,
+#[derive(Deserialize, Debug, Validate)]
+pub struct ConfirmBetPayload {
+    outcome_index: u8,
+    #[validate(custom(function = validation::not_blank))]
+    signature: String, // Signature of the submitted escrow deposit
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ResolveMarketPayload {
+    // Markets have at most 16 outcomes (markets::MAX_OUTCOMES)
+    #[validate(range(max = 15))]
+    outcome_index: u8, // Winning outcome
 }
 
 // --- Controller Actions ---
 
 // GET /markets - List all markets
 #[get("/markets")]
-async fn list_markets(/*solana_service: web::Data<SolanaService>*/) -> impl Responder {
-    // TODO: Call solana_service to get a list of markets from the blockchain
-    // For now, returning dummy data
-    let dummy_markets = vec![
-        MarketResponse {
-            id: "market1_pubkey_placeholder".to_string(),
-            event_name: "Will it rain tomorrow?".to_string(),
-            outcomes: vec!["Yes".to_string(), "No".to_string()],
-            is_open: true,
-        },
-        MarketResponse {
-            id: "market2_pubkey_placeholder".to_string(),
-            event_name: "Who wins the next match?".to_string(),
-            outcomes: vec!["Team A".to_string(), "Team B".to_string(), "Draw".to_string()],
-            is_open: false, // Example of a closed market
-        }
-    ];
-    HttpResponse::Ok().json(dummy_markets)
+async fn list_markets(pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/markets", None);
+    match markets::list_markets(&pool).await {
+        Ok(markets) => HttpResponse::Ok().json(markets),
+        Err(e) => error_response(e),
+    }
 }
 
 // POST /markets - Create a new market
 #[post("/markets")]
 async fn create_market(
-    payload: web::Json<CreateMarketPayload>,
-    // solana_service: web::Data<SolanaService>
+    payload: Valid<web::Json<CreateMarketPayload>>,
+    pool: web::Data<PgPool>,
+    session: Session,
 ) -> impl Responder {
-    // TODO: Validate payload (e.g., at least 2 outcomes)
-    // TODO: Call solana_service to create the market on-chain
-    // The solana_service would return the new market's ID (e.g., program account pubkey)
-    
-    println!("Received create market payload: {:?}", payload);
-
-    let new_market_id = "new_market_id_placeholder".to_string(); // Get from solana_service
-
-    HttpResponse::Created().json(MarketResponse {
-        id: new_market_id,
-        event_name: payload.event_name.clone(),
-        outcomes: payload.outcomes.clone(),
-        is_open: true, // New markets are typically open
-    })
+    utils::route_log("POST", "/markets", Some(&payload.event_name));
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match markets::create_market(
+        &mut client,
+        &payload.event_name,
+        &payload.outcomes,
+        payload.mint.as_deref(),
+        &user_id,
+    )
+    .await
+    {
+        Ok(market) => HttpResponse::Created().json(market),
+        Err(e) => error_response(e),
+    }
 }
 
 // GET /markets/{market_id} - Get details for a specific market
 #[get("/markets/{market_id}")]
 async fn get_market_details(
     market_id: web::Path<String>,
-    // solana_service: web::Data<SolanaService>
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log("GET", "/markets/{market_id}", Some(&market_id));
+    match markets::get_market(&pool, &market_id).await {
+        Ok(market) => HttpResponse::Ok().json(market),
+        Err(e) => error_response(e),
+    }
+}
+
+// POST /markets/{market_id}/bet - Build the escrow deposit for a bet, for the wallet to sign
+#[post("/markets/{market_id}/bet")]
+async fn place_bet(
+    market_id: web::Path<String>,
+    payload: Valid<web::Json<PlaceBetPayload>>,
+    pool: web::Data<PgPool>,
+    solana: web::Data<SolanaService>,
+    session: Session,
 ) -> impl Responder {
-    // TODO: Call solana_service to get market details for market_id.as_str()
-    println!("Fetching details for market: {}", market_id);
+    utils::route_log(
+        "POST",
+        "/markets/{market_id}/bet",
+        Some(&format!(
+            "market: {}, outcome: {}, amount: {}",
+            market_id, payload.outcome_index, payload.amount
+        )),
+    );
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match markets::prepare_bet(
+        &PgRepo::new(&client),
+        &solana,
+        &market_id,
+        &session.wallet_address,
+        payload.outcome_index,
+        payload.amount,
+    )
+    .await
+    {
+        Ok(transaction) => HttpResponse::Ok().json(transaction),
+        Err(e) => error_response(e),
+    }
+}
 
-    // Dummy data for a specific market
-    let market_details = MarketResponse {
-        id: market_id.into_inner(),
-        event_name: "Specific Market Event".to_string(),
-        outcomes: vec!["Outcome A".to_string(), "Outcome B".to_string()],
-        is_open: true,
+// POST /markets/{market_id}/bet/confirm - Record a bet once its deposit is confirmed
+#[post("/markets/{market_id}/bet/confirm")]
+async fn confirm_bet(
+    market_id: web::Path<String>,
+    payload: Valid<web::Json<ConfirmBetPayload>>,
+    pool: web::Data<PgPool>,
+    solana: web::Data<SolanaService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/markets/{market_id}/bet/confirm",
+        Some(&format!(
+            "market: {}, signature: {}",
+            market_id, payload.signature
+        )),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
     };
-    HttpResponse::Ok().json(market_details)
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match markets::confirm_bet(
+        &mut client,
+        &solana,
+        &market_id,
+        &user_id,
+        &session.wallet_address,
+        payload.outcome_index,
+        &payload.signature,
+    )
+    .await
+    {
+        Ok(bet) => HttpResponse::Created().json(bet),
+        Err(e) => error_response(e),
+    }
 }
 
+// POST /markets/{market_id}/close - Stop accepting bets
+#[post("/markets/{market_id}/close")]
+async fn close_market(
+    market_id: web::Path<String>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("POST", "/markets/{market_id}/close", Some(&market_id));
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match markets::close_market(&PgRepo::new(&client), &market_id, &user_id).await {
+        Ok(market) => HttpResponse::Ok().json(market),
+        Err(e) => error_response(e),
+    }
+}
 
-#[post("/markets/{market_id}/bet")]
-async fn place_bet(
+// POST /markets/{market_id}/resolve - Settle a closed market and pay out the winners
+#[post("/markets/{market_id}/resolve")]
+async fn resolve_market(
     market_id: web::Path<String>,
-    payload: web::Json<PlaceBetPayload>,
-    // solana_service: web::Data<SolanaService>
+    payload: Valid<web::Json<ResolveMarketPayload>>,
+    pool: web::Data<PgPool>,
+    session: Session,
 ) -> impl Responder {
-    // TODO: Validate payload (e.g., outcome_index is valid for the market)
-    // TODO: Ensure market is open for betting via solana_service
-    // TODO: Construct transaction for user to sign, or interact with solana_service to place the bet
-    println!(
-        "Placing bet on market {}: {:?}",
-        market_id.as_str(),
-        payload
+    utils::route_log(
+        "POST",
+        "/markets/{market_id}/resolve",
+        Some(&format!(
+            "market: {}, outcome: {}",
+            market_id, payload.outcome_index
+        )),
     );
-    // Dummy response
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "success",
-        "market_id": market_id.as_str(),
-        "outcome_index": payload.outcome_index,
-        "amount": payload.amount,
-        "transaction_signature_placeholder": "dummy_tx_sig_goes_here"
-    }))
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match markets::resolve_market(&mut client, &market_id, &user_id, payload.outcome_index).await {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(e) => error_response(e),
+    }
+}
+
+// POST /markets/{market_id}/claim - Pay the caller's winnings out of the escrow to their wallet
+#[post("/markets/{market_id}/claim")]
+async fn claim_winnings(
+    market_id: web::Path<String>,
+    pool: web::Data<PgPool>,
+    solana: web::Data<SolanaService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("POST", "/markets/{market_id}/claim", Some(&market_id));
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match markets::claim_winnings(
+        &mut client,
+        &solana,
+        &market_id,
+        &user_id,
+        &session.wallet_address,
+    )
+    .await
+    {
+        Ok(claim) => HttpResponse::Ok().json(claim),
+        Err(e) => error_response(e),
+    }
 }
 
 // Function to configure routes for this controller
-// ... existing code ...
\ No newline at end of file
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_markets)
+        .service(create_market)
+        .service(get_market_details)
+        .service(place_bet)
+        .service(confirm_bet)
+        .service(close_market)
+        .service(resolve_market)
+        .service(claim_winnings);
+}
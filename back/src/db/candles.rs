@@ -0,0 +1,104 @@
+use super::{parse_timestamp, DbError, GenericClient};
+use crate::decimal::Decimal;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Most buckets returned by one request.
+pub const MAX_CANDLES: i64 = 1_000;
+
+/// Bucket width of a candle, matching the resolutions rolled up by the `candles` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl Interval {
+    fn resolution(self) -> &'static str {
+        match self {
+            Interval::OneMinute => "1m",
+            Interval::FiveMinutes => "5m",
+            Interval::OneHour => "1h",
+            Interval::OneDay => "1d",
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        match self {
+            Interval::OneMinute => Duration::minutes(1),
+            Interval::FiveMinutes => Duration::minutes(5),
+            Interval::OneHour => Duration::hours(1),
+            Interval::OneDay => Duration::days(1),
+        }
+    }
+}
+
+/// Open, high, low, close and volume of one time bucket.
+#[derive(Debug, Clone, Serialize)]
+pub struct Candle {
+    /// Start of the bucket.
+    pub time: Option<DateTime<Utc>>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    /// Idea tokens traded.
+    pub volume: Decimal,
+    /// Cash traded.
+    pub quote_volume: Decimal,
+    pub trades: i32,
+}
+
+/// Candles of an idea whose bucket starts in `[from, to)`, oldest first. Buckets without a
+/// rate are omitted, so charts carry the previous close across gaps.
+pub async fn get_candles(
+    client: &impl GenericClient,
+    idea_id: &str,
+    interval: Interval,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<Candle>, DbError> {
+    if from >= to {
+        return Err(DbError::ValidationError(
+            "`from` must be earlier than `to`".into(),
+        ));
+    }
+    let buckets = (to - from).num_seconds() / interval.duration().num_seconds();
+    if buckets > MAX_CANDLES {
+        return Err(DbError::ValidationError(format!(
+            "Range spans {} candles, at most {} can be requested at once",
+            buckets, MAX_CANDLES
+        )));
+    }
+
+    let rows = client
+        .query(
+            "SELECT bucket::text, open, high, low, close, volume, quote_volume, trades
+             FROM candles
+             WHERE idea_id = $1 AND resolution = $2 AND bucket >= $3 AND bucket < $4
+               AND open IS NOT NULL
+             ORDER BY bucket",
+            &[&idea_id, &interval.resolution(), &from, &to],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Candle {
+            time: parse_timestamp(row, "bucket"),
+            open: row.get("open"),
+            high: row.get("high"),
+            low: row.get("low"),
+            close: row.get("close"),
+            volume: row.get("volume"),
+            quote_volume: row.get("quote_volume"),
+            trades: row.get("trades"),
+        })
+        .collect())
+}
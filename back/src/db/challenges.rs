@@ -0,0 +1,41 @@
+use super::{DbError, GenericClient};
+use chrono::{DateTime, Utc};
+
+/// Stores a wallet's pending challenge, replacing any earlier one, and sweeps expired ones.
+pub async fn put_challenge(
+    client: &impl GenericClient,
+    wallet_address: &str,
+    message: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "DELETE FROM auth_challenges WHERE expires_at <= CURRENT_TIMESTAMP",
+            &[],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO auth_challenges (wallet_address, message, expires_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (wallet_address) DO UPDATE
+             SET message = EXCLUDED.message, expires_at = EXCLUDED.expires_at",
+            &[&wallet_address, &message, &expires_at],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Removes and returns the wallet's pending challenge message and when it expires.
+pub async fn take_challenge(
+    client: &impl GenericClient,
+    wallet_address: &str,
+) -> Result<Option<(String, DateTime<Utc>)>, DbError> {
+    let row = client
+        .query_opt(
+            "DELETE FROM auth_challenges WHERE wallet_address = $1 RETURNING message, expires_at",
+            &[&wallet_address],
+        )
+        .await?;
+    Ok(row.map(|row| (row.get("message"), row.get("expires_at"))))
+}
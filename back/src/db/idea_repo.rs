@@ -1,42 +0,0 @@
-use crate::db::PgPool;
-use crate::models::idea::Idea;
-
-pub async fn list_ideas(pool: &PgPool) -> Result<Vec<Idea>, String> {
-    // Mock: In a real implementation, you would query your postgres instance.
-    // For example, you might do:
-    // let client = pool.get().await.map_err(|e| e.to_string())?;
-    // let rows = client.query("SELECT id, title, description FROM ideas", &[]).await.map_err(|e| e.to_string())?;
-    // let ideas: Vec<Idea> = rows.iter().map(|row| Idea { id: row.get(0), title: row.get(1), description: row.get(2) }).collect();
-    // Ok(ideas)
-    Ok(vec![
-        Idea { id: 1, title: "Mock Idea 1".to_string(), description: "Mock description 1".to_string() },
-        Idea { id: 2, title: "Mock Idea 2".to_string(), description: "Mock description 2".to_string() }
-    ])
-}
-
-pub async fn create_idea(pool: &PgPool, idea: Idea) -> Result<Idea, String> {
-    // Mock: In a real implementation, you would insert into postgres.
-    // For example, you might do:
-    // let client = pool.get().await.map_err(|e| e.to_string())?;
-    // let row = client.query_one("INSERT INTO ideas (title, description) VALUES ($1, $2) RETURNING id, title, description", &[&idea.title, &idea.description]).await.map_err(|e| e.to_string())?;
-    // Ok(Idea { id: row.get(0), title: row.get(1), description: row.get(2) })
-    Ok(idea)
-}
-
-pub async fn update_idea(pool: &PgPool, id: u32, idea: Idea) -> Result<Idea, String> {
-    // Mock: In a real implementation, you would update the postgres record.
-    // For example, you might do:
-    // let client = pool.get().await.map_err(|e| e.to_string())?;
-    // let row = client.query_one("UPDATE ideas SET title = $1, description = $2 WHERE id = $3 RETURNING id, title, description", &[&idea.title, &idea.description, &id]).await.map_err(|e| e.to_string())?;
-    // Ok(Idea { id: row.get(0), title: row.get(1), description: row.get(2) })
-    Ok(idea)
-}
-
-pub async fn delete_idea(pool: &PgPool, id: u32) -> Result<(), String> {
-    // Mock: In a real implementation, you would delete the record from postgres.
-    // For example, you might do:
-    // let client = pool.get().await.map_err(|e| e.to_string())?;
-    // client.execute("DELETE FROM ideas WHERE id = $1", &[&id]).await.map_err(|e| e.to_string())?;
-    // Ok(())
-    Ok(())
-} 
\ No newline at end of file
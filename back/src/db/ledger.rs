@@ -0,0 +1,593 @@
+use super::{parse_timestamp, DbError, GenericClient, Payout, Transaction, AMM_ACCOUNT_ID};
+use crate::decimal::{Decimal, Rounding};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// System account funds come from on deposit and go to on withdrawal.
+pub const DEPOSITS_ACCOUNT: &str = "deposits";
+pub const WITHDRAWALS_ACCOUNT: &str = "withdrawals";
+/// Counterparty of trades against the market maker, holding the proceeds idea payouts are paid from.
+pub const AMM_LEDGER_ACCOUNT: &str = "amm";
+/// Rewards reserved by active stakes.
+pub const STAKING_ACCOUNT: &str = "staking";
+/// Source of staking rewards, paid in through [`fund_staking_rewards`].
+pub const STAKING_REWARDS_ACCOUNT: &str = "staking_rewards";
+
+pub const ENTRY_DEPOSIT: &str = "deposit";
+pub const ENTRY_WITHDRAWAL: &str = "withdrawal";
+pub const ENTRY_WITHDRAWAL_REVERSAL: &str = "withdrawal_reversal";
+pub const ENTRY_TRADE: &str = "trade";
+pub const ENTRY_ORDER_HOLD: &str = "order_hold";
+pub const ENTRY_ORDER_RELEASE: &str = "order_release";
+pub const ENTRY_STAKE: &str = "stake";
+pub const ENTRY_UNSTAKE: &str = "unstake";
+pub const ENTRY_PAYOUT: &str = "payout";
+pub const ENTRY_STAKING_FUNDING: &str = "staking_funding";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    Sol,
+    Usdc,
+}
+
+/// Currency trades are paid in.
+pub const TRADE_CURRENCY: Currency = Currency::Usdc;
+
+impl Currency {
+    pub const ALL: [Currency; 2] = [Currency::Sol, Currency::Usdc];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Currency::Sol => "SOL",
+            Currency::Usdc => "USDC",
+        }
+    }
+
+    /// Decimals of the smallest unit amounts are stored in.
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Currency::Sol => 9,
+            Currency::Usdc => 6,
+        }
+    }
+
+    /// Converts a decimal amount to smallest units with the given rounding.
+    pub fn to_units(self, amount: Decimal, rounding: Rounding) -> Result<i64, DbError> {
+        amount
+            .to_units(self.decimals(), rounding)
+            .ok_or_else(|| DbError::ValidationError(format!("Amount {} is too large", amount)))
+    }
+
+    /// Converts smallest units back to a decimal amount, rounding down past [`crate::decimal::SCALE`] places.
+    pub fn to_amount(self, units: i64) -> Result<Decimal, DbError> {
+        Decimal::from_units(units, self.decimals(), Rounding::Down).ok_or_else(|| {
+            DbError::ValidationError(format!("{} units of {} is too large", units, self.as_str()))
+        })
+    }
+}
+
+impl std::str::FromStr for Currency {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "SOL" => Ok(Currency::Sol),
+            "USDC" => Ok(Currency::Usdc),
+            _ => Err(DbError::ValidationError(format!(
+                "Unsupported currency: {}",
+                s
+            ))),
+        }
+    }
+}
+
+pub fn user_account(user_id: &str, currency: Currency) -> String {
+    format!("user:{}:{}", user_id, currency.as_str())
+}
+
+/// Funds of a user reserved by their resting buy orders.
+pub fn hold_account(user_id: &str, currency: Currency) -> String {
+    format!("hold:{}:{}", user_id, currency.as_str())
+}
+
+pub fn system_account(name: &str, currency: Currency) -> String {
+    format!("system:{}:{}", name, currency.as_str())
+}
+
+// Owner encoded in a user or hold account ID
+fn account_owner(account_id: &str) -> Option<&str> {
+    account_id
+        .strip_prefix("user:")
+        .or_else(|| account_id.strip_prefix("hold:"))
+        .and_then(|rest| rest.rsplit_once(':'))
+        .map(|(user_id, _)| user_id)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Balance {
+    pub currency: Currency,
+    /// Available funds in smallest units.
+    pub balance: i64,
+    /// Funds reserved by resting orders.
+    pub held: i64,
+    pub decimals: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalLine {
+    pub account_id: String,
+    /// Positive credits the account, negative debits it.
+    pub amount: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub kind: String,
+    pub currency: Currency,
+    pub reference: Option<String>,
+    pub memo: Option<String>,
+    pub lines: Vec<JournalLine>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Posts a balanced entry and updates the balances of the accounts it touches.
+///
+/// Accounts are created on first use and locked in a fixed order, so concurrent postings
+/// cannot overdraw an account. Only the deposits account goes negative, as the mirror of funds
+/// that came in from outside; every other system account pays only from what it holds. Fails
+/// if `reference` was already posted. Run it inside a database transaction so the entry and the
+/// balances change together.
+pub async fn post_entry(
+    client: &impl GenericClient,
+    kind: &str,
+    currency: Currency,
+    reference: Option<&str>,
+    memo: Option<&str>,
+    lines: Vec<JournalLine>,
+) -> Result<JournalEntry, DbError> {
+    if lines.is_empty() || lines.iter().any(|line| line.amount == 0) {
+        return Err(DbError::ValidationError(
+            "Journal lines must have non-zero amounts".into(),
+        ));
+    }
+    if lines.iter().map(|line| line.amount as i128).sum::<i128>() != 0 {
+        return Err(DbError::ValidationError(
+            "Journal entry is not balanced".into(),
+        ));
+    }
+
+    let mut changes: HashMap<&str, i64> = HashMap::new();
+    for line in &lines {
+        let change = changes.entry(line.account_id.as_str()).or_default();
+        *change = change
+            .checked_add(line.amount)
+            .ok_or_else(|| DbError::ValidationError("Amount overflows".into()))?;
+    }
+    let mut account_ids: Vec<&str> = changes.keys().copied().collect();
+    account_ids.sort_unstable();
+
+    for account_id in &account_ids {
+        client
+            .execute(
+                "INSERT INTO ledger_accounts (id, user_id, currency) VALUES ($1, $2, $3)
+                 ON CONFLICT (id) DO NOTHING",
+                &[account_id, &account_owner(account_id), &currency.as_str()],
+            )
+            .await?;
+    }
+
+    let locked = client
+        .query(
+            "SELECT id, user_id, currency, balance FROM ledger_accounts
+             WHERE id = ANY($1) ORDER BY id FOR UPDATE",
+            &[&account_ids],
+        )
+        .await?;
+    for row in &locked {
+        let id: &str = row.get("id");
+        let account_currency: &str = row.get("currency");
+        if account_currency != currency.as_str() {
+            return Err(DbError::ValidationError(format!(
+                "Account {} is not a {} account",
+                id,
+                currency.as_str()
+            )));
+        }
+        let balance: i64 = row.get("balance");
+        if balance + changes[id] >= 0 || id == system_account(DEPOSITS_ACCOUNT, currency) {
+            continue;
+        }
+        if row.get::<_, Option<String>>("user_id").is_some() {
+            return Err(DbError::ValidationError(format!(
+                "Insufficient {} balance",
+                currency.as_str()
+            )));
+        }
+        return Err(DbError::ValidationError(format!(
+            "System account {} cannot cover {} units",
+            id, -changes[id]
+        )));
+    }
+
+    let entry = client
+        .query_opt(
+            "INSERT INTO journal_entries (kind, currency, reference, memo)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (reference) DO NOTHING
+             RETURNING id, created_at::text",
+            &[&kind, &currency.as_str(), &reference, &memo],
+        )
+        .await?
+        .ok_or_else(|| {
+            DbError::Conflict(format!(
+                "Entry {} is already posted",
+                reference.unwrap_or_default()
+            ))
+        })?;
+    let entry_id: String = entry.get("id");
+
+    for line in &lines {
+        client
+            .execute(
+                "INSERT INTO journal_lines (entry_id, account_id, amount) VALUES ($1, $2, $3)",
+                &[&entry_id, &line.account_id, &line.amount],
+            )
+            .await?;
+    }
+    for account_id in &account_ids {
+        client
+            .execute(
+                "UPDATE ledger_accounts SET balance = balance + $2 WHERE id = $1",
+                &[account_id, &changes[account_id]],
+            )
+            .await?;
+    }
+
+    Ok(JournalEntry {
+        id: entry_id,
+        kind: kind.to_string(),
+        currency,
+        reference: reference.map(str::to_string),
+        memo: memo.map(str::to_string),
+        lines,
+        created_at: parse_timestamp(&entry, "created_at"),
+    })
+}
+
+/// Cash value of a trade in smallest units of `TRADE_CURRENCY`, rounded half-even.
+pub fn trade_units(trade: &Transaction) -> Result<i64, DbError> {
+    TRADE_CURRENCY.to_units(trade.total_value, Rounding::HalfEven)
+}
+
+/// Moves the cash of a completed trade from the buyer to the seller.
+///
+/// Up to `from_hold` units are paid from the buyer's hold account, the rest from their
+/// available balance.
+pub async fn post_trade(
+    client: &impl GenericClient,
+    trade: &Transaction,
+    from_hold: i64,
+) -> Result<JournalEntry, DbError> {
+    let account = |user_id: &str| {
+        if user_id == AMM_ACCOUNT_ID {
+            system_account(AMM_LEDGER_ACCOUNT, TRADE_CURRENCY)
+        } else {
+            user_account(user_id, TRADE_CURRENCY)
+        }
+    };
+    let units = trade_units(trade)?;
+    if units <= 0 {
+        return Err(DbError::ValidationError(
+            "Trade value is below the smallest unit".into(),
+        ));
+    }
+    let reference = trade.id.as_ref().map(|id| format!("trade:{}", id));
+
+    post_entry(
+        client,
+        ENTRY_TRADE,
+        TRADE_CURRENCY,
+        reference.as_deref(),
+        Some(&format!(
+            "{} tokens of idea {}",
+            trade.amount, trade.idea_id
+        )),
+        buyer_lines(&trade.buyer_id, units, from_hold, account)
+            .into_iter()
+            .chain([JournalLine {
+                account_id: account(&trade.seller_id),
+                amount: units,
+            }])
+            .collect(),
+    )
+    .await
+}
+
+fn buyer_lines(
+    buyer_id: &str,
+    units: i64,
+    from_hold: i64,
+    account: impl Fn(&str) -> String,
+) -> Vec<JournalLine> {
+    let from_hold = from_hold.clamp(0, units);
+    let mut lines = Vec::with_capacity(2);
+    if from_hold > 0 {
+        lines.push(JournalLine {
+            account_id: hold_account(buyer_id, TRADE_CURRENCY),
+            amount: -from_hold,
+        });
+    }
+    if units > from_hold {
+        lines.push(JournalLine {
+            account_id: account(buyer_id),
+            amount: from_hold - units,
+        });
+    }
+    lines
+}
+
+/// Pays a holder of a settled idea out of the market maker's account.
+pub async fn post_payout(
+    client: &impl GenericClient,
+    payout: &Payout,
+) -> Result<JournalEntry, DbError> {
+    let units = TRADE_CURRENCY.to_units(payout.payout, Rounding::Down)?;
+    if units <= 0 {
+        return Err(DbError::ValidationError(
+            "Payout is below the smallest unit".into(),
+        ));
+    }
+    let reference = payout.id.as_ref().map(|id| format!("payout:{}", id));
+
+    post_entry(
+        client,
+        ENTRY_PAYOUT,
+        TRADE_CURRENCY,
+        reference.as_deref(),
+        Some(&format!(
+            "{} tokens of idea {}",
+            payout.amount, payout.idea_id
+        )),
+        vec![
+            JournalLine {
+                account_id: system_account(AMM_LEDGER_ACCOUNT, TRADE_CURRENCY),
+                amount: -units,
+            },
+            JournalLine {
+                account_id: user_account(&payout.user_id, TRADE_CURRENCY),
+                amount: units,
+            },
+        ],
+    )
+    .await
+}
+
+/// Moves `units` of a user's cash into the staking rewards pool.
+pub async fn fund_staking_rewards(
+    client: &impl GenericClient,
+    user_id: &str,
+    units: i64,
+) -> Result<JournalEntry, DbError> {
+    if units <= 0 {
+        return Err(DbError::ValidationError("Amount must be positive".into()));
+    }
+    post_entry(
+        client,
+        ENTRY_STAKING_FUNDING,
+        TRADE_CURRENCY,
+        None,
+        Some("staking rewards funding"),
+        vec![
+            JournalLine {
+                account_id: user_account(user_id, TRADE_CURRENCY),
+                amount: -units,
+            },
+            JournalLine {
+                account_id: system_account(STAKING_REWARDS_ACCOUNT, TRADE_CURRENCY),
+                amount: units,
+            },
+        ],
+    )
+    .await
+}
+
+/// Moves `units` between a user's available balance and their hold account;
+/// positive reserves funds, negative releases them.
+pub async fn move_hold(
+    client: &impl GenericClient,
+    user_id: &str,
+    units: i64,
+    memo: &str,
+) -> Result<JournalEntry, DbError> {
+    let kind = if units > 0 {
+        ENTRY_ORDER_HOLD
+    } else {
+        ENTRY_ORDER_RELEASE
+    };
+    post_entry(
+        client,
+        kind,
+        TRADE_CURRENCY,
+        None,
+        Some(memo),
+        vec![
+            JournalLine {
+                account_id: user_account(user_id, TRADE_CURRENCY),
+                amount: -units,
+            },
+            JournalLine {
+                account_id: hold_account(user_id, TRADE_CURRENCY),
+                amount: units,
+            },
+        ],
+    )
+    .await
+}
+
+pub async fn set_entry_reference(
+    client: &impl GenericClient,
+    entry_id: &str,
+    reference: &str,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE journal_entries SET reference = $2 WHERE id = $1",
+            &[&entry_id, &reference],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn entry_exists(client: &impl GenericClient, reference: &str) -> Result<bool, DbError> {
+    let row = client
+        .query_opt(
+            "SELECT 1 FROM journal_entries WHERE reference = $1",
+            &[&reference],
+        )
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Balance of an account in smallest units, zero if it was never used.
+pub async fn get_account_balance(
+    client: &impl GenericClient,
+    account_id: &str,
+) -> Result<i64, DbError> {
+    let row = client
+        .query_opt(
+            "SELECT balance FROM ledger_accounts WHERE id = $1",
+            &[&account_id],
+        )
+        .await?;
+    Ok(row.map_or(0, |row| row.get("balance")))
+}
+
+/// Balance of a user in every currency, zero for accounts never used.
+pub async fn get_user_balances(
+    client: &impl GenericClient,
+    user_id: &str,
+) -> Result<Vec<Balance>, DbError> {
+    let rows = client
+        .query(
+            "SELECT currency,
+                    COALESCE(SUM(balance) FILTER (WHERE id LIKE 'user:%'), 0)::int8 AS balance,
+                    COALESCE(SUM(balance) FILTER (WHERE id LIKE 'hold:%'), 0)::int8 AS held
+             FROM ledger_accounts WHERE user_id = $1
+             GROUP BY currency",
+            &[&user_id],
+        )
+        .await?;
+    let mut balances: HashMap<String, (i64, i64)> = rows
+        .into_iter()
+        .map(|row| (row.get("currency"), (row.get("balance"), row.get("held"))))
+        .collect();
+
+    Ok(Currency::ALL
+        .iter()
+        .map(|currency| {
+            let (balance, held) = balances.remove(currency.as_str()).unwrap_or_default();
+            Balance {
+                currency: *currency,
+                balance,
+                held,
+                decimals: currency.decimals(),
+            }
+        })
+        .collect())
+}
+
+/// Most recent entries touching any of the user's accounts.
+pub async fn get_user_entries(
+    client: &impl GenericClient,
+    user_id: &str,
+    limit: i64,
+) -> Result<Vec<JournalEntry>, DbError> {
+    let rows = client
+        .query(
+            "SELECT e.id, e.kind, e.currency, e.reference, e.memo, e.created_at::text
+             FROM journal_entries e
+             WHERE EXISTS (
+                 SELECT 1 FROM journal_lines l
+                 JOIN ledger_accounts a ON a.id = l.account_id
+                 WHERE l.entry_id = e.id AND a.user_id = $1
+             )
+             ORDER BY e.created_at DESC, e.id
+             LIMIT $2",
+            &[&user_id, &limit],
+        )
+        .await?;
+
+    let entry_ids: Vec<String> = rows.iter().map(|row| row.get("id")).collect();
+    let mut lines: HashMap<String, Vec<JournalLine>> = HashMap::new();
+    for row in client
+        .query(
+            "SELECT entry_id, account_id, amount FROM journal_lines
+             WHERE entry_id = ANY($1) ORDER BY id",
+            &[&entry_ids],
+        )
+        .await?
+    {
+        lines
+            .entry(row.get("entry_id"))
+            .or_default()
+            .push(JournalLine {
+                account_id: row.get("account_id"),
+                amount: row.get("amount"),
+            });
+    }
+
+    rows.iter()
+        .map(|row| {
+            let id: String = row.get("id");
+            Ok(JournalEntry {
+                lines: lines.remove(&id).unwrap_or_default(),
+                id,
+                kind: row.get("kind"),
+                currency: row.get::<_, &str>("currency").parse()?,
+                reference: row.get("reference"),
+                memo: row.get("memo"),
+                created_at: parse_timestamp(row, "created_at"),
+            })
+        })
+        .collect()
+}
+
+/// Where reconciliation of a deposit address resumes; `None` before its first pass.
+pub struct DepositCursor {
+    /// Newest signature seen, or `None` if the address had none when reconciliation started.
+    pub last_signature: Option<String>,
+}
+
+pub async fn get_deposit_cursor(
+    client: &impl GenericClient,
+    address: &str,
+) -> Result<Option<DepositCursor>, DbError> {
+    let row = client
+        .query_opt(
+            "SELECT last_signature FROM deposit_cursors WHERE address = $1",
+            &[&address],
+        )
+        .await?;
+    Ok(row.map(|row| DepositCursor {
+        last_signature: row.get("last_signature"),
+    }))
+}
+
+pub async fn set_deposit_cursor(
+    client: &impl GenericClient,
+    address: &str,
+    last_signature: Option<&str>,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "INSERT INTO deposit_cursors (address, last_signature) VALUES ($1, $2)
+             ON CONFLICT (address)
+             DO UPDATE SET last_signature = $2, updated_at = CURRENT_TIMESTAMP",
+            &[&address, &last_signature],
+        )
+        .await?;
+    Ok(())
+}
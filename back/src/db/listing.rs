@@ -0,0 +1,234 @@
+use super::{idea_from_row, DbError, GenericClient, Idea, IDEA_COLUMNS};
+use crate::decimal::Decimal;
+use crate::validation;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+use validator::{Validate, ValidationError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdeaSort {
+    #[default]
+    Newest,
+    /// Cash traded in the last 24 hours.
+    Volume,
+    /// Price change over the last 24 hours.
+    PriceChange,
+}
+
+impl IdeaSort {
+    // Sort column of the `listed` subquery and its SQL type, for decoding cursors
+    fn key(self) -> (&'static str, &'static str) {
+        match self {
+            IdeaSort::Newest => ("listed.created_at", "TIMESTAMPTZ"),
+            IdeaSort::Volume => ("listed.volume_24h", "NUMERIC"),
+            IdeaSort::PriceChange => ("listed.change_24h_percentage", "NUMERIC"),
+        }
+    }
+}
+
+/// Filters of an idea listing; unset fields match every idea.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[validate(schema(function = price_bounds_ordered, skip_on_field_errors = false))]
+pub struct IdeaFilter {
+    /// Matched case-insensitively.
+    #[validate(length(max = 50))]
+    pub category: Option<String>,
+    #[validate(custom(function = validation::idea_status))]
+    pub status: Option<String>,
+    /// Creator's user ID or wallet address.
+    #[validate(length(max = 64))]
+    pub creator: Option<String>,
+    /// Bounds on the current price, inclusive.
+    #[validate(custom(function = validation::non_negative))]
+    pub min_price: Option<Decimal>,
+    #[validate(custom(function = validation::non_negative))]
+    pub max_price: Option<Decimal>,
+    /// Web-search syntax over title and description: words, `"phrases"`, `or` and `-excluded`.
+    #[validate(length(max = 200))]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: IdeaSort,
+}
+
+fn price_bounds_ordered(filter: &IdeaFilter) -> Result<(), ValidationError> {
+    match (filter.min_price, filter.max_price) {
+        (Some(min), Some(max)) if min > max => Err(validation::field_error(
+            "max_price",
+            "Must not be below the minimum price",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// An idea with the market data listings show and sort by.
+#[derive(Debug, Clone, Serialize)]
+pub struct IdeaListing {
+    #[serde(flatten)]
+    pub idea: Idea,
+    /// Latest traded rate, or the initial price before the first trade.
+    pub current_price: Decimal,
+    pub change_24h_percentage: f64,
+    pub volume_24h: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IdeaPage {
+    pub ideas: Vec<IdeaListing>,
+    /// Pass back as `cursor` for the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Position after the last idea of a page: its sort key and ID, which breaks ties.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: IdeaSort,
+    key: String,
+    id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        BASE64.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str, sort: IdeaSort) -> Result<Self, DbError> {
+        let cursor = BASE64
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Cursor>(&bytes).ok())
+            .ok_or_else(|| DbError::ValidationError("Invalid cursor".into()))?;
+        if cursor.sort != sort {
+            return Err(DbError::ValidationError(
+                "Cursor belongs to a listing with a different sort".into(),
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+/// One page of unarchived ideas matching `filter`, in descending sort order.
+pub async fn list_ideas(
+    client: &impl GenericClient,
+    filter: &IdeaFilter,
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<IdeaPage, DbError> {
+    if limit <= 0 {
+        return Err(DbError::ValidationError("Limit must be positive".into()));
+    }
+    let cursor = cursor
+        .map(|cursor| Cursor::decode(cursor, filter.sort))
+        .transpose()?;
+    let q = filter.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let (sort_column, sort_type) = filter.sort.key();
+
+    let mut idea_conditions = vec!["i.archived_at IS NULL".to_string()];
+    let mut listed_conditions = vec!["TRUE".to_string()];
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+    if let Some(category) = &filter.category {
+        params.push(category);
+        idea_conditions.push(format!("lower(i.category) = lower(${})", params.len()));
+    }
+    if let Some(status) = &filter.status {
+        params.push(status);
+        idea_conditions.push(format!("i.status = ${}", params.len()));
+    }
+    if let Some(creator) = &filter.creator {
+        params.push(creator);
+        idea_conditions.push(format!(
+            "i.creator_id IN (SELECT id FROM users WHERE id = ${0} OR wallet_address = ${0})",
+            params.len()
+        ));
+    }
+    if let Some(q) = &q {
+        params.push(q);
+        idea_conditions.push(format!(
+            "i.search_vector @@ websearch_to_tsquery('english', ${})",
+            params.len()
+        ));
+    }
+    if let Some(min_price) = &filter.min_price {
+        params.push(min_price);
+        listed_conditions.push(format!("listed.current_price >= ${}", params.len()));
+    }
+    if let Some(max_price) = &filter.max_price {
+        params.push(max_price);
+        listed_conditions.push(format!("listed.current_price <= ${}", params.len()));
+    }
+    if let Some(cursor) = &cursor {
+        params.push(&cursor.key);
+        params.push(&cursor.id);
+        listed_conditions.push(format!(
+            "({}, listed.id) < (${}::text::{}, ${})",
+            sort_column,
+            params.len() - 1,
+            sort_type,
+            params.len()
+        ));
+    }
+    // One extra row tells whether another page follows
+    let fetch = limit + 1;
+    params.push(&fetch);
+
+    let query = format!(
+        "SELECT {columns}, current_price, change_24h_percentage, volume_24h,
+                {sort_column}::text AS sort_key
+         FROM (
+             SELECT i.*, m.current_price, m.volume_24h,
+                    CASE WHEN m.reference = 0 THEN 0
+                         ELSE ROUND((m.current_price - m.reference) / m.reference * 100, 8)
+                    END AS change_24h_percentage
+             FROM ideas i
+             CROSS JOIN LATERAL (
+                 SELECT
+                     COALESCE((SELECT rate FROM rates WHERE idea_id = i.id
+                               ORDER BY created_at DESC LIMIT 1), i.initial_price) AS current_price,
+                     COALESCE((SELECT rate FROM rates WHERE idea_id = i.id
+                                 AND created_at <= CURRENT_TIMESTAMP - INTERVAL '24 hours'
+                               ORDER BY created_at DESC LIMIT 1), i.initial_price) AS reference,
+                     (SELECT COALESCE(SUM(total_value), 0) FROM transactions
+                      WHERE idea_id = i.id AND status = 'completed'
+                        AND created_at > CURRENT_TIMESTAMP - INTERVAL '24 hours') AS volume_24h
+             ) m
+             WHERE {idea_conditions}
+         ) listed
+         WHERE {listed_conditions}
+         ORDER BY {sort_column} DESC, listed.id DESC
+         LIMIT ${limit}",
+        columns = IDEA_COLUMNS,
+        sort_column = sort_column,
+        idea_conditions = idea_conditions.join(" AND "),
+        listed_conditions = listed_conditions.join(" AND "),
+        limit = params.len(),
+    );
+    let mut rows = client.query(&query, &params).await?;
+
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|row| {
+            Cursor {
+                sort: filter.sort,
+                key: row.get("sort_key"),
+                id: row.get("id"),
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    let ideas = rows
+        .iter()
+        .map(|row| IdeaListing {
+            idea: idea_from_row(row),
+            current_price: row.get("current_price"),
+            change_24h_percentage: row.get::<_, Decimal>("change_24h_percentage").to_f64(),
+            volume_24h: row.get("volume_24h"),
+        })
+        .collect();
+
+    Ok(IdeaPage { ideas, next_cursor })
+}
@@ -0,0 +1,362 @@
+use super::{parse_timestamp, DbError, GenericClient};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const MARKET_OPEN: &str = "open";
+pub const MARKET_CLOSED: &str = "closed";
+pub const MARKET_RESOLVED: &str = "resolved";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketOutcome {
+    pub index: i16,
+    pub label: String,
+    /// Lamports staked on this outcome.
+    pub pool: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Market {
+    pub id: String,
+    pub event_name: String,
+    pub creator_id: String,
+    /// SPL mint bets are placed in; `None` for SOL.
+    pub mint: Option<String>,
+    pub status: String,
+    pub is_open: bool,
+    pub outcomes: Vec<MarketOutcome>,
+    pub resolved_outcome: Option<i16>,
+    /// Sum of every outcome pool.
+    pub total_pot: i64,
+    pub created_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bet {
+    pub id: Option<String>,
+    pub market_id: String,
+    pub user_id: String,
+    pub outcome_index: i16,
+    pub amount: i64,
+    /// Set once the market is resolved; zero for losing bets.
+    pub payout: Option<i64>,
+    /// Escrow deposit that funded the bet.
+    pub tx_signature: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the winner claimed the payout out of the escrow.
+    pub claimed_at: Option<DateTime<Utc>>,
+    /// Escrow withdrawal that paid the claim, unset while it is in flight.
+    pub claim_signature: Option<String>,
+}
+
+const MARKET_COLUMNS: &str = "id, event_name, creator_id, mint, status, resolved_outcome,
+    created_at::text, closed_at::text, resolved_at::text";
+
+const BET_COLUMNS: &str = "id, market_id, user_id, outcome_index, amount, payout, tx_signature,
+    created_at::text, claimed_at::text, claim_signature";
+
+fn market_from_row(row: &tokio_postgres::Row, outcomes: Vec<MarketOutcome>) -> Market {
+    let status: String = row.get("status");
+    Market {
+        id: row.get("id"),
+        event_name: row.get("event_name"),
+        creator_id: row.get("creator_id"),
+        mint: row.get("mint"),
+        is_open: status == MARKET_OPEN,
+        status,
+        total_pot: outcomes.iter().map(|o| o.pool).sum(),
+        outcomes,
+        resolved_outcome: row.get("resolved_outcome"),
+        created_at: parse_timestamp(row, "created_at"),
+        closed_at: parse_timestamp(row, "closed_at"),
+        resolved_at: parse_timestamp(row, "resolved_at"),
+    }
+}
+
+fn bet_from_row(row: &tokio_postgres::Row) -> Bet {
+    Bet {
+        id: Some(row.get("id")),
+        market_id: row.get("market_id"),
+        user_id: row.get("user_id"),
+        outcome_index: row.get("outcome_index"),
+        amount: row.get("amount"),
+        payout: row.get("payout"),
+        tx_signature: row.get("tx_signature"),
+        created_at: parse_timestamp(row, "created_at"),
+        claimed_at: parse_timestamp(row, "claimed_at"),
+        claim_signature: row.get("claim_signature"),
+    }
+}
+
+async fn get_outcomes(
+    client: &impl GenericClient,
+    market_id: &str,
+) -> Result<Vec<MarketOutcome>, DbError> {
+    let rows = client
+        .query(
+            "SELECT outcome_index, label, pool FROM market_outcomes
+             WHERE market_id = $1 ORDER BY outcome_index",
+            &[&market_id],
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| MarketOutcome {
+            index: row.get("outcome_index"),
+            label: row.get("label"),
+            pool: row.get("pool"),
+        })
+        .collect())
+}
+
+/// Creates an open market with an empty pool per outcome.
+pub async fn insert_market(
+    client: &impl GenericClient,
+    event_name: &str,
+    creator_id: &str,
+    mint: Option<&str>,
+    outcomes: &[String],
+) -> Result<Market, DbError> {
+    let row = client
+        .query_one(
+            &format!(
+                "INSERT INTO markets (event_name, creator_id, mint) VALUES ($1, $2, $3) RETURNING {}",
+                MARKET_COLUMNS
+            ),
+            &[&event_name, &creator_id, &mint],
+        )
+        .await?;
+    let market_id: String = row.get("id");
+
+    let mut market_outcomes = Vec::with_capacity(outcomes.len());
+    for (index, label) in outcomes.iter().enumerate() {
+        let index = index as i16;
+        client
+            .execute(
+                "INSERT INTO market_outcomes (market_id, outcome_index, label) VALUES ($1, $2, $3)",
+                &[&market_id, &index, label],
+            )
+            .await?;
+        market_outcomes.push(MarketOutcome {
+            index,
+            label: label.clone(),
+            pool: 0,
+        });
+    }
+
+    Ok(market_from_row(&row, market_outcomes))
+}
+
+/// Every market, newest first.
+pub async fn get_markets(client: &impl GenericClient) -> Result<Vec<Market>, DbError> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM markets ORDER BY created_at DESC",
+                MARKET_COLUMNS
+            ),
+            &[],
+        )
+        .await?;
+
+    let mut markets = Vec::with_capacity(rows.len());
+    for row in rows {
+        let outcomes = get_outcomes(client, row.get("id")).await?;
+        markets.push(market_from_row(&row, outcomes));
+    }
+    Ok(markets)
+}
+
+pub async fn get_market(client: &impl GenericClient, market_id: &str) -> Result<Market, DbError> {
+    fetch_market(client, market_id, false).await
+}
+
+/// Reads a market and locks its row until the surrounding transaction ends.
+pub async fn lock_market(client: &impl GenericClient, market_id: &str) -> Result<Market, DbError> {
+    fetch_market(client, market_id, true).await
+}
+
+async fn fetch_market(
+    client: &impl GenericClient,
+    market_id: &str,
+    for_update: bool,
+) -> Result<Market, DbError> {
+    if market_id.trim().is_empty() {
+        return Err(DbError::ValidationError("Market ID cannot be empty".into()));
+    }
+
+    let query = format!(
+        "SELECT {} FROM markets WHERE id = $1{}",
+        MARKET_COLUMNS,
+        if for_update { " FOR UPDATE" } else { "" }
+    );
+    match client.query_opt(&query, &[&market_id]).await? {
+        Some(row) => {
+            let outcomes = get_outcomes(client, market_id).await?;
+            Ok(market_from_row(&row, outcomes))
+        }
+        None => Err(DbError::NotFound(format!(
+            "Market with ID {} not found",
+            market_id
+        ))),
+    }
+}
+
+/// Moves a market from one status to another, failing if it is no longer in `from`.
+pub async fn transition_market_status(
+    client: &impl GenericClient,
+    market_id: &str,
+    from: &str,
+    to: &str,
+    resolved_outcome: Option<i16>,
+) -> Result<(), DbError> {
+    let updated = client
+        .execute(
+            "UPDATE markets
+             SET status = $3,
+                 resolved_outcome = COALESCE($4, resolved_outcome),
+                 closed_at = CASE WHEN $3 = 'closed' THEN CURRENT_TIMESTAMP ELSE closed_at END,
+                 resolved_at = CASE WHEN $3 = 'resolved' THEN CURRENT_TIMESTAMP ELSE resolved_at END
+             WHERE id = $1 AND status = $2",
+            &[&market_id, &from, &to, &resolved_outcome],
+        )
+        .await?;
+
+    if updated == 0 {
+        return Err(DbError::ValidationError(format!(
+            "Market {} is not {}",
+            market_id, from
+        )));
+    }
+    Ok(())
+}
+
+/// Records a bet and adds its amount to the outcome pool.
+pub async fn insert_bet(client: &impl GenericClient, bet: Bet) -> Result<Bet, DbError> {
+    let row = client
+        .query_one(
+            &format!(
+                "INSERT INTO market_bets (market_id, user_id, outcome_index, amount, tx_signature)
+                 VALUES ($1, $2, $3, $4, $5)
+                 RETURNING {}",
+                BET_COLUMNS
+            ),
+            &[
+                &bet.market_id,
+                &bet.user_id,
+                &bet.outcome_index,
+                &bet.amount,
+                &bet.tx_signature,
+            ],
+        )
+        .await?;
+
+    client
+        .execute(
+            "UPDATE market_outcomes SET pool = pool + $3
+             WHERE market_id = $1 AND outcome_index = $2",
+            &[&bet.market_id, &bet.outcome_index, &bet.amount],
+        )
+        .await?;
+
+    Ok(bet_from_row(&row))
+}
+
+/// Whether a deposit has already been recorded as a bet.
+pub async fn bet_signature_exists(
+    client: &impl GenericClient,
+    tx_signature: &str,
+) -> Result<bool, DbError> {
+    let row = client
+        .query_opt(
+            "SELECT 1 FROM market_bets WHERE tx_signature = $1",
+            &[&tx_signature],
+        )
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Bets on a market in the order they were placed.
+pub async fn get_market_bets(
+    client: &impl GenericClient,
+    market_id: &str,
+) -> Result<Vec<Bet>, DbError> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM market_bets WHERE market_id = $1 ORDER BY created_at, id",
+                BET_COLUMNS
+            ),
+            &[&market_id],
+        )
+        .await?;
+
+    Ok(rows.iter().map(bet_from_row).collect())
+}
+
+pub async fn set_bet_payout(
+    client: &impl GenericClient,
+    bet_id: &str,
+    payout: i64,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE market_bets SET payout = $2 WHERE id = $1",
+            &[&bet_id, &payout],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Claims a user's unclaimed winning bets on a market, returning them.
+pub async fn claim_bets(
+    client: &impl GenericClient,
+    market_id: &str,
+    user_id: &str,
+) -> Result<Vec<Bet>, DbError> {
+    let rows = client
+        .query(
+            &format!(
+                "UPDATE market_bets SET claimed_at = CURRENT_TIMESTAMP
+                 WHERE market_id = $1 AND user_id = $2 AND claimed_at IS NULL AND payout > 0
+                 RETURNING {}",
+                BET_COLUMNS
+            ),
+            &[&market_id, &user_id],
+        )
+        .await?;
+
+    Ok(rows.iter().map(bet_from_row).collect())
+}
+
+/// Records the escrow withdrawal that paid claimed bets.
+pub async fn set_claim_signature(
+    client: &impl GenericClient,
+    bet_ids: &[String],
+    signature: &str,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE market_bets SET claim_signature = $2 WHERE id = ANY($1)",
+            &[&bet_ids, &signature],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Makes claimed bets claimable again after their withdrawal failed.
+pub async fn release_claims(
+    client: &impl GenericClient,
+    bet_ids: &[String],
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE market_bets SET claimed_at = NULL
+             WHERE id = ANY($1) AND claim_signature IS NULL",
+            &[&bet_ids],
+        )
+        .await?;
+    Ok(())
+}
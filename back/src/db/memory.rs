@@ -0,0 +1,799 @@
+//! In-memory repositories for tests. Ledger balances are kept per account ID, without journal
+//! entries, and user accounts may not go negative just like in Postgres. There are no stakes,
+//! so a holding is available unless resting orders reserve it.
+
+use super::ledger::{self, TRADE_CURRENCY};
+use super::markets::{Bet, Market, MarketOutcome, MARKET_CLOSED, MARKET_OPEN, MARKET_RESOLVED};
+use super::repo::{
+    Database, IdeaRepo, MarketRepo, OrderRepo, RateRepo, Store, TradeRepo, UnitOfWork, UserRepo,
+};
+use super::{DbError, Idea, OrderHold, Rate, Transaction, User, AMM_ACCOUNT_ID};
+use crate::decimal::Decimal;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Default)]
+struct MemoryState {
+    next_id: u64,
+    users: Vec<User>,
+    ideas: Vec<Idea>,
+    rates: Vec<Rate>,
+    transactions: Vec<Transaction>,
+    balances: HashMap<String, i64>,
+    markets: Vec<Market>,
+    bets: Vec<Bet>,
+    next_order_id: u64,
+    order_holds: Vec<OrderHold>,
+    /// Idea and user IDs of holdings queued for an on-chain sync.
+    token_syncs: Vec<(String, String)>,
+}
+
+impl MemoryState {
+    fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        self.next_id.to_string()
+    }
+
+    fn add_balance(
+        &mut self,
+        account_id: String,
+        units: i64,
+        user_owned: bool,
+    ) -> Result<(), DbError> {
+        let balance = self.balances.entry(account_id.clone()).or_default();
+        if user_owned && *balance + units < 0 {
+            return Err(DbError::ValidationError(format!(
+                "Insufficient funds in {}",
+                account_id
+            )));
+        }
+        *balance += units;
+        Ok(())
+    }
+
+    fn move_hold(&mut self, user_id: &str, units: i64) -> Result<(), DbError> {
+        self.add_balance(ledger::user_account(user_id, TRADE_CURRENCY), -units, true)?;
+        self.add_balance(ledger::hold_account(user_id, TRADE_CURRENCY), units, true)
+    }
+
+    fn user_holding(&self, idea_id: &str, user_id: &str) -> Decimal {
+        self.transactions
+            .iter()
+            .filter(|t| {
+                t.idea_id == idea_id && t.status == "completed" && t.buyer_id != t.seller_id
+            })
+            .map(|t| {
+                if t.buyer_id == user_id {
+                    t.amount
+                } else if t.seller_id == user_id {
+                    -t.amount
+                } else {
+                    Decimal::ZERO
+                }
+            })
+            .sum()
+    }
+
+    fn market_mut(&mut self, id: &str) -> Result<&mut Market, DbError> {
+        self.markets
+            .iter_mut()
+            .find(|m| m.id == id)
+            .ok_or_else(|| DbError::NotFound(format!("Market with ID {} not found", id)))
+    }
+}
+
+/// Shared in-memory tables. A store returned by `begin` works on a private copy that replaces
+/// the shared one on `commit`; committing any other store is a no-op since it writes through.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<MemoryState>>,
+    parent: Option<Arc<Mutex<MemoryState>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&self) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(self.lock().clone())),
+            parent: Some(self.state.clone()),
+        }
+    }
+
+    /// Credits a user's available trading balance, standing in for a deposit.
+    pub fn credit(&self, user_id: &str, units: i64) -> Result<(), DbError> {
+        self.lock()
+            .add_balance(ledger::user_account(user_id, TRADE_CURRENCY), units, true)
+    }
+
+    pub fn balance(&self, user_id: &str) -> i64 {
+        self.account_balance(&ledger::user_account(user_id, TRADE_CURRENCY))
+    }
+
+    /// Units in a user's hold account, reserved by their resting buy orders.
+    pub fn held(&self, user_id: &str) -> i64 {
+        self.account_balance(&ledger::hold_account(user_id, TRADE_CURRENCY))
+    }
+
+    /// Holdings queued for an on-chain sync, as idea and user IDs.
+    pub fn token_syncs(&self) -> Vec<(String, String)> {
+        self.lock().token_syncs.clone()
+    }
+
+    /// Units in a system account, e.g. the market maker's.
+    pub fn system_balance(&self, account_id: &str) -> i64 {
+        self.account_balance(account_id)
+    }
+
+    fn account_balance(&self, account_id: &str) -> i64 {
+        self.lock().balances.get(account_id).copied().unwrap_or(0)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl UnitOfWork for MemoryStore {
+    async fn commit(self) -> Result<(), DbError> {
+        if let Some(parent) = &self.parent {
+            let state = self.lock().clone();
+            *parent.lock().unwrap_or_else(|e| e.into_inner()) = state;
+        }
+        Ok(())
+    }
+}
+
+impl Database for MemoryStore {
+    type Conn = MemoryStore;
+
+    async fn connect(&self) -> Result<MemoryStore, DbError> {
+        Ok(self.clone())
+    }
+}
+
+// The private copy behind a unit of work stands in for row locks
+impl Store for MemoryStore {
+    type Work<'a> = MemoryStore;
+
+    async fn begin_work(&mut self) -> Result<MemoryStore, DbError> {
+        Ok(self.begin())
+    }
+}
+
+impl UserRepo for MemoryStore {
+    async fn create_user(&self, user: User) -> Result<User, DbError> {
+        if user.username.trim().is_empty() {
+            return Err(DbError::ValidationError("Username cannot be empty".into()));
+        }
+        let mut state = self.lock();
+        if state
+            .users
+            .iter()
+            .any(|u| u.wallet_address == user.wallet_address)
+        {
+            return Err(DbError::Conflict(format!(
+                "Wallet address {} is already registered",
+                user.wallet_address
+            )));
+        }
+        let user = User {
+            id: Some(state.next_id()),
+            created_at: Some(Utc::now()),
+            updated_at: Some(Utc::now()),
+            ..user
+        };
+        state.users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, DbError> {
+        Ok(self.lock().users.iter().rev().cloned().collect())
+    }
+
+    async fn user_by_id(&self, id: &str) -> Result<User, DbError> {
+        self.lock()
+            .users
+            .iter()
+            .find(|u| u.id.as_deref() == Some(id))
+            .cloned()
+            .ok_or_else(|| DbError::NotFound(format!("User with ID {} not found", id)))
+    }
+
+    async fn user_by_wallet(&self, wallet_address: &str) -> Result<User, DbError> {
+        self.lock()
+            .users
+            .iter()
+            .find(|u| u.wallet_address == wallet_address)
+            .cloned()
+            .ok_or_else(|| {
+                DbError::NotFound(format!(
+                    "User with wallet address {} not found",
+                    wallet_address
+                ))
+            })
+    }
+}
+
+impl IdeaRepo for MemoryStore {
+    async fn create_idea(&self, idea: Idea) -> Result<Idea, DbError> {
+        if idea.title.trim().is_empty() {
+            return Err(DbError::ValidationError("Title cannot be empty".into()));
+        }
+        let mut state = self.lock();
+        if let Some(resolver_id) = &idea.resolver_id {
+            if !state
+                .users
+                .iter()
+                .any(|user| user.id.as_ref() == Some(resolver_id))
+            {
+                return Err(DbError::ValidationError(format!(
+                    "Resolver {} is not a user",
+                    resolver_id
+                )));
+            }
+        }
+        let id = state.next_id();
+        let idea = Idea {
+            id: Some(id.clone()),
+            status: "active".to_string(),
+            created_at: Some(Utc::now()),
+            updated_at: Some(Utc::now()),
+            ..idea
+        };
+        if idea.pricing_mode == super::PRICING_CONSTANT_PRODUCT {
+            let rate_id = state.next_id();
+            state.rates.push(Rate {
+                id: Some(rate_id),
+                created_at: Some(Utc::now()),
+                ..Rate::new(id, idea.initial_price, Decimal::ZERO)
+            });
+        }
+        state.ideas.push(idea.clone());
+        Ok(idea)
+    }
+
+    async fn idea_by_id(&self, id: &str) -> Result<Idea, DbError> {
+        self.lock()
+            .ideas
+            .iter()
+            .find(|idea| idea.id.as_deref() == Some(id))
+            .cloned()
+            .ok_or_else(|| DbError::NotFound(format!("Idea with ID {} not found", id)))
+    }
+
+    async fn lock_idea(&self, id: &str) -> Result<Idea, DbError> {
+        self.idea_by_id(id).await
+    }
+
+    async fn transition_idea(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        outcome: Option<&str>,
+    ) -> Result<Idea, DbError> {
+        let mut state = self.lock();
+        let idea = state
+            .ideas
+            .iter_mut()
+            .find(|idea| idea.id.as_deref() == Some(id) && idea.status == from)
+            .ok_or_else(|| DbError::ValidationError(format!("Idea {} is not {}", id, from)))?;
+        idea.status = to.to_string();
+        idea.resolution_outcome = outcome
+            .map(str::to_string)
+            .or(idea.resolution_outcome.take());
+        if to == "resolved" || to == "voided" {
+            idea.resolved_at = Some(Utc::now());
+        }
+        Ok(idea.clone())
+    }
+}
+
+impl RateRepo for MemoryStore {
+    async fn insert_rate(&self, rate: Rate) -> Result<Rate, DbError> {
+        if rate.rate.is_negative() || rate.volume.is_negative() {
+            return Err(DbError::ValidationError(
+                "Rate and volume cannot be negative".into(),
+            ));
+        }
+        let mut state = self.lock();
+        let rate = Rate {
+            id: Some(state.next_id()),
+            created_at: Some(Utc::now()),
+            ..rate
+        };
+        state.rates.push(rate.clone());
+        Ok(rate)
+    }
+
+    async fn latest_rate(&self, idea_id: &str) -> Result<Option<Rate>, DbError> {
+        Ok(self
+            .lock()
+            .rates
+            .iter()
+            .rev()
+            .find(|rate| rate.idea_id == idea_id)
+            .cloned())
+    }
+}
+
+impl TradeRepo for MemoryStore {
+    async fn insert_transaction(&self, trade: Transaction) -> Result<Transaction, DbError> {
+        if !trade.amount.is_positive() || !trade.rate.is_positive() {
+            return Err(DbError::ValidationError(
+                "Amount and rate must be positive".into(),
+            ));
+        }
+        let mut state = self.lock();
+        let trade = Transaction {
+            id: Some(state.next_id()),
+            created_at: Some(Utc::now()),
+            updated_at: Some(Utc::now()),
+            ..trade
+        };
+        state.transactions.push(trade.clone());
+        Ok(trade)
+    }
+
+    async fn post_trade(&self, trade: &Transaction, from_hold: i64) -> Result<(), DbError> {
+        let units = ledger::trade_units(trade)?;
+        if units <= 0 {
+            return Err(DbError::ValidationError(
+                "Trade value is below the smallest unit".into(),
+            ));
+        }
+        let account = |user_id: &str| {
+            if user_id == AMM_ACCOUNT_ID {
+                (
+                    ledger::system_account(ledger::AMM_LEDGER_ACCOUNT, TRADE_CURRENCY),
+                    false,
+                )
+            } else {
+                (ledger::user_account(user_id, TRADE_CURRENCY), true)
+            }
+        };
+        let from_hold = from_hold.clamp(0, units);
+
+        let mut state = self.lock();
+        if from_hold > 0 {
+            state.add_balance(
+                ledger::hold_account(&trade.buyer_id, TRADE_CURRENCY),
+                -from_hold,
+                true,
+            )?;
+        }
+        let (buyer, buyer_owned) = account(&trade.buyer_id);
+        state.add_balance(buyer, from_hold - units, buyer_owned)?;
+        let (seller, seller_owned) = account(&trade.seller_id);
+        state.add_balance(seller, units, seller_owned)
+    }
+
+    async fn queue_token_sync(&self, trade: &Transaction) -> Result<(), DbError> {
+        let mut state = self.lock();
+        let minted = state
+            .ideas
+            .iter()
+            .any(|idea| idea.id.as_deref() == Some(&trade.idea_id) && idea.token_mint.is_some());
+        if !minted {
+            return Ok(());
+        }
+        for user_id in [&trade.buyer_id, &trade.seller_id] {
+            let queued = (trade.idea_id.clone(), user_id.clone());
+            if user_id != AMM_ACCOUNT_ID && !state.token_syncs.contains(&queued) {
+                state.token_syncs.push(queued);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OrderRepo for MemoryStore {
+    async fn next_order_id(&self) -> Result<u64, DbError> {
+        let mut state = self.lock();
+        state.next_order_id += 1;
+        Ok(state.next_order_id)
+    }
+
+    async fn user_holding(&self, idea_id: &str, user_id: &str) -> Result<Decimal, DbError> {
+        Ok(self.lock().user_holding(idea_id, user_id))
+    }
+
+    async fn lock_available_holding(
+        &self,
+        idea_id: &str,
+        user_id: &str,
+    ) -> Result<Decimal, DbError> {
+        let state = self.lock();
+        let held: Decimal = state
+            .order_holds
+            .iter()
+            .filter(|h| h.idea_id == idea_id && h.user_id == user_id)
+            .map(|h| h.tokens)
+            .sum();
+        Ok(state.user_holding(idea_id, user_id) - held)
+    }
+
+    async fn insert_order_hold(&self, hold: &OrderHold) -> Result<(), DbError> {
+        if hold.cash < 0 || hold.tokens.is_negative() {
+            return Err(DbError::ValidationError("Holds cannot be negative".into()));
+        }
+        let mut state = self.lock();
+        if state
+            .order_holds
+            .iter()
+            .any(|h| h.order_id == hold.order_id)
+        {
+            return Err(DbError::Conflict(format!(
+                "Order {} already holds funds",
+                hold.order_id
+            )));
+        }
+        if hold.cash > 0 {
+            state.move_hold(&hold.user_id, hold.cash)?;
+        }
+        state.order_holds.push(hold.clone());
+        Ok(())
+    }
+
+    async fn lock_order_holds(&self, order_ids: &[u64]) -> Result<Vec<OrderHold>, DbError> {
+        Ok(self
+            .lock()
+            .order_holds
+            .iter()
+            .filter(|h| order_ids.contains(&h.order_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn consume_order_hold(
+        &self,
+        order_id: u64,
+        cash: i64,
+        tokens: Decimal,
+    ) -> Result<(), DbError> {
+        let mut state = self.lock();
+        if let Some(hold) = state
+            .order_holds
+            .iter_mut()
+            .find(|h| h.order_id == order_id)
+        {
+            if hold.cash < cash || hold.tokens < tokens {
+                return Err(DbError::ValidationError(format!(
+                    "Order {} holds less than it spent",
+                    order_id
+                )));
+            }
+            hold.cash -= cash;
+            hold.tokens -= tokens;
+        }
+        Ok(())
+    }
+
+    async fn release_order_hold(&self, order_id: u64) -> Result<Option<OrderHold>, DbError> {
+        let mut state = self.lock();
+        let Some(index) = state
+            .order_holds
+            .iter()
+            .position(|h| h.order_id == order_id)
+        else {
+            return Ok(None);
+        };
+        let hold = state.order_holds.remove(index);
+        if hold.cash > 0 {
+            state.move_hold(&hold.user_id, -hold.cash)?;
+        }
+        Ok(Some(hold))
+    }
+
+    async fn instance_order_ids(&self, instance_id: &str) -> Result<Vec<u64>, DbError> {
+        Ok(self
+            .lock()
+            .order_holds
+            .iter()
+            .filter(|h| h.instance_id == instance_id)
+            .map(|h| h.order_id)
+            .collect())
+    }
+}
+
+impl MarketRepo for MemoryStore {
+    async fn insert_market(
+        &self,
+        event_name: &str,
+        creator_id: &str,
+        mint: Option<&str>,
+        outcomes: &[String],
+    ) -> Result<Market, DbError> {
+        let mut state = self.lock();
+        let market = Market {
+            id: state.next_id(),
+            event_name: event_name.to_string(),
+            creator_id: creator_id.to_string(),
+            mint: mint.map(str::to_string),
+            status: MARKET_OPEN.to_string(),
+            is_open: true,
+            outcomes: outcomes
+                .iter()
+                .enumerate()
+                .map(|(index, label)| MarketOutcome {
+                    index: index as i16,
+                    label: label.clone(),
+                    pool: 0,
+                })
+                .collect(),
+            resolved_outcome: None,
+            total_pot: 0,
+            created_at: Some(Utc::now()),
+            closed_at: None,
+            resolved_at: None,
+        };
+        state.markets.push(market.clone());
+        Ok(market)
+    }
+
+    async fn market_by_id(&self, id: &str) -> Result<Market, DbError> {
+        if id.trim().is_empty() {
+            return Err(DbError::ValidationError("Market ID cannot be empty".into()));
+        }
+        self.lock().market_mut(id).map(|m| m.clone())
+    }
+
+    async fn lock_market(&self, id: &str) -> Result<Market, DbError> {
+        self.market_by_id(id).await
+    }
+
+    async fn transition_market(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        resolved_outcome: Option<i16>,
+    ) -> Result<(), DbError> {
+        let mut state = self.lock();
+        let market = state.market_mut(id)?;
+        if market.status != from {
+            return Err(DbError::ValidationError(format!(
+                "Market {} is not {}",
+                id, from
+            )));
+        }
+        market.status = to.to_string();
+        market.is_open = to == MARKET_OPEN;
+        market.resolved_outcome = resolved_outcome.or(market.resolved_outcome);
+        if to == MARKET_CLOSED {
+            market.closed_at = Some(Utc::now());
+        }
+        if to == MARKET_RESOLVED {
+            market.resolved_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn insert_bet(&self, bet: Bet) -> Result<Bet, DbError> {
+        if bet.amount <= 0 {
+            return Err(DbError::ValidationError("Amount must be positive".into()));
+        }
+        let mut state = self.lock();
+        if bet.tx_signature.is_some()
+            && state
+                .bets
+                .iter()
+                .any(|b| b.tx_signature == bet.tx_signature)
+        {
+            return Err(DbError::Conflict("Bet signature already recorded".into()));
+        }
+        let market = state.market_mut(&bet.market_id)?;
+        let outcome = market
+            .outcomes
+            .iter_mut()
+            .find(|o| o.index == bet.outcome_index)
+            .ok_or_else(|| DbError::ValidationError("Unknown outcome".into()))?;
+        outcome.pool += bet.amount;
+        market.total_pot += bet.amount;
+
+        let bet = Bet {
+            id: Some(state.next_id()),
+            created_at: Some(Utc::now()),
+            ..bet
+        };
+        state.bets.push(bet.clone());
+        Ok(bet)
+    }
+
+    async fn bet_signature_exists(&self, tx_signature: &str) -> Result<bool, DbError> {
+        Ok(self
+            .lock()
+            .bets
+            .iter()
+            .any(|b| b.tx_signature.as_deref() == Some(tx_signature)))
+    }
+
+    async fn market_bets(&self, market_id: &str) -> Result<Vec<Bet>, DbError> {
+        Ok(self
+            .lock()
+            .bets
+            .iter()
+            .filter(|b| b.market_id == market_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn set_bet_payout(&self, bet_id: &str, payout: i64) -> Result<(), DbError> {
+        let mut state = self.lock();
+        if let Some(bet) = state
+            .bets
+            .iter_mut()
+            .find(|b| b.id.as_deref() == Some(bet_id))
+        {
+            bet.payout = Some(payout);
+        }
+        Ok(())
+    }
+
+    async fn claim_bets(&self, market_id: &str, user_id: &str) -> Result<Vec<Bet>, DbError> {
+        let mut state = self.lock();
+        let mut claimed = Vec::new();
+        for bet in state.bets.iter_mut().filter(|b| {
+            b.market_id == market_id
+                && b.user_id == user_id
+                && b.claimed_at.is_none()
+                && b.payout.unwrap_or(0) > 0
+        }) {
+            bet.claimed_at = Some(Utc::now());
+            claimed.push(bet.clone());
+        }
+        Ok(claimed)
+    }
+
+    async fn set_claim_signature(
+        &self,
+        bet_ids: &[String],
+        signature: &str,
+    ) -> Result<(), DbError> {
+        for bet in self.lock().bets.iter_mut() {
+            if bet.id.as_ref().is_some_and(|id| bet_ids.contains(id)) {
+                bet.claim_signature = Some(signature.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    async fn release_claims(&self, bet_ids: &[String]) -> Result<(), DbError> {
+        for bet in self.lock().bets.iter_mut() {
+            if bet.id.as_ref().is_some_and(|id| bet_ids.contains(id))
+                && bet.claim_signature.is_none()
+            {
+                bet.claimed_at = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::repo;
+
+    // Smallest units in a whole USDC
+    const USDC: i64 = 1_000_000;
+
+    fn trade(buyer: &str, seller: &str, amount: Decimal, rate: Decimal) -> Transaction {
+        Transaction::completed("idea".into(), buyer.into(), seller.into(), amount, rate)
+    }
+
+    #[tokio::test]
+    async fn record_trade_applies_on_commit() {
+        let store = MemoryStore::new();
+        store.credit("buyer", 100 * USDC).unwrap();
+
+        let uow = store.begin();
+        let (trade, rate) = repo::record_trade(
+            &uow,
+            trade("buyer", "seller", Decimal::from(10), Decimal::from(2)),
+            Rate::new("idea".into(), Decimal::from(2), Decimal::from(10)),
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(trade.total_value, Decimal::from(20));
+        assert!(trade.id.is_some() && rate.id.is_some());
+        // Nothing shows before the commit
+        assert_eq!(store.balance("buyer"), 100 * USDC);
+        assert!(store.latest_rate("idea").await.unwrap().is_none());
+
+        uow.commit().await.unwrap();
+        assert_eq!(store.balance("buyer"), 80 * USDC);
+        assert_eq!(store.balance("seller"), 20 * USDC);
+        let latest = store.latest_rate("idea").await.unwrap().unwrap();
+        assert_eq!(latest.rate(), Decimal::from(2));
+    }
+
+    #[tokio::test]
+    async fn record_trade_refuses_an_overdraft() {
+        let store = MemoryStore::new();
+        store.credit("buyer", USDC).unwrap();
+
+        let uow = store.begin();
+        let result = repo::record_trade(
+            &uow,
+            trade("buyer", "seller", Decimal::from(10), Decimal::from(2)),
+            Rate::new("idea".into(), Decimal::from(2), Decimal::from(10)),
+            0,
+        )
+        .await;
+        assert!(matches!(result, Err(DbError::ValidationError(_))));
+        drop(uow);
+
+        assert_eq!(store.balance("buyer"), USDC);
+        assert_eq!(store.balance("seller"), 0);
+        assert!(store.latest_rate("idea").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn record_trade_queues_minted_holdings() {
+        let store = MemoryStore::new();
+        store.credit("buyer", 100 * USDC).unwrap();
+        let idea = Idea {
+            token_mint: Some("mint".into()),
+            ..Idea::new(
+                "Idea".into(),
+                "Description".into(),
+                "creator".into(),
+                "Technology".into(),
+                Decimal::from(1),
+                Decimal::from(2),
+                "1y".into(),
+                3,
+                "Large".into(),
+                "None".into(),
+            )
+        };
+        let idea_id = store.create_idea(idea).await.unwrap().id.unwrap();
+
+        let uow = store.begin();
+        for seller in ["seller", AMM_ACCOUNT_ID, "seller"] {
+            repo::record_trade(
+                &uow,
+                Transaction::completed(
+                    idea_id.clone(),
+                    "buyer".into(),
+                    seller.into(),
+                    Decimal::from(1),
+                    Decimal::from(2),
+                ),
+                Rate::new(idea_id.clone(), Decimal::from(2), Decimal::from(1)),
+                0,
+            )
+            .await
+            .unwrap();
+        }
+        // Nothing is queued until the trades commit, and the market maker never is
+        assert!(store.token_syncs().is_empty());
+        uow.commit().await.unwrap();
+        assert_eq!(
+            store.token_syncs(),
+            [
+                (idea_id.clone(), "buyer".to_string()),
+                (idea_id, "seller".to_string())
+            ]
+        );
+
+        // Ideas without a mint have nothing to mirror
+        let uow = store.begin();
+        repo::record_trade(
+            &uow,
+            trade("buyer", "seller", Decimal::from(1), Decimal::from(2)),
+            Rate::new("idea".into(), Decimal::from(2), Decimal::from(1)),
+            0,
+        )
+        .await
+        .unwrap();
+        uow.commit().await.unwrap();
+        assert_eq!(store.token_syncs().len(), 2);
+    }
+}
@@ -0,0 +1,332 @@
+use super::{parse_timestamp, DbError, PgPool};
+use crate::config::CONFIG;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Client, GenericClient};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// A schema change embedded in the binary. Migrations only move forward: once applied, a
+/// migration is never edited or reverted, a new one is appended instead.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration in version order. Add new files under `back/migrations` and list them here.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../../migrations/001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "idea_revisions",
+        sql: include_str!("../../migrations/002_idea_revisions.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "candles",
+        sql: include_str!("../../migrations/003_candles.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "idea_search",
+        sql: include_str!("../../migrations/004_idea_search.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "votes",
+        sql: include_str!("../../migrations/005_votes.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "stakes",
+        sql: include_str!("../../migrations/006_stakes.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "idea_events",
+        sql: include_str!("../../migrations/007_idea_events.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "order_holds",
+        sql: include_str!("../../migrations/008_order_holds.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "token_syncs",
+        sql: include_str!("../../migrations/009_token_syncs.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "auth_challenges",
+        sql: include_str!("../../migrations/010_auth_challenges.sql"),
+    },
+];
+
+// Statements that lose data, refused in production. Each is matched as whole leading keywords
+// of a statement, so identifiers such as `truncated_at` do not count.
+const DESTRUCTIVE_STATEMENTS: &[&str] = &[
+    "DROP TABLE",
+    "DROP SCHEMA",
+    "DROP DATABASE",
+    "TRUNCATE",
+    "DELETE FROM",
+];
+
+// What may follow DROP in an ALTER statement without losing data, as in ALTER COLUMN ... DROP
+// NOT NULL. Any other DROP removes a column or constraint.
+const HARMLESS_ALTER_DROPS: &[&str] = &["NOT", "DEFAULT", "IDENTITY", "EXPRESSION"];
+
+// Advisory lock serializing instances that start at the same time
+const MIGRATION_LOCK_ID: i64 = 7_311_019;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+impl Migration {
+    /// Hex SHA-256 of the migration's SQL, recorded when it is applied.
+    pub fn checksum(&self) -> String {
+        format!("{:x}", Sha256::digest(self.sql.as_bytes()))
+    }
+}
+
+/// First destructive statement found in `sql`, ignoring `--` comments.
+fn destructive_statement(sql: &str) -> Option<&'static str> {
+    let uncommented = sql
+        .lines()
+        .map(|line| line.split("--").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .to_uppercase();
+    uncommented.split(';').find_map(|statement| {
+        let words: Vec<&str> = statement
+            .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .filter(|word| !word.is_empty())
+            .collect();
+        if let Some(found) = DESTRUCTIVE_STATEMENTS.iter().find(|keywords| {
+            let keywords: Vec<&str> = keywords.split(' ').collect();
+            words.starts_with(&keywords)
+        }) {
+            return Some(*found);
+        }
+        let drops_data = words.first() == Some(&"ALTER")
+            && words
+                .windows(2)
+                .any(|pair| pair[0] == "DROP" && !HARMLESS_ALTER_DROPS.contains(&pair[1]));
+        drops_data.then_some("ALTER ... DROP")
+    })
+}
+
+pub async fn get_applied_migrations(
+    client: &impl GenericClient,
+) -> Result<Vec<AppliedMigration>, DbError> {
+    let rows = client
+        .query(
+            "SELECT version, name, checksum, applied_at::text FROM schema_migrations ORDER BY version",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| AppliedMigration {
+            version: row.get("version"),
+            name: row.get("name"),
+            checksum: row.get("checksum"),
+            applied_at: parse_timestamp(row, "applied_at"),
+        })
+        .collect())
+}
+
+/// Applies every pending migration, each in its own transaction, and returns their versions.
+///
+/// Fails without applying anything if an applied migration no longer matches the binary by name
+/// or checksum, a pending one is older than the latest applied version, or, in production, a pending one
+/// drops or truncates data.
+pub async fn run_migrations(pool: &PgPool) -> Result<Vec<i64>, DbError> {
+    let mut client = pool.get().await?;
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL, -- SHA-256 of the applied SQL
+                applied_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+            )",
+        )
+        .await?;
+
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_ID])
+        .await?;
+    let result = apply_pending(&mut client).await;
+    client
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_ID])
+        .await?;
+    result
+}
+
+async fn apply_pending(client: &mut Client) -> Result<Vec<i64>, DbError> {
+    let applied = get_applied_migrations(&*client).await?;
+    for migration in &applied {
+        match MIGRATIONS.iter().find(|m| m.version == migration.version) {
+            Some(known) if known.name != migration.name => {
+                return Err(DbError::ValidationError(format!(
+                    "Migration {} was applied as '{}' but this build ships '{}'",
+                    migration.version, migration.name, known.name
+                )));
+            }
+            Some(known) if known.checksum() != migration.checksum => {
+                return Err(DbError::ValidationError(format!(
+                    "Migration {} ('{}') was edited after it was applied; add a new version instead",
+                    migration.version, migration.name
+                )));
+            }
+            Some(_) => {}
+            None => utils::info(&format!(
+                "Database has migration {} ('{}') which this build does not know",
+                migration.version, migration.name
+            )),
+        }
+    }
+
+    let latest = applied.iter().map(|m| m.version).max().unwrap_or(0);
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .collect();
+
+    for migration in &pending {
+        if migration.version < latest {
+            return Err(DbError::ValidationError(format!(
+                "Migration {} is older than the applied migration {}; add a new version instead",
+                migration.version, latest
+            )));
+        }
+        if CONFIG.is_production() {
+            if let Some(statement) = destructive_statement(migration.sql) {
+                return Err(DbError::ValidationError(format!(
+                    "Migration {} ('{}') contains {}, which is refused in production",
+                    migration.version, migration.name, statement
+                )));
+            }
+        }
+    }
+
+    let mut versions = Vec::with_capacity(pending.len());
+    for migration in pending {
+        let tx = client.transaction().await?;
+        tx.batch_execute(migration.sql).await.map_err(|e| {
+            DbError::QueryError(format!(
+                "Migration {} ('{}') failed: {}",
+                migration.version, migration.name, e
+            ))
+        })?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+            &[&migration.version, &migration.name, &migration.checksum()],
+        )
+        .await?;
+        tx.commit().await?;
+
+        utils::log(&format!(
+            "[Migrations] Applied {} ({})",
+            migration.version, migration.name
+        ));
+        versions.push(migration.version);
+    }
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destructive_statements_are_matched_as_keywords() {
+        assert_eq!(
+            destructive_statement("DROP TABLE votes;"),
+            Some("DROP TABLE")
+        );
+        assert_eq!(
+            destructive_statement("truncate\n  candles"),
+            Some("TRUNCATE")
+        );
+        assert_eq!(
+            destructive_statement("CREATE INDEX i ON t(a);\ndelete from users where id = 'amm';"),
+            Some("DELETE FROM")
+        );
+        assert_eq!(
+            destructive_statement("ALTER TABLE votes DROP COLUMN weight;"),
+            Some("ALTER ... DROP")
+        );
+        assert_eq!(
+            destructive_statement("ALTER TABLE t DROP CONSTRAINT IF EXISTS t_fkey"),
+            Some("ALTER ... DROP")
+        );
+        assert_eq!(
+            destructive_statement("ALTER TABLE t DROP weight"),
+            Some("ALTER ... DROP")
+        );
+    }
+
+    #[test]
+    fn identifiers_and_comments_are_not_destructive() {
+        assert_eq!(
+            destructive_statement("ALTER TABLE t ADD COLUMN truncated_at TIMESTAMP"),
+            None
+        );
+        assert_eq!(
+            destructive_statement("CREATE TABLE drop_table_log (deleted_from TEXT)"),
+            None
+        );
+        assert_eq!(
+            destructive_statement(
+                "ALTER TABLE t ALTER COLUMN a DROP NOT NULL, ALTER b DROP DEFAULT"
+            ),
+            None
+        );
+        assert_eq!(destructive_statement("-- DROP TABLE users\nSELECT 1"), None);
+        assert_eq!(destructive_statement("DROP INDEX idx_votes_user"), None);
+    }
+
+    #[test]
+    fn shipped_migrations_are_ordered_and_keep_data() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+            assert_eq!(
+                destructive_statement(migration.sql),
+                None,
+                "{}",
+                migration.name
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_follows_the_sql() {
+        let migration = |sql| Migration {
+            version: 1,
+            name: "a",
+            sql,
+        };
+        assert_eq!(
+            migration("SELECT 1").checksum(),
+            migration("SELECT 1").checksum()
+        );
+        assert_ne!(
+            migration("SELECT 1").checksum(),
+            migration("SELECT 2").checksum()
+        );
+        assert_eq!(migration("").checksum().len(), 64);
+    }
+}
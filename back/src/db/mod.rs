@@ -1,10 +1,73 @@
-use chrono::{DateTime, TimeZone, Utc};
-use deadpool_postgres::{Client, Config, Pool, Runtime};
+use crate::config::CONFIG;
+use crate::decimal::{Decimal, Rounding};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{
+    Client, GenericClient, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime,
+};
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use serde::{Deserialize, Serialize};
-use tokio_postgres::{types::ToSql, Error, NoTls};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_postgres::config::SslMode;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{types::ToSql, NoTls};
+
+pub mod candles;
+pub mod challenges;
+pub mod ledger;
+pub mod listing;
+pub mod markets;
+pub mod migrate;
+pub mod notify;
+pub mod positions;
+pub mod repo;
+pub mod revisions;
+pub mod stakes;
+pub mod token_syncs;
+pub mod trending;
+pub mod votes;
+
+#[cfg(test)]
+#[allow(dead_code)] // exercised only by tests
+pub mod memory;
 
 pub type PgPool = Pool;
 
+// Pricing modes an idea can be created with
+pub const PRICING_ORDER_BOOK: &str = "order_book";
+pub const PRICING_CONSTANT_PRODUCT: &str = "constant_product";
+
+/// Categories an idea may be filed under, matching the create form.
+pub const IDEA_CATEGORIES: &[&str] = &[
+    "Technology",
+    "Finance",
+    "Healthcare",
+    "Energy",
+    "Consumer Goods",
+    "Real Estate",
+    "Entertainment",
+    "Education",
+    "Transportation",
+    "Agriculture",
+];
+
+/// Lifecycle states of an idea, matching the `ideas.status` column.
+pub const IDEA_STATUSES: &[&str] = &["active", "closed", "resolved", "voided"];
+
+/// Horizons an idea's target price may be set for.
+pub const IDEA_TIMEFRAMES: &[&str] = &["3m", "6m", "1y", "2y", "5y"];
+
+/// Token reserve a constant-product pool is seeded with; the cash side is `initial_price` times this.
+pub const AMM_SEED_TOKENS: Decimal = Decimal::new(10_000, 0);
+
+/// Tokens an order-book idea issues in total, offered by the market maker at `initial_price`.
+pub const ORDER_BOOK_SUPPLY: Decimal = Decimal::new(10_000, 0);
+
+/// System account recorded as the counterparty of trades with the market maker: the pool of a
+/// curve-priced idea, or the issuer of an order-book idea's tokens.
+pub const AMM_ACCOUNT_ID: &str = "amm";
+
 // Custom error type for better error handling
 #[derive(Debug)]
 pub enum DbError {
@@ -12,6 +75,11 @@ pub enum DbError {
     QueryError(String),
     NotFound(String),
     ValidationError(String),
+    Forbidden(String),
+    /// The write duplicates an existing record, e.g. a taken username.
+    Conflict(String),
+    /// An on-chain transaction has not confirmed yet; the same request may succeed later.
+    Unconfirmed(String),
 }
 
 impl std::fmt::Display for DbError {
@@ -21,6 +89,9 @@ impl std::fmt::Display for DbError {
             DbError::QueryError(e) => write!(f, "Database query error: {}", e),
             DbError::NotFound(e) => write!(f, "Record not found: {}", e),
             DbError::ValidationError(e) => write!(f, "Validation error: {}", e),
+            DbError::Forbidden(e) => write!(f, "Forbidden: {}", e),
+            DbError::Conflict(e) => write!(f, "Conflict: {}", e),
+            DbError::Unconfirmed(e) => write!(f, "Unconfirmed: {}", e),
         }
     }
 }
@@ -33,6 +104,22 @@ impl From<deadpool_postgres::PoolError> for DbError {
 
 impl From<tokio_postgres::Error> for DbError {
     fn from(err: tokio_postgres::Error) -> Self {
+        // Unique constraints back up the duplicate checks when two writes race
+        if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+            let detail = err
+                .as_db_error()
+                .and_then(|e| e.detail())
+                .unwrap_or("Record already exists");
+            return DbError::Conflict(detail.to_string());
+        }
+        // Foreign keys back up the existence checks, e.g. a row deleted by a concurrent request
+        if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+            let detail = err
+                .as_db_error()
+                .map(|e| e.detail().unwrap_or(e.message()))
+                .unwrap_or("Referenced record does not exist");
+            return DbError::Conflict(detail.to_string());
+        }
         DbError::QueryError(err.to_string())
     }
 }
@@ -43,14 +130,14 @@ impl From<deadpool_postgres::CreatePoolError> for DbError {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
-    id: Option<String>,
-    username: String,
-    wallet_address: String,
-    category: Option<String>,
-    created_at: Option<DateTime<Utc>>,
-    updated_at: Option<DateTime<Utc>>,
+    pub id: Option<String>,
+    pub username: String,
+    pub wallet_address: String,
+    pub category: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 impl User {
@@ -72,8 +159,8 @@ impl Idea {
         description: String,
         creator_id: String,
         category: String,
-        initial_price: f64,
-        target_price: f64,
+        initial_price: Decimal,
+        target_price: Decimal,
         timeframe: String,
         risk_level: i32,
         market_size: String,
@@ -91,113 +178,244 @@ impl Idea {
             risk_level,
             market_size,
             competitive_advantage,
+            pricing_mode: PRICING_ORDER_BOOK.to_string(),
+            resolver_id: None,
+            token_mint: None,
             status: "active".to_string(),
             resolution_outcome: None,
             created_at: Some(Utc::now()),
             updated_at: Some(Utc::now()),
             resolved_at: None,
+            archived_at: None,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Idea {
     pub id: Option<String>,
     pub title: String,
     pub description: String,
     pub creator_id: String,
     pub category: String,
-    pub initial_price: f64,
-    pub target_price: f64,
+    pub initial_price: Decimal,
+    pub target_price: Decimal,
     pub timeframe: String,
     pub risk_level: i32,
     pub market_size: String,
     pub competitive_advantage: String,
+    #[serde(default = "default_pricing_mode")]
+    pub pricing_mode: String,
+    /// User allowed to resolve the idea besides its creator.
+    #[serde(default)]
+    pub resolver_id: Option<String>,
+    /// SPL mint mirroring holdings on-chain, if one was created.
+    #[serde(default)]
+    pub token_mint: Option<String>,
     pub status: String,
     pub resolution_outcome: Option<String>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
     pub resolved_at: Option<DateTime<Utc>>,
+    /// Set once the idea is archived; archived ideas are hidden and cannot be traded.
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Idea {
+    /// Whether orders and pool trades are accepted.
+    pub fn is_tradable(&self) -> bool {
+        self.status == "active" && self.archived_at.is_none()
+    }
+}
+
+fn default_pricing_mode() -> String {
+    PRICING_ORDER_BOOK.to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rate {
     id: Option<String>,
     idea_id: String,
-    rate: f64,
-    volume: f64,
+    rate: Decimal,
+    volume: Decimal,
     created_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Rate {
+    pub fn new(idea_id: String, rate: Decimal, volume: Decimal) -> Self {
+        Self {
+            id: None,
+            idea_id,
+            rate,
+            volume,
+            created_at: None,
+        }
+    }
+
+    pub fn idea_id(&self) -> &str {
+        &self.idea_id
+    }
+
+    pub fn rate(&self) -> Decimal {
+        self.rate
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    id: Option<String>,
-    idea_id: String,
-    buyer_id: String,
-    seller_id: String,
-    amount: f64,
-    rate: f64,
-    total_value: f64,
-    status: String,
-    created_at: Option<DateTime<Utc>>,
-    updated_at: Option<DateTime<Utc>>,
-    completed_at: Option<DateTime<Utc>>,
+    pub id: Option<String>,
+    pub idea_id: String,
+    pub buyer_id: String,
+    pub seller_id: String,
+    pub amount: Decimal,
+    pub rate: Decimal,
+    pub total_value: Decimal,
+    pub status: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl Transaction {
+    /// Builds a completed trade; `total_value` is `amount * rate` rounded half-even.
+    pub fn completed(
+        idea_id: String,
+        buyer_id: String,
+        seller_id: String,
+        amount: Decimal,
+        rate: Decimal,
+    ) -> Self {
+        Self {
+            id: None,
+            idea_id,
+            buyer_id,
+            seller_id,
+            amount,
+            rate,
+            total_value: amount.mul_rounded(rate, Rounding::HalfEven),
+            status: "completed".to_string(),
+            created_at: None,
+            updated_at: None,
+            completed_at: None,
+        }
+    }
+}
+
+/// Reserves of a constant-product market maker backing an idea.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AmmPool {
+    pub idea_id: String,
+    pub token_reserve: Decimal,
+    pub cash_reserve: Decimal,
+}
+
+/// Net position of one user in one idea, derived from completed transactions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holding {
+    pub user_id: String,
+    pub idea_id: String,
+    /// Tokens bought minus tokens sold.
+    pub amount: Decimal,
+    /// Cash paid on buys minus cash received on sells.
+    pub net_cost: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payout {
+    pub id: Option<String>,
+    pub idea_id: String,
+    pub user_id: String,
+    pub amount: Decimal,
+    pub payout: Decimal,
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 // Helper function to parse timestamp from database
 fn parse_timestamp(row: &tokio_postgres::Row, column: &str) -> Option<DateTime<Utc>> {
-    row.get::<_, Option<String>>(column)
-        .and_then(|ts| Utc.datetime_from_str(&ts, "%Y-%m-%d %H:%M:%S%.f%z").ok())
-}
-
-pub fn get_pool() -> Result<PgPool, DbError> {
-    let mut cfg = Config::new();
-    cfg.host = Some("localhost".to_string());
-    cfg.port = Some(5432);
-    cfg.user = Some("postgres".to_string());
-    cfg.password = Some("postgres".to_string());
-    cfg.dbname = Some("postgres".to_string());
-    cfg.manager = Some(deadpool_postgres::ManagerConfig {
-        recycling_method: deadpool_postgres::RecyclingMethod::Fast,
-    });
-    cfg.create_pool(Some(Runtime::Tokio1), NoTls)
+    // Postgres prints offsets as `+00`, without minutes, which `%#z` accepts
+    row.get::<_, Option<String>>(column).and_then(|ts| {
+        DateTime::parse_from_str(&ts, "%Y-%m-%d %H:%M:%S%.f%#z")
+            .ok()
+            .map(|ts| ts.with_timezone(&Utc))
+    })
+}
+
+// Columns read by `idea_from_row`
+const IDEA_COLUMNS: &str =
+    "id, title, description, creator_id, category, initial_price, target_price,
+    timeframe, risk_level, market_size, competitive_advantage, pricing_mode, resolver_id, token_mint, status,
+    resolution_outcome, created_at::text, updated_at::text, resolved_at::text, archived_at::text";
+
+fn idea_from_row(row: &tokio_postgres::Row) -> Idea {
+    Idea {
+        id: Some(row.get("id")),
+        title: row.get("title"),
+        description: row.get("description"),
+        creator_id: row.get("creator_id"),
+        category: row.get("category"),
+        initial_price: row.get("initial_price"),
+        target_price: row.get("target_price"),
+        timeframe: row.get("timeframe"),
+        risk_level: row.get("risk_level"),
+        market_size: row.get("market_size"),
+        competitive_advantage: row.get("competitive_advantage"),
+        pricing_mode: row.get("pricing_mode"),
+        resolver_id: row.get("resolver_id"),
+        token_mint: row.get("token_mint"),
+        status: row.get("status"),
+        resolution_outcome: row.get("resolution_outcome"),
+        created_at: parse_timestamp(row, "created_at"),
+        updated_at: parse_timestamp(row, "updated_at"),
+        resolved_at: parse_timestamp(row, "resolved_at"),
+        archived_at: parse_timestamp(row, "archived_at"),
+    }
+}
+
+/// Builds the connection pool shared by the whole app from `DATABASE_URL`.
+///
+/// TLS follows the URL's `sslmode`: `disable` connects in plain text, `prefer` and `require`
+/// use TLS, trusting `DB_SSL_ROOT_CERT` in addition to the system roots.
+pub fn create_pool() -> Result<PgPool, DbError> {
+    let pg_config = tokio_postgres::Config::from_str(&CONFIG.database_url)
+        .map_err(|e| DbError::ConnectionError(format!("Invalid DATABASE_URL: {}", e)))?;
+    let manager_config = ManagerConfig {
+        recycling_method: RecyclingMethod::Fast,
+    };
+
+    let manager = match pg_config.get_ssl_mode() {
+        SslMode::Disable => Manager::from_config(pg_config, NoTls, manager_config),
+        _ => Manager::from_config(pg_config, tls_connector()?, manager_config),
+    };
+
+    let timeout = Some(Duration::from_secs(CONFIG.db_pool_timeout_secs));
+    Pool::builder(manager)
+        .max_size(CONFIG.db_pool_size)
+        .wait_timeout(timeout)
+        .create_timeout(timeout)
+        .recycle_timeout(timeout)
+        .runtime(Runtime::Tokio1)
+        .build()
         .map_err(|e| DbError::ConnectionError(e.to_string()))
 }
 
-pub async fn getGlobalClient() -> Result<Client, DbError> {
-    let pool = get_pool()?;
-    let client = pool.get().await?;
-    Ok(client)
-}
-
-// pub async fn createIdea(idea: Idea) -> Result<Idea, DbError> {
-//     let client = getGlobalClient().await?;
-//     let result = client.query_one(
-//         "INSERT INTO ideas (title, description, creator_id, category, initial_price, target_price, timeframe, risk_level, market_size, competitive_advantage, status) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING id, created_at::text, updated_at::text",
-//         &[&idea.title, &idea.description, &idea.creator_id, &idea.category, &idea.initial_price, &idea.target_price, &idea.timeframe, &idea.risk_level, &idea.market_size, &idea.competitive_advantage, &idea.status],
-//     ).await?;
-
-//     Ok(Idea {
-//         id: Some(result.get("id")),
-//         title: idea.title,
-//         description: idea.description,
-//         creator_id: idea.creator_id,
-//         category: idea.category,
-//         initial_price: idea.initial_price,
-//         target_price: idea.target_price,
-//         timeframe: idea.timeframe,
-//         risk_level: idea.risk_level,
-//         market_size: idea.market_size,
-//         competitive_advantage: idea.competitive_advantage,
-//         status: idea.status,
-//         resolution_outcome: None,
-//         created_at: parse_timestamp(&result, "created_at"),
-//         updated_at: parse_timestamp(&result, "updated_at"),
-//         resolved_at: None,
-//     })
-// }
-
-pub async fn createUser(user: User) -> Result<User, DbError> {
+fn tls_connector() -> Result<MakeTlsConnector, DbError> {
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = &CONFIG.db_ssl_root_cert {
+        let pem = std::fs::read(path)
+            .map_err(|e| DbError::ConnectionError(format!("Cannot read {}: {}", path, e)))?;
+        let certificate = Certificate::from_pem(&pem).map_err(|e| {
+            DbError::ConnectionError(format!("Invalid certificate {}: {}", path, e))
+        })?;
+        builder.add_root_certificate(certificate);
+    }
+    let connector = builder
+        .build()
+        .map_err(|e| DbError::ConnectionError(e.to_string()))?;
+    Ok(MakeTlsConnector::new(connector))
+}
+
+async fn insert_user(client: &impl GenericClient, user: User) -> Result<User, DbError> {
     // Validate input
     if user.username.trim().is_empty() {
         return Err(DbError::ValidationError("Username cannot be empty".into()));
@@ -208,8 +426,6 @@ pub async fn createUser(user: User) -> Result<User, DbError> {
         ));
     }
 
-    let client = getGlobalClient().await?;
-
     // Check if username already exists
     let existing_username = client
         .query_opt(
@@ -219,7 +435,7 @@ pub async fn createUser(user: User) -> Result<User, DbError> {
         .await?;
 
     if existing_username.is_some() {
-        return Err(DbError::ValidationError("Username already taken".into()));
+        return Err(DbError::Conflict("Username already taken".into()));
     }
 
     // Check if wallet address already exists
@@ -231,7 +447,7 @@ pub async fn createUser(user: User) -> Result<User, DbError> {
         .await?;
 
     if existing_wallet.is_some() {
-        return Err(DbError::ValidationError(
+        return Err(DbError::Conflict(
             "User with this wallet address already exists".into(),
         ));
     }
@@ -253,8 +469,7 @@ pub async fn createUser(user: User) -> Result<User, DbError> {
     })
 }
 
-pub async fn getAllUsers() -> Result<Vec<User>, DbError> {
-    let client = getGlobalClient().await?;
+async fn get_all_users(client: &impl GenericClient) -> Result<Vec<User>, DbError> {
     let result = client
         .query(
             "SELECT id, username, wallet_address, category, created_at::text, updated_at::text FROM users ORDER BY created_at DESC",
@@ -275,14 +490,16 @@ pub async fn getAllUsers() -> Result<Vec<User>, DbError> {
         .collect())
 }
 
-pub async fn getUserByWalletAddress(wallet_address: String) -> Result<User, DbError> {
+async fn get_user_by_wallet(
+    client: &impl GenericClient,
+    wallet_address: &str,
+) -> Result<User, DbError> {
     if wallet_address.trim().is_empty() {
         return Err(DbError::ValidationError(
             "Wallet address cannot be empty".into(),
         ));
     }
 
-    let client = getGlobalClient().await?;
     let result = client
         .query_opt(
             "SELECT id, username, wallet_address, category, created_at::text, updated_at::text FROM users WHERE wallet_address = $1",
@@ -306,61 +523,54 @@ pub async fn getUserByWalletAddress(wallet_address: String) -> Result<User, DbEr
     }
 }
 
-pub async fn createIdea(idea: Idea) -> Result<Idea, DbError> {
-    // Validate input
-    if idea.title.trim().is_empty() {
-        return Err(DbError::ValidationError("Title cannot be empty".into()));
-    }
-    if idea.description.trim().is_empty() {
-        return Err(DbError::ValidationError(
-            "Description cannot be empty".into(),
-        ));
+async fn get_user_by_id(client: &impl GenericClient, id: &str) -> Result<User, DbError> {
+    let result = client
+        .query_opt(
+            "SELECT id, username, wallet_address, category, created_at::text, updated_at::text FROM users WHERE id = $1",
+            &[&id],
+        )
+        .await?;
+
+    match result {
+        Some(row) => Ok(User {
+            id: Some(row.get("id")),
+            username: row.get("username"),
+            wallet_address: row.get("wallet_address"),
+            category: row.get("category"),
+            created_at: parse_timestamp(&row, "created_at"),
+            updated_at: parse_timestamp(&row, "updated_at"),
+        }),
+        None => Err(DbError::NotFound(format!("User with ID {} not found", id))),
     }
+}
+
+/// Inserts an idea using the given client, so it can take part in a surrounding transaction.
+pub async fn insert_idea(client: &impl GenericClient, idea: Idea) -> Result<Idea, DbError> {
+    // Field rules are checked when the create request is extracted
     if idea.creator_id.trim().is_empty() {
         return Err(DbError::ValidationError(
             "Creator ID cannot be empty".into(),
         ));
     }
-    if idea.category.trim().is_empty() {
-        return Err(DbError::ValidationError("Category cannot be empty".into()));
-    }
-    if idea.initial_price <= 0.0 {
-        return Err(DbError::ValidationError(
-            "Initial price must be positive".into(),
-        ));
-    }
-    if idea.target_price <= idea.initial_price {
-        return Err(DbError::ValidationError(
-            "Target price must be greater than initial price".into(),
-        ));
-    }
-    if idea.timeframe.trim().is_empty() {
-        return Err(DbError::ValidationError("Timeframe cannot be empty".into()));
-    }
-    if idea.risk_level < 1 || idea.risk_level > 10 {
-        return Err(DbError::ValidationError(
-            "Risk level must be between 1 and 10".into(),
-        ));
-    }
-    if idea.market_size.trim().is_empty() {
-        return Err(DbError::ValidationError(
-            "Market size cannot be empty".into(),
-        ));
-    }
-    if idea.competitive_advantage.trim().is_empty() {
-        return Err(DbError::ValidationError(
-            "Competitive advantage cannot be empty".into(),
-        ));
+    if let Some(resolver_id) = &idea.resolver_id {
+        let resolver = client
+            .query_opt("SELECT 1 FROM users WHERE id = $1", &[resolver_id])
+            .await?;
+        if resolver.is_none() {
+            return Err(DbError::ValidationError(format!(
+                "Resolver {} is not a user",
+                resolver_id
+            )));
+        }
     }
 
-    let client = getGlobalClient().await?;
     let result = client
         .query_one(
             "INSERT INTO ideas (
                 title, description, creator_id, category, initial_price, 
                 target_price, timeframe, risk_level, market_size, 
-                competitive_advantage, status
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) 
+                competitive_advantage, pricing_mode, resolver_id, token_mint, status
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) 
             RETURNING id, created_at::text, updated_at::text",
             &[
                 &idea.title,
@@ -373,13 +583,35 @@ pub async fn createIdea(idea: Idea) -> Result<Idea, DbError> {
                 &idea.risk_level,
                 &idea.market_size,
                 &idea.competitive_advantage,
+                &idea.pricing_mode,
+                &idea.resolver_id,
+                &idea.token_mint,
                 &"active",
             ],
         )
         .await?;
 
-    Ok(Idea {
-        id: Some(result.get("id")),
+    let id: String = result.get("id");
+    if idea.pricing_mode == PRICING_CONSTANT_PRODUCT {
+        client
+            .execute(
+                "INSERT INTO amm_pools (idea_id, token_reserve, cash_reserve) VALUES ($1, $2, $3)",
+                &[
+                    &id,
+                    &AMM_SEED_TOKENS,
+                    &AMM_SEED_TOKENS.mul_rounded(idea.initial_price, Rounding::HalfEven),
+                ],
+            )
+            .await?;
+        insert_rate(
+            client,
+            Rate::new(id.clone(), idea.initial_price, Decimal::ZERO),
+        )
+        .await?;
+    }
+
+    let created = Idea {
+        id: Some(id),
         title: idea.title,
         description: idea.description,
         creator_id: idea.creator_id,
@@ -390,65 +622,391 @@ pub async fn createIdea(idea: Idea) -> Result<Idea, DbError> {
         risk_level: idea.risk_level,
         market_size: idea.market_size,
         competitive_advantage: idea.competitive_advantage,
+        pricing_mode: idea.pricing_mode,
+        resolver_id: idea.resolver_id,
+        token_mint: idea.token_mint,
         status: "active".to_string(),
         resolution_outcome: None,
         created_at: parse_timestamp(&result, "created_at"),
         updated_at: parse_timestamp(&result, "updated_at"),
         resolved_at: None,
-    })
+        archived_at: None,
+    };
+    revisions::record_creation(client, &created).await?;
+    Ok(created)
+}
+
+async fn get_idea(client: &impl GenericClient, id: &str) -> Result<Idea, DbError> {
+    fetch_idea(client, id, false).await
 }
 
-pub async fn getIdeaById(id: &str) -> Result<Idea, DbError> {
+/// Reads an idea and locks its row until the surrounding transaction ends, so its status
+/// cannot change under the caller.
+pub async fn lock_idea(client: &impl GenericClient, id: &str) -> Result<Idea, DbError> {
+    fetch_idea(client, id, true).await
+}
+
+async fn fetch_idea(
+    client: &impl GenericClient,
+    id: &str,
+    for_update: bool,
+) -> Result<Idea, DbError> {
     if id.trim().is_empty() {
         return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
     }
 
-    let client = getGlobalClient().await?;
     let result = client
         .query_opt(
-            "SELECT id, title, description, creator_id, status, resolution_outcome, 
-                    created_at::text, updated_at::text, resolved_at::text 
-             FROM ideas WHERE id = $1",
+            &format!(
+                "SELECT {} FROM ideas WHERE id = $1{}",
+                IDEA_COLUMNS,
+                if for_update { " FOR UPDATE" } else { "" }
+            ),
             &[&id],
         )
         .await?;
 
     match result {
-        Some(row) => Ok(Idea {
-            id: Some(row.get("id")),
-            title: row.get("title"),
-            description: row.get("description"),
-            creator_id: row.get("creator_id"),
-            status: row.get("status"),
-            resolution_outcome: row.get("resolution_outcome"),
-            created_at: parse_timestamp(&row, "created_at"),
-            updated_at: parse_timestamp(&row, "updated_at"),
-            resolved_at: parse_timestamp(&row, "resolved_at"),
-            category: row.get("category"),
-            initial_price: row.get("initial_price"),
-            target_price: row.get("target_price"),
-            timeframe: row.get("timeframe"),
-            risk_level: row.get("risk_level"),
-            market_size: row.get("market_size"),
-            competitive_advantage: row.get("competitive_advantage"),
-        }),
+        Some(row) => Ok(idea_from_row(&row)),
         None => Err(DbError::NotFound(format!("Idea with ID {} not found", id))),
     }
 }
 
-pub async fn createRate(rate: Rate) -> Result<Rate, DbError> {
+/// Moves an idea from one status to another, failing if it is no longer in `from`.
+pub async fn transition_idea_status(
+    client: &impl GenericClient,
+    idea_id: &str,
+    from: &str,
+    to: &str,
+    outcome: Option<&str>,
+) -> Result<Idea, DbError> {
+    let result = client
+        .query_opt(
+            &format!(
+                "UPDATE ideas
+                 SET status = $3,
+                     resolution_outcome = COALESCE($4, resolution_outcome),
+                     resolved_at = CASE WHEN $3 IN ('resolved', 'voided')
+                                        THEN CURRENT_TIMESTAMP ELSE resolved_at END
+                 WHERE id = $1 AND status = $2
+                 RETURNING {}",
+                IDEA_COLUMNS
+            ),
+            &[&idea_id, &from, &to, &outcome],
+        )
+        .await?;
+
+    match result {
+        Some(row) => Ok(idea_from_row(&row)),
+        None => Err(DbError::ValidationError(format!(
+            "Idea {} is not {}",
+            idea_id, from
+        ))),
+    }
+}
+
+/// Records the price every token of a resolved idea was settled at.
+pub async fn set_settlement_price(
+    client: &impl GenericClient,
+    idea_id: &str,
+    settlement_price: Decimal,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE ideas SET settlement_price = $2 WHERE id = $1",
+            &[&idea_id, &settlement_price],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Price a resolved idea was settled at; `None` before resolution or for voided ideas.
+pub async fn get_settlement_price(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<Option<Decimal>, DbError> {
+    let row = client
+        .query_opt(
+            "SELECT settlement_price FROM ideas WHERE id = $1",
+            &[&idea_id],
+        )
+        .await?
+        .ok_or_else(|| DbError::NotFound(format!("Idea with ID {} not found", idea_id)))?;
+    Ok(row.get("settlement_price"))
+}
+
+/// Net holdings of every user in an idea, excluding the market maker account.
+pub async fn get_idea_holdings(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<Vec<Holding>, DbError> {
+    let result = client
+        .query(
+            "SELECT user_id, SUM(amount) AS amount, SUM(value) AS net_cost
+             FROM (
+                 SELECT buyer_id AS user_id, amount, total_value AS value
+                 FROM transactions WHERE idea_id = $1 AND status = 'completed'
+                 UNION ALL
+                 SELECT seller_id, -amount, -total_value
+                 FROM transactions WHERE idea_id = $1 AND status = 'completed'
+             ) legs
+             WHERE user_id <> $2
+             GROUP BY user_id
+             ORDER BY user_id",
+            &[&idea_id, &AMM_ACCOUNT_ID],
+        )
+        .await?;
+
+    Ok(result
+        .into_iter()
+        .map(|row| Holding {
+            user_id: row.get("user_id"),
+            idea_id: idea_id.to_string(),
+            amount: row.get("amount"),
+            net_cost: row.get("net_cost"),
+        })
+        .collect())
+}
+
+/// Net tokens a user holds in an idea across completed transactions.
+pub async fn get_user_holding(
+    client: &impl GenericClient,
+    idea_id: &str,
+    user_id: &str,
+) -> Result<Decimal, DbError> {
+    let row = client
+        .query_one(
+            "SELECT COALESCE(SUM(CASE WHEN buyer_id = $2 THEN amount ELSE -amount END), 0)
+                 AS amount
+             FROM transactions
+             WHERE idea_id = $1 AND status = 'completed'
+               AND (buyer_id = $2 OR seller_id = $2)
+               AND buyer_id <> seller_id",
+            &[&idea_id, &user_id],
+        )
+        .await?;
+    Ok(row.get("amount"))
+}
+
+/// Serializes everything that reduces a user's holding of an idea until the surrounding
+/// transaction ends, so a holding checked after this cannot be spent twice concurrently.
+pub async fn lock_user_holding(
+    client: &impl GenericClient,
+    idea_id: &str,
+    user_id: &str,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "SELECT pg_advisory_xact_lock(hashtextextended($1 || ':' || $2, 0))",
+            &[&idea_id, &user_id],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Net cash the market maker took in for an idea's tokens: what buyers paid it less what it paid
+/// sellers. The idea's payouts are funded from this.
+pub async fn get_market_maker_proceeds(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<Decimal, DbError> {
+    let row = client
+        .query_one(
+            "SELECT COALESCE(SUM(CASE WHEN seller_id = $2 THEN total_value ELSE -total_value END), 0)
+                 AS proceeds
+             FROM transactions
+             WHERE idea_id = $1 AND status = 'completed'
+               AND (buyer_id = $2 OR seller_id = $2)",
+            &[&idea_id, &AMM_ACCOUNT_ID],
+        )
+        .await?;
+    Ok(row.get("proceeds"))
+}
+
+/// Net holding less the tokens reserved by the user's resting sell orders and locked by their
+/// active stakes.
+pub async fn get_available_holding(
+    client: &impl GenericClient,
+    idea_id: &str,
+    user_id: &str,
+) -> Result<Decimal, DbError> {
+    let holding = get_user_holding(client, idea_id, user_id).await?;
+    let row = client
+        .query_one(
+            "SELECT (SELECT COALESCE(SUM(tokens), 0) FROM order_holds
+                     WHERE idea_id = $1 AND user_id = $2)
+                  + (SELECT COALESCE(SUM(amount), 0) FROM stakes
+                     WHERE idea_id = $1 AND user_id = $2 AND status = 'active') AS held",
+            &[&idea_id, &user_id],
+        )
+        .await?;
+    Ok(holding - row.get::<_, Decimal>("held"))
+}
+
+/// What a resting order reserves, see `order_holds`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderHold {
+    pub order_id: u64,
+    pub instance_id: String,
+    pub idea_id: String,
+    pub user_id: String,
+    /// Units still in the owner's hold account.
+    pub cash: i64,
+    /// Tokens still reserved by a sell order.
+    pub tokens: Decimal,
+}
+
+fn order_hold_from_row(row: &tokio_postgres::Row) -> OrderHold {
+    OrderHold {
+        order_id: row.get::<_, i64>("order_id") as u64,
+        instance_id: row.get("instance_id"),
+        idea_id: row.get("idea_id"),
+        user_id: row.get("user_id"),
+        cash: row.get("cash"),
+        tokens: row.get("tokens"),
+    }
+}
+
+/// A new order ID, unique across server instances and restarts.
+pub async fn next_order_id(client: &impl GenericClient) -> Result<u64, DbError> {
+    let row = client.query_one("SELECT nextval('order_ids')", &[]).await?;
+    Ok(row.get::<_, i64>(0) as u64)
+}
+
+/// Records what an order reserves and moves its cash into the owner's hold account. Check the
+/// available holding of a sell order first, under `lock_user_holding`.
+pub async fn insert_order_hold(
+    client: &impl GenericClient,
+    hold: &OrderHold,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "INSERT INTO order_holds (order_id, instance_id, idea_id, user_id, cash, tokens)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &(hold.order_id as i64),
+                &hold.instance_id,
+                &hold.idea_id,
+                &hold.user_id,
+                &hold.cash,
+                &hold.tokens,
+            ],
+        )
+        .await?;
+    if hold.cash > 0 {
+        let memo = format!("order {}", hold.order_id);
+        ledger::move_hold(client, &hold.user_id, hold.cash, &memo).await?;
+    }
+    Ok(())
+}
+
+/// Holds of the given orders, locked until the surrounding transaction ends.
+pub async fn lock_order_holds(
+    client: &impl GenericClient,
+    order_ids: &[u64],
+) -> Result<Vec<OrderHold>, DbError> {
+    let ids: Vec<i64> = order_ids.iter().map(|id| *id as i64).collect();
+    let rows = client
+        .query(
+            "SELECT order_id, instance_id, idea_id, user_id, cash, tokens FROM order_holds
+             WHERE order_id = ANY($1) ORDER BY order_id FOR UPDATE",
+            &[&ids],
+        )
+        .await?;
+    Ok(rows.iter().map(order_hold_from_row).collect())
+}
+
+/// Takes what a fill spent out of an order's hold. The cash itself leaves the hold account
+/// with the trade's ledger entry.
+pub async fn consume_order_hold(
+    client: &impl GenericClient,
+    order_id: u64,
+    cash: i64,
+    tokens: Decimal,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE order_holds SET cash = cash - $2, tokens = tokens - $3 WHERE order_id = $1",
+            &[&(order_id as i64), &cash, &tokens],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Drops an order's hold and returns the cash it still held to its owner.
+pub async fn release_order_hold(
+    client: &impl GenericClient,
+    order_id: u64,
+) -> Result<Option<OrderHold>, DbError> {
+    let row = client
+        .query_opt(
+            "DELETE FROM order_holds WHERE order_id = $1
+             RETURNING order_id, instance_id, idea_id, user_id, cash, tokens",
+            &[&(order_id as i64)],
+        )
+        .await?;
+    let Some(hold) = row.as_ref().map(order_hold_from_row) else {
+        return Ok(None);
+    };
+    if hold.cash > 0 {
+        let memo = format!("order {}", order_id);
+        ledger::move_hold(client, &hold.user_id, -hold.cash, &memo).await?;
+    }
+    Ok(Some(hold))
+}
+
+/// Orders holding anything on behalf of a server instance.
+pub async fn get_instance_order_ids(
+    client: &impl GenericClient,
+    instance_id: &str,
+) -> Result<Vec<u64>, DbError> {
+    let rows = client
+        .query(
+            "SELECT order_id FROM order_holds WHERE instance_id = $1 ORDER BY order_id",
+            &[&instance_id],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| row.get::<_, i64>("order_id") as u64)
+        .collect())
+}
+
+pub async fn insert_payout(client: &impl GenericClient, payout: Payout) -> Result<Payout, DbError> {
+    let result = client
+        .query_one(
+            "INSERT INTO payouts (idea_id, user_id, amount, payout)
+             VALUES ($1, $2, $3, $4)
+             RETURNING id, created_at::text",
+            &[
+                &payout.idea_id,
+                &payout.user_id,
+                &payout.amount,
+                &payout.payout,
+            ],
+        )
+        .await?;
+
+    Ok(Payout {
+        id: Some(result.get("id")),
+        created_at: parse_timestamp(&result, "created_at"),
+        ..payout
+    })
+}
+
+/// Inserts a rate using the given client, so it can take part in a surrounding transaction.
+pub async fn insert_rate(client: &impl GenericClient, rate: Rate) -> Result<Rate, DbError> {
     // Validate input
     if rate.idea_id.trim().is_empty() {
         return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
     }
-    if rate.rate < 0.0 {
+    if rate.rate.is_negative() {
         return Err(DbError::ValidationError("Rate cannot be negative".into()));
     }
-    if rate.volume < 0.0 {
+    if rate.volume.is_negative() {
         return Err(DbError::ValidationError("Volume cannot be negative".into()));
     }
 
-    let client = getGlobalClient().await?;
     let result = client
         .query_one(
             "INSERT INTO rates (idea_id, rate, volume) 
@@ -467,65 +1025,45 @@ pub async fn createRate(rate: Rate) -> Result<Rate, DbError> {
     })
 }
 
-pub async fn getIdeaRates(idea_id: &str, limit: i64) -> Result<Vec<Rate>, DbError> {
-    if idea_id.trim().is_empty() {
-        return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
-    }
-    if limit <= 0 {
-        return Err(DbError::ValidationError("Limit must be positive".into()));
+/// Average rate over `[from, to)`, each rate weighted by how long it stood. The rate in effect
+/// at `from` counts from then on; `None` if no trade happened before `to`.
+pub async fn time_weighted_rate(
+    client: &impl GenericClient,
+    idea_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Option<Decimal>, DbError> {
+    if from >= to {
+        return Ok(None);
     }
-
-    let client = getGlobalClient().await?;
-    let result = client
-        .query(
-            "SELECT id, idea_id, rate, volume, created_at::text 
-             FROM rates 
-             WHERE idea_id = $1 
-             ORDER BY created_at DESC 
-             LIMIT $2",
-            &[&idea_id, &limit],
+    let row = client
+        .query_one(
+            "WITH window_rates AS (
+                 (SELECT rate, $2::timestamptz AS since FROM rates
+                  WHERE idea_id = $1 AND created_at <= $2
+                  ORDER BY created_at DESC LIMIT 1)
+                 UNION ALL
+                 SELECT rate, created_at FROM rates
+                 WHERE idea_id = $1 AND created_at > $2 AND created_at < $3
+             ),
+             spans AS (
+                 SELECT rate,
+                        EXTRACT(EPOCH FROM LEAD(since, 1, $3) OVER (ORDER BY since) - since)
+                            AS seconds
+                 FROM window_rates
+             )
+             SELECT ROUND(SUM(rate * seconds) / NULLIF(SUM(seconds), 0), 8) AS twap FROM spans",
+            &[&idea_id, &from, &to],
         )
         .await?;
-
-    Ok(result
-        .into_iter()
-        .map(|row| Rate {
-            id: Some(row.get("id")),
-            idea_id: row.get("idea_id"),
-            rate: row.get("rate"),
-            volume: row.get("volume"),
-            created_at: parse_timestamp(&row, "created_at"),
-        })
-        .collect())
+    Ok(row.get("twap"))
 }
 
-pub async fn getAllIdeas() -> Result<Vec<Idea>, DbError> {
-    let client = getGlobalClient().await?;
-    let result = client.query("SELECT id, title, description, creator_id, status, resolution_outcome, created_at::text, updated_at::text, resolved_at::text FROM ideas ORDER BY created_at DESC", &[]).await?;
-    Ok(result
-        .into_iter()
-        .map(|row| Idea {
-            id: Some(row.get("id")),
-            title: row.get("title"),
-            description: row.get("description"),
-            creator_id: row.get("creator_id"),
-            status: row.get("status"),
-            resolution_outcome: row.get("resolution_outcome"),
-            created_at: parse_timestamp(&row, "created_at"),
-            updated_at: parse_timestamp(&row, "updated_at"),
-            resolved_at: parse_timestamp(&row, "resolved_at"),
-            category: row.get("category"),
-            initial_price: row.get("initial_price"),
-            target_price: row.get("target_price"),
-            timeframe: row.get("timeframe"),
-            risk_level: row.get("risk_level"),
-            market_size: row.get("market_size"),
-            competitive_advantage: row.get("competitive_advantage"),
-        })
-        .collect())
-}
-
-pub async fn createTransaction(tx: Transaction) -> Result<Transaction, DbError> {
+/// Inserts a transaction using the given client, so it can take part in a surrounding transaction.
+pub async fn insert_transaction(
+    client: &impl GenericClient,
+    tx: Transaction,
+) -> Result<Transaction, DbError> {
     // Validate input
     if tx.idea_id.trim().is_empty() {
         return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
@@ -536,20 +1074,26 @@ pub async fn createTransaction(tx: Transaction) -> Result<Transaction, DbError>
     if tx.seller_id.trim().is_empty() {
         return Err(DbError::ValidationError("Seller ID cannot be empty".into()));
     }
-    if tx.amount <= 0.0 {
+    if !tx.amount.is_positive() {
         return Err(DbError::ValidationError("Amount must be positive".into()));
     }
-    if tx.rate <= 0.0 {
+    if !tx.rate.is_positive() {
         return Err(DbError::ValidationError("Rate must be positive".into()));
     }
 
-    let client = getGlobalClient().await?;
+    let status = if tx.status.trim().is_empty() {
+        "pending".to_string()
+    } else {
+        tx.status
+    };
+
     let result = client
         .query_one(
             "INSERT INTO transactions 
-             (idea_id, buyer_id, seller_id, amount, rate, total_value) 
-             VALUES ($1, $2, $3, $4, $5, $6) 
-             RETURNING id, created_at::text, updated_at::text",
+             (idea_id, buyer_id, seller_id, amount, rate, total_value, status, completed_at) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7,
+                     CASE WHEN $7 = 'completed' THEN CURRENT_TIMESTAMP END) 
+             RETURNING id, created_at::text, updated_at::text, completed_at::text",
             &[
                 &tx.idea_id,
                 &tx.buyer_id,
@@ -557,6 +1101,7 @@ pub async fn createTransaction(tx: Transaction) -> Result<Transaction, DbError>
                 &tx.amount,
                 &tx.rate,
                 &tx.total_value,
+                &status,
             ],
         )
         .await?;
@@ -569,58 +1114,70 @@ pub async fn createTransaction(tx: Transaction) -> Result<Transaction, DbError>
         amount: tx.amount,
         rate: tx.rate,
         total_value: tx.total_value,
-        status: "pending".to_string(),
+        status,
         created_at: parse_timestamp(&result, "created_at"),
         updated_at: parse_timestamp(&result, "updated_at"),
-        completed_at: None,
+        completed_at: parse_timestamp(&result, "completed_at"),
     })
 }
 
-pub async fn getIdeaTransactions(idea_id: &str, limit: i64) -> Result<Vec<Transaction>, DbError> {
+pub async fn get_amm_pool(client: &impl GenericClient, idea_id: &str) -> Result<AmmPool, DbError> {
+    fetch_amm_pool(client, idea_id, false).await
+}
+
+/// Reads a pool and locks its row until the surrounding transaction ends.
+pub async fn lock_amm_pool(client: &impl GenericClient, idea_id: &str) -> Result<AmmPool, DbError> {
+    fetch_amm_pool(client, idea_id, true).await
+}
+
+async fn fetch_amm_pool(
+    client: &impl GenericClient,
+    idea_id: &str,
+    for_update: bool,
+) -> Result<AmmPool, DbError> {
     if idea_id.trim().is_empty() {
         return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
     }
-    if limit <= 0 {
-        return Err(DbError::ValidationError("Limit must be positive".into()));
+
+    let query = format!(
+        "SELECT idea_id, token_reserve, cash_reserve FROM amm_pools WHERE idea_id = $1{}",
+        if for_update { " FOR UPDATE" } else { "" }
+    );
+    let result = client.query_opt(&query, &[&idea_id]).await?;
+
+    match result {
+        Some(row) => Ok(AmmPool {
+            idea_id: row.get("idea_id"),
+            token_reserve: row.get("token_reserve"),
+            cash_reserve: row.get("cash_reserve"),
+        }),
+        None => Err(DbError::NotFound(format!(
+            "No market maker pool for idea {}",
+            idea_id
+        ))),
     }
+}
 
-    let client = getGlobalClient().await?;
-    let result = client
-        .query(
-            "SELECT id, idea_id, buyer_id, seller_id, amount, rate, total_value, status,
-                    created_at::text, updated_at::text, completed_at::text
-             FROM transactions 
-             WHERE idea_id = $1 
-             ORDER BY created_at DESC 
-             LIMIT $2",
-            &[&idea_id, &limit],
+pub async fn update_amm_pool(client: &impl GenericClient, pool: &AmmPool) -> Result<(), DbError> {
+    client
+        .execute(
+            "UPDATE amm_pools SET token_reserve = $1, cash_reserve = $2, updated_at = CURRENT_TIMESTAMP
+             WHERE idea_id = $3",
+            &[&pool.token_reserve, &pool.cash_reserve, &pool.idea_id],
         )
         .await?;
-
-    Ok(result
-        .into_iter()
-        .map(|row| Transaction {
-            id: Some(row.get("id")),
-            idea_id: row.get("idea_id"),
-            buyer_id: row.get("buyer_id"),
-            seller_id: row.get("seller_id"),
-            amount: row.get("amount"),
-            rate: row.get("rate"),
-            total_value: row.get("total_value"),
-            status: row.get("status"),
-            created_at: parse_timestamp(&row, "created_at"),
-            updated_at: parse_timestamp(&row, "updated_at"),
-            completed_at: parse_timestamp(&row, "completed_at"),
-        })
-        .collect())
+    Ok(())
 }
 
-pub async fn getCurrentRate(idea_id: &str) -> Result<Rate, DbError> {
+/// Most recent rate, or `None` before the idea's first trade.
+async fn get_latest_rate(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<Option<Rate>, DbError> {
     if idea_id.trim().is_empty() {
         return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
     }
 
-    let client = getGlobalClient().await?;
     let result = client
         .query_opt(
             "SELECT id, idea_id, rate, volume, created_at::text 
@@ -632,27 +1189,23 @@ pub async fn getCurrentRate(idea_id: &str) -> Result<Rate, DbError> {
         )
         .await?;
 
-    match result {
-        Some(row) => Ok(Rate {
-            id: Some(row.get("id")),
-            idea_id: row.get("idea_id"),
-            rate: row.get("rate"),
-            volume: row.get("volume"),
-            created_at: parse_timestamp(&row, "created_at"),
-        }),
-        None => Err(DbError::NotFound(format!(
-            "No rates found for idea {}",
-            idea_id
-        ))),
-    }
+    Ok(result.map(|row| Rate {
+        id: Some(row.get("id")),
+        idea_id: row.get("idea_id"),
+        rate: row.get("rate"),
+        volume: row.get("volume"),
+        created_at: parse_timestamp(&row, "created_at"),
+    }))
 }
 
-pub async fn getIdeaStats(idea_id: &str) -> Result<serde_json::Value, DbError> {
+pub async fn get_idea_stats(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<serde_json::Value, DbError> {
     if idea_id.trim().is_empty() {
         return Err(DbError::ValidationError("Idea ID cannot be empty".into()));
     }
 
-    let client = getGlobalClient().await?;
     let result = client
         .query_one(
             "SELECT 
@@ -671,13 +1224,65 @@ pub async fn getIdeaStats(idea_id: &str) -> Result<serde_json::Value, DbError> {
     Ok(serde_json::json!({
         "unique_buyers": result.get::<_, i64>("unique_buyers"),
         "unique_sellers": result.get::<_, i64>("unique_sellers"),
-        "total_volume": result.get::<_, f64>("total_volume"),
-        "avg_rate": result.get::<_, f64>("avg_rate"),
-        "min_rate": result.get::<_, f64>("min_rate"),
-        "max_rate": result.get::<_, f64>("max_rate")
+        // Aggregates are null until the idea has a completed trade
+        "total_volume": result.get::<_, Option<Decimal>>("total_volume"),
+        "avg_rate": result.get::<_, Option<Decimal>>("avg_rate"),
+        "min_rate": result.get::<_, Option<Decimal>>("min_rate"),
+        "max_rate": result.get::<_, Option<Decimal>>("max_rate")
     }))
 }
 
+/// Trading activity of an idea over the last 24 hours.
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketSummary {
+    /// Latest rate at least 24 hours old; `None` for ideas younger than a day.
+    pub price_24h_ago: Option<Decimal>,
+    /// Cash traded in the last 24 hours.
+    pub volume_24h: Decimal,
+    pub trades_24h: i64,
+    /// Users with a positive net holding, excluding the market maker.
+    pub holders: i64,
+}
+
+pub async fn get_market_summary(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<MarketSummary, DbError> {
+    let row = client
+        .query_one(
+            "SELECT
+                (SELECT rate FROM rates
+                 WHERE idea_id = $1 AND created_at <= CURRENT_TIMESTAMP - INTERVAL '24 hours'
+                 ORDER BY created_at DESC LIMIT 1) AS price_24h_ago,
+                COALESCE(SUM(t.total_value), 0) AS volume_24h,
+                COUNT(t.id) AS trades_24h,
+                (SELECT COUNT(*) FROM (
+                     SELECT user_id FROM (
+                         SELECT buyer_id AS user_id, amount
+                         FROM transactions WHERE idea_id = $1 AND status = 'completed'
+                         UNION ALL
+                         SELECT seller_id, -amount
+                         FROM transactions WHERE idea_id = $1 AND status = 'completed'
+                     ) legs
+                     WHERE user_id <> $2
+                     GROUP BY user_id
+                     HAVING SUM(amount) > 0
+                 ) holders) AS holders
+             FROM transactions t
+             WHERE t.idea_id = $1 AND t.status = 'completed'
+               AND t.created_at > CURRENT_TIMESTAMP - INTERVAL '24 hours'",
+            &[&idea_id, &AMM_ACCOUNT_ID],
+        )
+        .await?;
+
+    Ok(MarketSummary {
+        price_24h_ago: row.get("price_24h_ago"),
+        volume_24h: row.get("volume_24h"),
+        trades_24h: row.get("trades_24h"),
+        holders: row.get("holders"),
+    })
+}
+
 pub async fn update_user(
     client: &Client,
     wallet_address: &str,
@@ -688,15 +1293,15 @@ pub async fn update_user(
     let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
     let mut param_count = 1;
 
-    if let Some(username) = username {
+    if let Some(username) = &username {
         updates.push(format!("username = ${}", param_count));
-        params.push(&username as &(dyn ToSql + Sync));
+        params.push(username as &(dyn ToSql + Sync));
         param_count += 1;
     }
 
-    if let Some(category) = category {
+    if let Some(category) = &category {
         updates.push(format!("category = ${}", param_count));
-        params.push(&category as &(dyn ToSql + Sync));
+        params.push(category as &(dyn ToSql + Sync));
         param_count += 1;
     }
 
@@ -704,6 +1309,21 @@ pub async fn update_user(
         return Err(DbError::ValidationError("No fields to update".into()));
     }
 
+    if let Some(username) = username {
+        if username.trim().is_empty() {
+            return Err(DbError::ValidationError("Username cannot be empty".into()));
+        }
+        let taken = client
+            .query_opt(
+                "SELECT id FROM users WHERE username = $1 AND wallet_address <> $2",
+                &[&username, &wallet_address],
+            )
+            .await?;
+        if taken.is_some() {
+            return Err(DbError::Conflict("Username already taken".into()));
+        }
+    }
+
     params.push(&wallet_address as &(dyn ToSql + Sync));
     let query = format!(
         "UPDATE users SET {} WHERE wallet_address = ${} RETURNING id, username, wallet_address, category, created_at, updated_at",
@@ -711,14 +1331,161 @@ pub async fn update_user(
         param_count
     );
 
-    let user = client.query_one(&query, &params).await?;
+    match client.query_opt(&query, &params).await? {
+        Some(user) => Ok(User {
+            id: user.get("id"),
+            username: user.get("username"),
+            wallet_address: user.get("wallet_address"),
+            category: user.get("category"),
+            created_at: user.get("created_at"),
+            updated_at: user.get("updated_at"),
+        }),
+        None => Err(DbError::NotFound(format!(
+            "User with wallet address {} not found",
+            wallet_address
+        ))),
+    }
+}
+
+/// Deletes a user with no balance and nothing referencing them, else a `Conflict`.
+pub async fn delete_user(client: &mut Client, wallet_address: &str) -> Result<(), DbError> {
+    let tx = client.transaction().await?;
+    let user = tx
+        .query_opt(
+            "SELECT id FROM users WHERE wallet_address = $1 FOR UPDATE",
+            &[&wallet_address],
+        )
+        .await?
+        .ok_or_else(|| {
+            DbError::NotFound(format!(
+                "User with wallet address {} not found",
+                wallet_address
+            ))
+        })?;
+    let user_id: String = user.get("id");
 
-    Ok(User {
-        id: user.get("id"),
-        username: user.get("username"),
-        wallet_address: user.get("wallet_address"),
-        category: user.get("category"),
-        created_at: user.get("created_at"),
-        updated_at: user.get("updated_at"),
-    })
+    let funded = tx
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM ledger_accounts WHERE user_id = $1 AND balance <> 0)
+                 AS funded",
+            &[&user_id],
+        )
+        .await?;
+    if funded.get::<_, bool>("funded") {
+        return Err(DbError::Conflict(
+            "User still has a balance and cannot be deleted".into(),
+        ));
+    }
+
+    // Everything that references the user, so it is refused up front rather than by a foreign key
+    let history = tx
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM ideas WHERE creator_id = $1 OR resolver_id = $1)
+                 OR EXISTS (SELECT 1 FROM transactions WHERE buyer_id = $1 OR seller_id = $1)
+                 OR EXISTS (SELECT 1 FROM payouts WHERE user_id = $1)
+                 OR EXISTS (SELECT 1 FROM idea_revisions WHERE editor_id = $1)
+                 OR EXISTS (SELECT 1 FROM votes WHERE user_id = $1)
+                 OR EXISTS (SELECT 1 FROM stakes WHERE user_id = $1)
+                 OR EXISTS (SELECT 1 FROM markets WHERE creator_id = $1)
+                 OR EXISTS (SELECT 1 FROM market_bets WHERE user_id = $1)
+                 OR EXISTS (SELECT 1 FROM journal_lines l
+                            JOIN ledger_accounts a ON a.id = l.account_id
+                            WHERE a.user_id = $1)
+                 AS has_history",
+            &[&user_id],
+        )
+        .await?;
+    if history.get::<_, bool>("has_history") {
+        return Err(DbError::Conflict(
+            "User has ideas, trades, bets or ledger history and cannot be deleted".into(),
+        ));
+    }
+
+    // Only empty, never-used accounts are left
+    tx.execute(
+        "DELETE FROM ledger_accounts WHERE user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+    // A row added since the checks, e.g. by a racing trade, still blocks the delete
+    tx.execute("DELETE FROM users WHERE id = $1", &[&user_id])
+        .await
+        .map_err(|e| match e.code() {
+            Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+                DbError::Conflict("User is still referenced and cannot be deleted".into())
+            }
+            _ => DbError::from(e),
+        })?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A transaction together with the title of the idea it traded.
+#[derive(Debug, Serialize)]
+pub struct UserTransaction {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    pub idea_title: String,
+}
+
+/// One page of a user's transactions, newest first, plus the total count.
+pub async fn get_user_transactions(
+    client: &Client,
+    user_id: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<UserTransaction>, i64), DbError> {
+    if user_id.trim().is_empty() {
+        return Err(DbError::ValidationError("User ID cannot be empty".into()));
+    }
+    if limit <= 0 {
+        return Err(DbError::ValidationError("Limit must be positive".into()));
+    }
+    if offset < 0 {
+        return Err(DbError::ValidationError("Offset cannot be negative".into()));
+    }
+
+    let total = client
+        .query_one(
+            "SELECT COUNT(*) AS total FROM transactions WHERE buyer_id = $1 OR seller_id = $1",
+            &[&user_id],
+        )
+        .await?
+        .get::<_, i64>("total");
+
+    let result = client
+        .query(
+            "SELECT t.id, t.idea_id, i.title AS idea_title, t.buyer_id, t.seller_id,
+                    t.amount, t.rate, t.total_value, t.status,
+                    t.created_at::text, t.updated_at::text, t.completed_at::text
+             FROM transactions t
+             JOIN ideas i ON i.id = t.idea_id
+             WHERE t.buyer_id = $1 OR t.seller_id = $1
+             ORDER BY t.created_at DESC, t.id
+             LIMIT $2 OFFSET $3",
+            &[&user_id, &limit, &offset],
+        )
+        .await?;
+
+    let transactions = result
+        .into_iter()
+        .map(|row| UserTransaction {
+            idea_title: row.get("idea_title"),
+            transaction: Transaction {
+                id: Some(row.get("id")),
+                idea_id: row.get("idea_id"),
+                buyer_id: row.get("buyer_id"),
+                seller_id: row.get("seller_id"),
+                amount: row.get("amount"),
+                rate: row.get("rate"),
+                total_value: row.get("total_value"),
+                status: row.get("status"),
+                created_at: parse_timestamp(&row, "created_at"),
+                updated_at: parse_timestamp(&row, "updated_at"),
+                completed_at: parse_timestamp(&row, "completed_at"),
+            },
+        })
+        .collect();
+
+    Ok((transactions, total))
 }
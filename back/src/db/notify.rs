@@ -0,0 +1,68 @@
+use super::{tls_connector, DbError};
+use crate::config::CONFIG;
+use std::future::poll_fn;
+use std::pin::pin;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::{AsyncMessage, Client, Connection, NoTls, Notification};
+
+/// Channel the `notify_rate` and `notify_trade` triggers publish on.
+pub const IDEA_EVENTS_CHANNEL: &str = "idea_events";
+
+/// Opens a dedicated connection, `LISTEN`s on `channel` and hands every notification to
+/// `on_notification` until the connection drops. Pooled clients cannot be used because
+/// notifications only arrive while their connection is polled.
+pub async fn listen(
+    channel: &str,
+    mut on_notification: impl FnMut(Notification),
+) -> Result<(), DbError> {
+    let pg_config = tokio_postgres::Config::from_str(&CONFIG.database_url)
+        .map_err(|e| DbError::ConnectionError(format!("Invalid DATABASE_URL: {}", e)))?;
+    match pg_config.get_ssl_mode() {
+        SslMode::Disable => {
+            let (client, connection) = pg_config.connect(NoTls).await?;
+            relay(client, connection, channel, &mut on_notification).await
+        }
+        _ => {
+            let (client, connection) = pg_config.connect(tls_connector()?).await?;
+            relay(client, connection, channel, &mut on_notification).await
+        }
+    }
+}
+
+async fn relay<S, T>(
+    client: Client,
+    mut connection: Connection<S, T>,
+    channel: &str,
+    on_notification: &mut impl FnMut(Notification),
+) -> Result<(), DbError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    // The LISTEN only completes while the connection is being polled
+    let query = format!("LISTEN {}", channel);
+    let mut subscribe = pin!(client.batch_execute(&query));
+    let mut subscribed = false;
+
+    loop {
+        tokio::select! {
+            result = &mut subscribe, if !subscribed => {
+                result?;
+                subscribed = true;
+            }
+            message = poll_fn(|cx| connection.poll_message(cx)) => match message {
+                Some(Ok(AsyncMessage::Notification(notification))) => on_notification(notification),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => {
+                    return Err(DbError::ConnectionError(format!(
+                        "Connection listening on {} closed",
+                        channel
+                    )))
+                }
+            }
+        }
+    }
+}
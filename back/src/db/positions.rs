@@ -0,0 +1,192 @@
+use super::{DbError, GenericClient};
+use crate::decimal::{Decimal, Rounding};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A user's position in one idea, valued at the idea's latest rate.
+#[derive(Debug, Clone, Serialize)]
+pub struct Position {
+    pub idea_id: String,
+    pub idea_title: String,
+    /// Net tokens held; negative for a short position.
+    pub total_amount: Decimal,
+    /// Average cost of the tokens still held.
+    pub average_price: Decimal,
+    pub current_price: Decimal,
+    /// `total_amount` valued at `current_price`.
+    pub total_value: Decimal,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    /// Realized plus unrealized P&L.
+    pub profit_loss: Decimal,
+    /// `profit_loss` relative to the cash put into the position.
+    pub profit_loss_percentage: f64,
+}
+
+/// One completed trade leg seen from the user's side.
+#[derive(Debug, Clone)]
+pub struct PositionLeg {
+    pub idea_id: String,
+    pub idea_title: String,
+    /// Positive for a buy, negative for a sell.
+    pub amount: Decimal,
+    pub rate: Decimal,
+}
+
+#[derive(Debug, Default)]
+struct Accumulator {
+    idea_title: String,
+    amount: Decimal,
+    // Signed cost basis of `amount`
+    cost: Decimal,
+    realized: Decimal,
+    invested: Decimal,
+}
+
+impl Accumulator {
+    /// Applies a trade using the average cost method.
+    fn apply(&mut self, amount: Decimal, rate: Decimal) {
+        let value = amount.mul_rounded(rate, Rounding::HalfEven);
+        self.invested += value.abs();
+
+        let opposite = !self.amount.is_zero() && amount.is_negative() != self.amount.is_negative();
+        if !opposite {
+            self.amount += amount;
+            self.cost += value;
+            return;
+        }
+
+        // Closing part of the position releases the same share of its cost basis
+        let closing = amount.abs().min(self.amount.abs());
+        let released = self
+            .cost
+            .mul_rounded(closing, Rounding::HalfEven)
+            .div_rounded(self.amount.abs(), Rounding::HalfEven)
+            .unwrap_or(self.cost);
+        let closing_value = closing.mul_rounded(rate, Rounding::HalfEven);
+        let (closing, closing_value) = if self.amount.is_negative() {
+            (-closing, -closing_value)
+        } else {
+            (closing, closing_value)
+        };
+        self.realized += closing_value - released;
+        self.amount -= closing;
+        self.cost -= released;
+
+        // Whatever is left over opens a position on the other side
+        let remainder = amount + closing;
+        if !remainder.is_zero() {
+            self.amount += remainder;
+            self.cost += remainder.mul_rounded(rate, Rounding::HalfEven);
+        }
+        if self.amount.is_zero() {
+            self.cost = Decimal::ZERO;
+        }
+    }
+}
+
+/// Folds chronologically ordered legs into one position per idea.
+pub fn build_positions(legs: &[PositionLeg], prices: &HashMap<String, Decimal>) -> Vec<Position> {
+    let mut order = Vec::new();
+    let mut accumulators: HashMap<&str, Accumulator> = HashMap::new();
+
+    for leg in legs {
+        let acc = accumulators.entry(&leg.idea_id).or_insert_with(|| {
+            order.push(leg.idea_id.as_str());
+            Accumulator {
+                idea_title: leg.idea_title.clone(),
+                ..Default::default()
+            }
+        });
+        acc.apply(leg.amount, leg.rate);
+    }
+
+    order
+        .into_iter()
+        .map(|idea_id| {
+            let acc = &accumulators[idea_id];
+            let average_price = acc
+                .cost
+                .div_rounded(acc.amount, Rounding::HalfEven)
+                .unwrap_or(Decimal::ZERO);
+            let current_price = prices.get(idea_id).copied().unwrap_or(average_price);
+            let total_value = acc.amount.mul_rounded(current_price, Rounding::HalfEven);
+            let unrealized_pnl = total_value - acc.cost;
+            let profit_loss = acc.realized + unrealized_pnl;
+
+            Position {
+                idea_id: idea_id.to_string(),
+                idea_title: acc.idea_title.clone(),
+                total_amount: acc.amount,
+                average_price,
+                current_price,
+                total_value,
+                realized_pnl: acc.realized,
+                unrealized_pnl,
+                profit_loss,
+                profit_loss_percentage: if acc.invested.is_positive() {
+                    profit_loss.to_f64() / acc.invested.to_f64() * 100.0
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect()
+}
+
+/// Positions of a user across every idea they traded, from completed transactions.
+pub async fn get_user_positions(
+    client: &impl GenericClient,
+    user_id: &str,
+) -> Result<Vec<Position>, DbError> {
+    if user_id.trim().is_empty() {
+        return Err(DbError::ValidationError("User ID cannot be empty".into()));
+    }
+
+    let rows = client
+        .query(
+            "SELECT t.idea_id, i.title AS idea_title,
+                    CASE WHEN t.buyer_id = $1 THEN t.amount ELSE -t.amount END AS amount,
+                    t.rate
+             FROM transactions t
+             JOIN ideas i ON i.id = t.idea_id
+             WHERE (t.buyer_id = $1 OR t.seller_id = $1)
+               AND t.buyer_id <> t.seller_id
+               AND t.status = 'completed'
+             ORDER BY t.created_at, t.id",
+            &[&user_id],
+        )
+        .await?;
+
+    let legs: Vec<PositionLeg> = rows
+        .into_iter()
+        .map(|row| PositionLeg {
+            idea_id: row.get("idea_id"),
+            idea_title: row.get("idea_title"),
+            amount: row.get("amount"),
+            rate: row.get("rate"),
+        })
+        .collect();
+
+    let mut idea_ids: Vec<&str> = legs.iter().map(|leg| leg.idea_id.as_str()).collect();
+    idea_ids.sort_unstable();
+    idea_ids.dedup();
+
+    // Latest rate per idea, falling back to the initial price for ideas without rates
+    let prices = client
+        .query(
+            "SELECT i.id, COALESCE(r.rate, i.initial_price) AS price
+             FROM ideas i
+             LEFT JOIN LATERAL (
+                 SELECT rate FROM rates WHERE idea_id = i.id ORDER BY created_at DESC LIMIT 1
+             ) r ON true
+             WHERE i.id = ANY($1)",
+            &[&idea_ids],
+        )
+        .await?
+        .into_iter()
+        .map(|row| (row.get("id"), row.get("price")))
+        .collect();
+
+    Ok(build_positions(&legs, &prices))
+}
@@ -0,0 +1,426 @@
+//! Repository traits over the core tables, with a Postgres implementation for any client or
+//! transaction and an in-memory one (`db::memory`) for tests.
+//!
+//! Writes that must land together go through a [`UnitOfWork`]: every repository call made on
+//! it is applied on `commit`, or not at all if it is dropped first.
+
+use super::markets::{self, Bet, Market};
+use super::{ledger, token_syncs, DbError, Idea, OrderHold, PgPool, Rate, Transaction, User};
+use crate::decimal::Decimal;
+use deadpool_postgres::{Client, GenericClient};
+use std::future::Future;
+
+pub trait UserRepo {
+    fn create_user(&self, user: User) -> impl Future<Output = Result<User, DbError>>;
+    fn list_users(&self) -> impl Future<Output = Result<Vec<User>, DbError>>;
+    fn user_by_id(&self, id: &str) -> impl Future<Output = Result<User, DbError>>;
+    fn user_by_wallet(&self, wallet_address: &str) -> impl Future<Output = Result<User, DbError>>;
+}
+
+pub trait IdeaRepo {
+    /// Inserts the idea, plus the pool and opening rate of curve-priced ideas.
+    fn create_idea(&self, idea: Idea) -> impl Future<Output = Result<Idea, DbError>>;
+    fn idea_by_id(&self, id: &str) -> impl Future<Output = Result<Idea, DbError>>;
+    /// Reads an idea and holds its status against concurrent changes until the work is done.
+    fn lock_idea(&self, id: &str) -> impl Future<Output = Result<Idea, DbError>>;
+    /// Moves an idea from one status to another, failing if it is no longer in `from`.
+    fn transition_idea(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        outcome: Option<&str>,
+    ) -> impl Future<Output = Result<Idea, DbError>>;
+}
+
+pub trait RateRepo {
+    fn insert_rate(&self, rate: Rate) -> impl Future<Output = Result<Rate, DbError>>;
+    /// Most recent rate, or `None` before the idea's first trade.
+    fn latest_rate(&self, idea_id: &str) -> impl Future<Output = Result<Option<Rate>, DbError>>;
+}
+
+pub trait TradeRepo {
+    fn insert_transaction(
+        &self,
+        trade: Transaction,
+    ) -> impl Future<Output = Result<Transaction, DbError>>;
+    /// Moves the cash of a trade from buyer to seller, `from_hold` units out of the buyer's hold.
+    fn post_trade(
+        &self,
+        trade: &Transaction,
+        from_hold: i64,
+    ) -> impl Future<Output = Result<(), DbError>>;
+    /// Queues the holdings a trade changed to be mirrored on-chain, if the idea has a mint.
+    fn queue_token_sync(&self, trade: &Transaction) -> impl Future<Output = Result<(), DbError>>;
+}
+
+/// Resting orders' holds and the token holdings they reserve.
+pub trait OrderRepo {
+    /// A new order ID, unique across server instances and restarts.
+    fn next_order_id(&self) -> impl Future<Output = Result<u64, DbError>>;
+    /// Net tokens a user holds in an idea.
+    fn user_holding(
+        &self,
+        idea_id: &str,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Decimal, DbError>>;
+    /// Holding not reserved by orders or stakes, held against concurrent spending until the
+    /// work is done.
+    fn lock_available_holding(
+        &self,
+        idea_id: &str,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Decimal, DbError>>;
+    /// Records what an order reserves, moving its cash into the owner's hold account.
+    fn insert_order_hold(&self, hold: &OrderHold) -> impl Future<Output = Result<(), DbError>>;
+    fn lock_order_holds(
+        &self,
+        order_ids: &[u64],
+    ) -> impl Future<Output = Result<Vec<OrderHold>, DbError>>;
+    /// Takes what a fill spent out of an order's hold.
+    fn consume_order_hold(
+        &self,
+        order_id: u64,
+        cash: i64,
+        tokens: Decimal,
+    ) -> impl Future<Output = Result<(), DbError>>;
+    /// Drops an order's hold, returning its remaining cash to the owner.
+    fn release_order_hold(
+        &self,
+        order_id: u64,
+    ) -> impl Future<Output = Result<Option<OrderHold>, DbError>>;
+    fn instance_order_ids(
+        &self,
+        instance_id: &str,
+    ) -> impl Future<Output = Result<Vec<u64>, DbError>>;
+}
+
+pub trait MarketRepo {
+    /// Creates an open market with an empty pool per outcome.
+    fn insert_market(
+        &self,
+        event_name: &str,
+        creator_id: &str,
+        mint: Option<&str>,
+        outcomes: &[String],
+    ) -> impl Future<Output = Result<Market, DbError>>;
+    fn market_by_id(&self, id: &str) -> impl Future<Output = Result<Market, DbError>>;
+    /// Reads a market and holds it against concurrent changes until the work is done.
+    fn lock_market(&self, id: &str) -> impl Future<Output = Result<Market, DbError>>;
+    /// Moves a market from one status to another, failing if it is no longer in `from`.
+    fn transition_market(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        resolved_outcome: Option<i16>,
+    ) -> impl Future<Output = Result<(), DbError>>;
+    /// Records a bet and adds its amount to the outcome pool.
+    fn insert_bet(&self, bet: Bet) -> impl Future<Output = Result<Bet, DbError>>;
+    fn bet_signature_exists(
+        &self,
+        tx_signature: &str,
+    ) -> impl Future<Output = Result<bool, DbError>>;
+    fn market_bets(&self, market_id: &str) -> impl Future<Output = Result<Vec<Bet>, DbError>>;
+    fn set_bet_payout(
+        &self,
+        bet_id: &str,
+        payout: i64,
+    ) -> impl Future<Output = Result<(), DbError>>;
+    /// Claims a user's unclaimed winning bets on a market, returning them.
+    fn claim_bets(
+        &self,
+        market_id: &str,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Vec<Bet>, DbError>>;
+    fn set_claim_signature(
+        &self,
+        bet_ids: &[String],
+        signature: &str,
+    ) -> impl Future<Output = Result<(), DbError>>;
+    /// Makes claimed bets claimable again after their withdrawal failed.
+    fn release_claims(&self, bet_ids: &[String]) -> impl Future<Output = Result<(), DbError>>;
+}
+
+/// A set of repositories whose writes are applied together.
+pub trait UnitOfWork: UserRepo + IdeaRepo + RateRepo + TradeRepo {
+    fn commit(self) -> impl Future<Output = Result<(), DbError>>;
+}
+
+/// Where units of work come from: a pooled Postgres client, or a `MemoryStore` in tests.
+pub trait Store {
+    type Work<'a>: UnitOfWork + MarketRepo + OrderRepo
+    where
+        Self: 'a;
+
+    fn begin_work(&mut self) -> impl Future<Output = Result<Self::Work<'_>, DbError>>;
+}
+
+/// Hands out stores to services that outlive a request: the connection pool, or a shared
+/// `MemoryStore` in tests.
+pub trait Database {
+    type Conn: Store + IdeaRepo + OrderRepo;
+
+    fn connect(&self) -> impl Future<Output = Result<Self::Conn, DbError>>;
+}
+
+/// Records a trade with its ledger movement and the resulting rate.
+///
+/// Nothing is visible until the caller commits `uow`.
+pub async fn record_trade(
+    uow: &impl UnitOfWork,
+    trade: Transaction,
+    rate: Rate,
+    from_hold: i64,
+) -> Result<(Transaction, Rate), DbError> {
+    let trade = uow.insert_transaction(trade).await?;
+    uow.post_trade(&trade, from_hold).await?;
+    uow.queue_token_sync(&trade).await?;
+    let rate = uow.insert_rate(rate).await?;
+    Ok((trade, rate))
+}
+
+/// Anything that runs the repositories' queries on a Postgres client.
+pub trait PgBacked {
+    type Client: GenericClient;
+
+    fn client(&self) -> &Self::Client;
+}
+
+/// Repositories over a pooled client or an open transaction.
+pub struct PgRepo<'a, C>(&'a C);
+
+impl<'a, C: GenericClient> PgRepo<'a, C> {
+    pub fn new(client: &'a C) -> Self {
+        Self(client)
+    }
+}
+
+impl PgBacked for Client {
+    type Client = Client;
+
+    fn client(&self) -> &Client {
+        self
+    }
+}
+
+impl<C: GenericClient> PgBacked for PgRepo<'_, C> {
+    type Client = C;
+
+    fn client(&self) -> &C {
+        self.0
+    }
+}
+
+/// A database transaction; rolled back if dropped without `commit`.
+pub struct PgUnitOfWork<'a>(deadpool_postgres::Transaction<'a>);
+
+impl<'a> PgUnitOfWork<'a> {
+    pub async fn begin(client: &'a mut Client) -> Result<Self, DbError> {
+        Ok(Self(client.transaction().await?))
+    }
+}
+
+impl<'a> PgBacked for PgUnitOfWork<'a> {
+    type Client = deadpool_postgres::Transaction<'a>;
+
+    fn client(&self) -> &Self::Client {
+        &self.0
+    }
+}
+
+impl UnitOfWork for PgUnitOfWork<'_> {
+    async fn commit(self) -> Result<(), DbError> {
+        self.0.commit().await?;
+        Ok(())
+    }
+}
+
+impl Database for PgPool {
+    type Conn = Client;
+
+    async fn connect(&self) -> Result<Client, DbError> {
+        Ok(self.get().await?)
+    }
+}
+
+impl Store for Client {
+    type Work<'a> = PgUnitOfWork<'a>;
+
+    async fn begin_work(&mut self) -> Result<PgUnitOfWork<'_>, DbError> {
+        PgUnitOfWork::begin(self).await
+    }
+}
+
+impl<T: PgBacked> UserRepo for T {
+    async fn create_user(&self, user: User) -> Result<User, DbError> {
+        super::insert_user(self.client(), user).await
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, DbError> {
+        super::get_all_users(self.client()).await
+    }
+
+    async fn user_by_id(&self, id: &str) -> Result<User, DbError> {
+        super::get_user_by_id(self.client(), id).await
+    }
+
+    async fn user_by_wallet(&self, wallet_address: &str) -> Result<User, DbError> {
+        super::get_user_by_wallet(self.client(), wallet_address).await
+    }
+}
+
+impl<T: PgBacked> IdeaRepo for T {
+    async fn create_idea(&self, idea: Idea) -> Result<Idea, DbError> {
+        super::insert_idea(self.client(), idea).await
+    }
+
+    async fn idea_by_id(&self, id: &str) -> Result<Idea, DbError> {
+        super::get_idea(self.client(), id).await
+    }
+
+    async fn lock_idea(&self, id: &str) -> Result<Idea, DbError> {
+        super::lock_idea(self.client(), id).await
+    }
+
+    async fn transition_idea(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        outcome: Option<&str>,
+    ) -> Result<Idea, DbError> {
+        super::transition_idea_status(self.client(), id, from, to, outcome).await
+    }
+}
+
+impl<T: PgBacked> RateRepo for T {
+    async fn insert_rate(&self, rate: Rate) -> Result<Rate, DbError> {
+        super::insert_rate(self.client(), rate).await
+    }
+
+    async fn latest_rate(&self, idea_id: &str) -> Result<Option<Rate>, DbError> {
+        super::get_latest_rate(self.client(), idea_id).await
+    }
+}
+
+impl<T: PgBacked> TradeRepo for T {
+    async fn insert_transaction(&self, trade: Transaction) -> Result<Transaction, DbError> {
+        super::insert_transaction(self.client(), trade).await
+    }
+
+    async fn post_trade(&self, trade: &Transaction, from_hold: i64) -> Result<(), DbError> {
+        ledger::post_trade(self.client(), trade, from_hold).await?;
+        Ok(())
+    }
+
+    async fn queue_token_sync(&self, trade: &Transaction) -> Result<(), DbError> {
+        token_syncs::queue_trade(self.client(), trade).await
+    }
+}
+
+impl<T: PgBacked> MarketRepo for T {
+    async fn insert_market(
+        &self,
+        event_name: &str,
+        creator_id: &str,
+        mint: Option<&str>,
+        outcomes: &[String],
+    ) -> Result<Market, DbError> {
+        markets::insert_market(self.client(), event_name, creator_id, mint, outcomes).await
+    }
+
+    async fn market_by_id(&self, id: &str) -> Result<Market, DbError> {
+        markets::get_market(self.client(), id).await
+    }
+
+    async fn lock_market(&self, id: &str) -> Result<Market, DbError> {
+        markets::lock_market(self.client(), id).await
+    }
+
+    async fn transition_market(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        resolved_outcome: Option<i16>,
+    ) -> Result<(), DbError> {
+        markets::transition_market_status(self.client(), id, from, to, resolved_outcome).await
+    }
+
+    async fn insert_bet(&self, bet: Bet) -> Result<Bet, DbError> {
+        markets::insert_bet(self.client(), bet).await
+    }
+
+    async fn bet_signature_exists(&self, tx_signature: &str) -> Result<bool, DbError> {
+        markets::bet_signature_exists(self.client(), tx_signature).await
+    }
+
+    async fn market_bets(&self, market_id: &str) -> Result<Vec<Bet>, DbError> {
+        markets::get_market_bets(self.client(), market_id).await
+    }
+
+    async fn set_bet_payout(&self, bet_id: &str, payout: i64) -> Result<(), DbError> {
+        markets::set_bet_payout(self.client(), bet_id, payout).await
+    }
+
+    async fn claim_bets(&self, market_id: &str, user_id: &str) -> Result<Vec<Bet>, DbError> {
+        markets::claim_bets(self.client(), market_id, user_id).await
+    }
+
+    async fn set_claim_signature(
+        &self,
+        bet_ids: &[String],
+        signature: &str,
+    ) -> Result<(), DbError> {
+        markets::set_claim_signature(self.client(), bet_ids, signature).await
+    }
+
+    async fn release_claims(&self, bet_ids: &[String]) -> Result<(), DbError> {
+        markets::release_claims(self.client(), bet_ids).await
+    }
+}
+
+impl<T: PgBacked> OrderRepo for T {
+    async fn next_order_id(&self) -> Result<u64, DbError> {
+        super::next_order_id(self.client()).await
+    }
+
+    async fn user_holding(&self, idea_id: &str, user_id: &str) -> Result<Decimal, DbError> {
+        super::get_user_holding(self.client(), idea_id, user_id).await
+    }
+
+    async fn lock_available_holding(
+        &self,
+        idea_id: &str,
+        user_id: &str,
+    ) -> Result<Decimal, DbError> {
+        super::lock_user_holding(self.client(), idea_id, user_id).await?;
+        super::get_available_holding(self.client(), idea_id, user_id).await
+    }
+
+    async fn insert_order_hold(&self, hold: &OrderHold) -> Result<(), DbError> {
+        super::insert_order_hold(self.client(), hold).await
+    }
+
+    async fn lock_order_holds(&self, order_ids: &[u64]) -> Result<Vec<OrderHold>, DbError> {
+        super::lock_order_holds(self.client(), order_ids).await
+    }
+
+    async fn consume_order_hold(
+        &self,
+        order_id: u64,
+        cash: i64,
+        tokens: Decimal,
+    ) -> Result<(), DbError> {
+        super::consume_order_hold(self.client(), order_id, cash, tokens).await
+    }
+
+    async fn release_order_hold(&self, order_id: u64) -> Result<Option<OrderHold>, DbError> {
+        super::release_order_hold(self.client(), order_id).await
+    }
+
+    async fn instance_order_ids(&self, instance_id: &str) -> Result<Vec<u64>, DbError> {
+        super::get_instance_order_ids(self.client(), instance_id).await
+    }
+}
@@ -0,0 +1,289 @@
+use super::{idea_from_row, parse_timestamp, DbError, GenericClient, Idea, IDEA_COLUMNS};
+use crate::decimal::Decimal;
+use crate::validation;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use validator::Validate;
+
+pub const ACTION_CREATE: &str = "create";
+pub const ACTION_UPDATE: &str = "update";
+pub const ACTION_ARCHIVE: &str = "archive";
+
+// Idea fields recorded in revisions
+const TRACKED_FIELDS: &[&str] = &[
+    "title",
+    "description",
+    "category",
+    "initial_price",
+    "target_price",
+    "timeframe",
+    "risk_level",
+    "market_size",
+    "competitive_advantage",
+    "pricing_mode",
+    "resolver_id",
+    "token_mint",
+];
+
+/// One recorded change to an idea.
+#[derive(Debug, Clone, Serialize)]
+pub struct IdeaRevision {
+    pub id: i64,
+    pub idea_id: String,
+    pub editor_id: String,
+    pub action: String,
+    /// `{"field": {"from": old, "to": new}}` for every field the change touched.
+    pub changes: Value,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Fields the creator may edit until the idea's first trade.
+/// Given fields follow the same rules as on creation.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct IdeaChanges {
+    #[validate(custom(function = validation::not_blank), length(min = 3, max = 100))]
+    pub title: Option<String>,
+    #[validate(custom(function = validation::not_blank), length(min = 10, max = 1000))]
+    pub description: Option<String>,
+    #[validate(custom(function = validation::idea_category))]
+    pub category: Option<String>,
+    #[validate(custom(function = validation::positive))]
+    pub target_price: Option<Decimal>,
+    #[validate(custom(function = validation::idea_timeframe))]
+    pub timeframe: Option<String>,
+    #[validate(range(min = 1, max = 10))]
+    pub risk_level: Option<i32>,
+    #[validate(custom(function = validation::not_blank), length(max = 100))]
+    pub market_size: Option<String>,
+    #[validate(custom(function = validation::not_blank), length(min = 10, max = 1000))]
+    pub competitive_advantage: Option<String>,
+}
+
+impl IdeaChanges {
+    fn apply(self, idea: &Idea) -> Idea {
+        Idea {
+            title: self.title.unwrap_or_else(|| idea.title.clone()),
+            description: self.description.unwrap_or_else(|| idea.description.clone()),
+            category: self.category.unwrap_or_else(|| idea.category.clone()),
+            target_price: self.target_price.unwrap_or(idea.target_price),
+            timeframe: self.timeframe.unwrap_or_else(|| idea.timeframe.clone()),
+            risk_level: self.risk_level.unwrap_or(idea.risk_level),
+            market_size: self.market_size.unwrap_or_else(|| idea.market_size.clone()),
+            competitive_advantage: self
+                .competitive_advantage
+                .unwrap_or_else(|| idea.competitive_advantage.clone()),
+            ..idea.clone()
+        }
+    }
+}
+
+/// Tracked fields that differ between `before` and `after`; every tracked field for a new idea.
+fn diff(before: Option<&Idea>, after: &Idea) -> Value {
+    let before = before.map(|idea| json!(idea));
+    let after = json!(after);
+    let mut changes = Map::new();
+    for field in TRACKED_FIELDS {
+        let from = before.as_ref().map_or(Value::Null, |b| b[*field].clone());
+        let to = after[*field].clone();
+        if before.is_none() || from != to {
+            changes.insert(field.to_string(), json!({ "from": from, "to": to }));
+        }
+    }
+    Value::Object(changes)
+}
+
+pub async fn insert_revision(
+    client: &impl GenericClient,
+    idea_id: &str,
+    editor_id: &str,
+    action: &str,
+    changes: &Value,
+) -> Result<(), DbError> {
+    client
+        .execute(
+            "INSERT INTO idea_revisions (idea_id, editor_id, action, changes)
+             VALUES ($1, $2, $3, $4)",
+            &[&idea_id, &editor_id, &action, changes],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Records the creation of `idea` with every tracked field.
+pub async fn record_creation(client: &impl GenericClient, idea: &Idea) -> Result<(), DbError> {
+    let idea_id = idea
+        .id
+        .as_deref()
+        .ok_or_else(|| DbError::ValidationError("Idea has no ID".into()))?;
+    insert_revision(
+        client,
+        idea_id,
+        &idea.creator_id,
+        ACTION_CREATE,
+        &diff(None, idea),
+    )
+    .await
+}
+
+/// Locks an idea the user created, failing for other users and archived ideas.
+async fn lock_own_idea(
+    client: &impl GenericClient,
+    idea_id: &str,
+    user_id: &str,
+) -> Result<Idea, DbError> {
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM ideas WHERE id = $1 FOR UPDATE",
+                IDEA_COLUMNS
+            ),
+            &[&idea_id],
+        )
+        .await?
+        .ok_or_else(|| DbError::NotFound(format!("Idea with ID {} not found", idea_id)))?;
+    let idea = idea_from_row(&row);
+    if idea.creator_id != user_id {
+        return Err(DbError::Forbidden(format!(
+            "User {} may not change this idea",
+            user_id
+        )));
+    }
+    if idea.archived_at.is_some() {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} is archived",
+            idea_id
+        )));
+    }
+    Ok(idea)
+}
+
+async fn has_trades(client: &impl GenericClient, idea_id: &str) -> Result<bool, DbError> {
+    Ok(client
+        .query_one(
+            "SELECT EXISTS(SELECT 1 FROM transactions WHERE idea_id = $1) AS traded",
+            &[&idea_id],
+        )
+        .await?
+        .get("traded"))
+}
+
+/// Applies `changes` to an idea that has not been traded yet and records the revision.
+/// Call it inside a transaction so the update and its revision land together.
+pub async fn update_idea(
+    client: &impl GenericClient,
+    idea_id: &str,
+    editor_id: &str,
+    changes: IdeaChanges,
+) -> Result<Idea, DbError> {
+    let idea = lock_own_idea(client, idea_id, editor_id).await?;
+    if idea.status != "active" || has_trades(client, idea_id).await? {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} can no longer be edited once trading has started",
+            idea_id
+        )));
+    }
+
+    // Field rules are checked when the request is extracted; this needs the stored price
+    let updated = changes.apply(&idea);
+    if updated.target_price <= updated.initial_price {
+        return Err(DbError::ValidationError(
+            "Target price must be greater than initial price".into(),
+        ));
+    }
+
+    let changes = diff(Some(&idea), &updated);
+    if changes.as_object().is_some_and(Map::is_empty) {
+        return Ok(idea);
+    }
+
+    let row = client
+        .query_one(
+            &format!(
+                "UPDATE ideas
+                 SET title = $2, description = $3, category = $4, target_price = $5,
+                     timeframe = $6, risk_level = $7, market_size = $8, competitive_advantage = $9
+                 WHERE id = $1
+                 RETURNING {}",
+                IDEA_COLUMNS
+            ),
+            &[
+                &idea_id,
+                &updated.title,
+                &updated.description,
+                &updated.category,
+                &updated.target_price,
+                &updated.timeframe,
+                &updated.risk_level,
+                &updated.market_size,
+                &updated.competitive_advantage,
+            ],
+        )
+        .await?;
+    insert_revision(client, idea_id, editor_id, ACTION_UPDATE, &changes).await?;
+    Ok(idea_from_row(&row))
+}
+
+/// Soft-deletes an idea, hiding it from listings and trading. Only ideas that were never traded
+/// or are already settled can be archived, so no open position is stranded.
+pub async fn archive_idea(
+    client: &impl GenericClient,
+    idea_id: &str,
+    editor_id: &str,
+) -> Result<Idea, DbError> {
+    let idea = lock_own_idea(client, idea_id, editor_id).await?;
+    let settled = idea.status == "resolved" || idea.status == "voided";
+    if !settled && has_trades(client, idea_id).await? {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} has been traded; resolve or void it before archiving",
+            idea_id
+        )));
+    }
+
+    let row = client
+        .query_one(
+            &format!(
+                "UPDATE ideas SET archived_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING {}",
+                IDEA_COLUMNS
+            ),
+            &[&idea_id],
+        )
+        .await?;
+    let archived = idea_from_row(&row);
+    insert_revision(
+        client,
+        idea_id,
+        editor_id,
+        ACTION_ARCHIVE,
+        &json!({ "archived_at": { "from": null, "to": archived.archived_at } }),
+    )
+    .await?;
+    Ok(archived)
+}
+
+/// Every revision of an idea, oldest first.
+pub async fn get_idea_revisions(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<Vec<IdeaRevision>, DbError> {
+    let rows = client
+        .query(
+            "SELECT id, idea_id, editor_id, action, changes, created_at::text
+             FROM idea_revisions
+             WHERE idea_id = $1
+             ORDER BY id",
+            &[&idea_id],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| IdeaRevision {
+            id: row.get("id"),
+            idea_id: row.get("idea_id"),
+            editor_id: row.get("editor_id"),
+            action: row.get("action"),
+            changes: row.get("changes"),
+            created_at: parse_timestamp(row, "created_at"),
+        })
+        .collect())
+}
@@ -0,0 +1,133 @@
+use super::{parse_timestamp, DbError, GenericClient};
+use crate::decimal::Decimal;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+pub const STAKE_ACTIVE: &str = "active";
+pub const STAKE_UNSTAKED: &str = "unstaked";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stake {
+    pub id: String,
+    pub idea_id: String,
+    pub user_id: String,
+    /// Idea tokens locked.
+    pub amount: Decimal,
+    /// Largest reward the stake can earn, set aside from the rewards pool when it was made.
+    pub reserved_reward: Decimal,
+    pub lockup_days: i32,
+    /// Idea price when the stake was made.
+    pub entry_price: Decimal,
+    pub status: String,
+    /// Paid on unstake; `None` while the stake is active.
+    pub reward: Option<Decimal>,
+    pub staked_at: Option<DateTime<Utc>>,
+    pub unlock_at: Option<DateTime<Utc>>,
+    pub unstaked_at: Option<DateTime<Utc>>,
+}
+
+// Columns read by `stake_from_row`
+const STAKE_COLUMNS: &str =
+    "id, idea_id, user_id, amount, reserved_reward, lockup_days, entry_price, status, reward,
+    staked_at::text, unlock_at::text, unstaked_at::text";
+
+fn stake_from_row(row: &tokio_postgres::Row) -> Stake {
+    Stake {
+        id: row.get("id"),
+        idea_id: row.get("idea_id"),
+        user_id: row.get("user_id"),
+        amount: row.get("amount"),
+        reserved_reward: row.get("reserved_reward"),
+        lockup_days: row.get("lockup_days"),
+        entry_price: row.get("entry_price"),
+        status: row.get("status"),
+        reward: row.get("reward"),
+        staked_at: parse_timestamp(row, "staked_at"),
+        unlock_at: parse_timestamp(row, "unlock_at"),
+        unstaked_at: parse_timestamp(row, "unstaked_at"),
+    }
+}
+
+pub async fn insert_stake(
+    client: &impl GenericClient,
+    idea_id: &str,
+    user_id: &str,
+    amount: Decimal,
+    lockup_days: i32,
+    entry_price: Decimal,
+    reserved_reward: Decimal,
+) -> Result<Stake, DbError> {
+    let row = client
+        .query_one(
+            &format!(
+                "INSERT INTO stakes
+                     (idea_id, user_id, amount, lockup_days, entry_price, reserved_reward,
+                      unlock_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP + make_interval(days => $4))
+                 RETURNING {}",
+                STAKE_COLUMNS
+            ),
+            &[
+                &idea_id,
+                &user_id,
+                &amount,
+                &lockup_days,
+                &entry_price,
+                &reserved_reward,
+            ],
+        )
+        .await?;
+    Ok(stake_from_row(&row))
+}
+
+/// Locks a stake until the surrounding transaction ends.
+pub async fn lock_stake(client: &impl GenericClient, stake_id: &str) -> Result<Stake, DbError> {
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM stakes WHERE id = $1 FOR UPDATE",
+                STAKE_COLUMNS
+            ),
+            &[&stake_id],
+        )
+        .await?
+        .ok_or_else(|| DbError::NotFound(format!("Stake with ID {} not found", stake_id)))?;
+    Ok(stake_from_row(&row))
+}
+
+pub async fn complete_unstake(
+    client: &impl GenericClient,
+    stake_id: &str,
+    reward: Decimal,
+) -> Result<Stake, DbError> {
+    let row = client
+        .query_opt(
+            &format!(
+                "UPDATE stakes SET status = $3, reward = $2, unstaked_at = CURRENT_TIMESTAMP
+                 WHERE id = $1 AND status = $4
+                 RETURNING {}",
+                STAKE_COLUMNS
+            ),
+            &[&stake_id, &reward, &STAKE_UNSTAKED, &STAKE_ACTIVE],
+        )
+        .await?
+        .ok_or_else(|| DbError::ValidationError(format!("Stake {} is not active", stake_id)))?;
+    Ok(stake_from_row(&row))
+}
+
+/// Every stake on an idea, newest first.
+pub async fn get_idea_stakes(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<Vec<Stake>, DbError> {
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM stakes WHERE idea_id = $1 ORDER BY staked_at DESC, id",
+                STAKE_COLUMNS
+            ),
+            &[&idea_id],
+        )
+        .await?;
+    Ok(rows.iter().map(stake_from_row).collect())
+}
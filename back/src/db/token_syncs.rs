@@ -0,0 +1,113 @@
+use super::{DbError, GenericClient, Transaction, AMM_ACCOUNT_ID};
+
+/// Longest wait between retries of a failing sync, in seconds.
+const MAX_RETRY_DELAY_SECS: f64 = 3600.0;
+
+/// A holding waiting to be mirrored on-chain.
+#[derive(Debug, Clone)]
+pub struct TokenSync {
+    pub idea_id: String,
+    pub user_id: String,
+    pub mint: String,
+    /// Tells a sync that finished apart from one queued again while it ran.
+    pub generation: i64,
+    pub attempts: i32,
+}
+
+/// Queues the trade's parties, other than the AMM, if the idea has a mint. Runs in the
+/// transaction recording the trade, so a committed trade is always mirrored eventually.
+pub async fn queue_trade(client: &impl GenericClient, trade: &Transaction) -> Result<(), DbError> {
+    client
+        .execute(
+            "INSERT INTO token_syncs (idea_id, user_id)
+             SELECT i.id, party
+             FROM ideas i, unnest(ARRAY[$2, $3]::text[]) AS party
+             WHERE i.id = $1 AND i.token_mint IS NOT NULL AND party <> $4
+             ON CONFLICT (idea_id, user_id) DO UPDATE
+             SET generation = token_syncs.generation + 1,
+                 attempts = 0,
+                 next_attempt_at = CURRENT_TIMESTAMP",
+            &[
+                &trade.idea_id,
+                &trade.buyer_id,
+                &trade.seller_id,
+                &AMM_ACCOUNT_ID,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Takes up to `limit` due syncs, holding each off for `lease_secs` so another instance does
+/// not run it at the same time.
+pub async fn claim_due(
+    client: &impl GenericClient,
+    limit: i64,
+    lease_secs: f64,
+) -> Result<Vec<TokenSync>, DbError> {
+    let rows = client
+        .query(
+            "UPDATE token_syncs s
+             SET next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $2::float8),
+                 attempts = s.attempts + 1
+             FROM ideas i
+             WHERE i.id = s.idea_id
+               AND (s.idea_id, s.user_id) IN (
+                   SELECT idea_id, user_id FROM token_syncs
+                   WHERE next_attempt_at <= CURRENT_TIMESTAMP
+                   ORDER BY next_attempt_at
+                   LIMIT $1
+                   FOR UPDATE SKIP LOCKED)
+             RETURNING s.idea_id, s.user_id, i.token_mint, s.generation, s.attempts",
+            &[&limit, &lease_secs],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            Some(TokenSync {
+                idea_id: row.get("idea_id"),
+                user_id: row.get("user_id"),
+                mint: row.get::<_, Option<String>>("token_mint")?,
+                generation: row.get("generation"),
+                attempts: row.get("attempts"),
+            })
+        })
+        .collect())
+}
+
+/// Removes a finished sync unless a newer trade queued it again in the meantime.
+pub async fn complete(client: &impl GenericClient, sync: &TokenSync) -> Result<(), DbError> {
+    client
+        .execute(
+            "DELETE FROM token_syncs WHERE idea_id = $1 AND user_id = $2 AND generation = $3",
+            &[&sync.idea_id, &sync.user_id, &sync.generation],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Records why a sync failed and schedules its retry, backing off exponentially.
+pub async fn fail(
+    client: &impl GenericClient,
+    sync: &TokenSync,
+    error: &str,
+) -> Result<(), DbError> {
+    let delay = 2f64.powi(sync.attempts.min(12)).min(MAX_RETRY_DELAY_SECS);
+    client
+        .execute(
+            "UPDATE token_syncs
+             SET last_error = $4,
+                 next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $5::float8)
+             WHERE idea_id = $1 AND user_id = $2 AND generation = $3",
+            &[
+                &sync.idea_id,
+                &sync.user_id,
+                &sync.generation,
+                &error,
+                &delay,
+            ],
+        )
+        .await?;
+    Ok(())
+}
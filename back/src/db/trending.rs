@@ -0,0 +1,94 @@
+use super::{DbError, GenericClient, AMM_ACCOUNT_ID};
+use crate::decimal::Decimal;
+
+/// Recent activity of one tradable idea, the inputs of its trending score.
+#[derive(Debug, Clone)]
+pub struct TrendingSignals {
+    pub idea_id: String,
+    pub title: String,
+    pub category: String,
+    pub current_price: Decimal,
+    /// Price at the start of the window, or the initial price for younger ideas.
+    pub window_start_price: Decimal,
+    /// Cash traded in the window, each trade weighted by its decay.
+    pub decayed_volume: f64,
+    /// Distinct buyers in the window, each weighted by the decay of their latest buy.
+    pub decayed_buyers: f64,
+    /// Up votes minus down votes cast in the window, each weighted by its decay.
+    pub decayed_votes: f64,
+}
+
+/// Signals of every active, unarchived idea over the last `window_secs`. A trade's weight
+/// halves every `half_life_secs`.
+pub async fn get_trending_signals(
+    client: &impl GenericClient,
+    window_secs: f64,
+    half_life_secs: f64,
+) -> Result<Vec<TrendingSignals>, DbError> {
+    let rows = client
+        .query(
+            "WITH recent AS (
+                 SELECT idea_id, buyer_id, total_value,
+                        exp(-ln(2.0) * extract(epoch FROM CURRENT_TIMESTAMP - created_at)::float8
+                            / $2::float8)
+                            AS weight
+                 FROM transactions
+                 WHERE status = 'completed'
+                   AND created_at > CURRENT_TIMESTAMP - make_interval(secs => $1::float8)
+             ),
+             volume AS (
+                 SELECT idea_id, SUM(total_value::float8 * weight) AS decayed_volume
+                 FROM recent
+                 GROUP BY idea_id
+             ),
+             buyers AS (
+                 SELECT idea_id, SUM(weight) AS decayed_buyers
+                 FROM (
+                     SELECT idea_id, buyer_id, MAX(weight) AS weight
+                     FROM recent
+                     WHERE buyer_id <> $3
+                     GROUP BY idea_id, buyer_id
+                 ) latest
+                 GROUP BY idea_id
+             ),
+             recent_votes AS (
+                 SELECT idea_id,
+                        SUM(direction * exp(-ln(2.0)
+                            * extract(epoch FROM CURRENT_TIMESTAMP - created_at)::float8
+                            / $2::float8)) AS decayed_votes
+                 FROM votes
+                 WHERE created_at > CURRENT_TIMESTAMP - make_interval(secs => $1::float8)
+                 GROUP BY idea_id
+             )
+             SELECT i.id, i.title, i.category,
+                    COALESCE((SELECT rate FROM rates WHERE idea_id = i.id
+                              ORDER BY created_at DESC LIMIT 1), i.initial_price) AS current_price,
+                    COALESCE((SELECT rate FROM rates WHERE idea_id = i.id
+                                AND created_at <= CURRENT_TIMESTAMP - make_interval(secs => $1::float8)
+                              ORDER BY created_at DESC LIMIT 1), i.initial_price) AS window_start_price,
+                    COALESCE(v.decayed_volume, 0) AS decayed_volume,
+                    COALESCE(b.decayed_buyers, 0) AS decayed_buyers,
+                    COALESCE(rv.decayed_votes, 0) AS decayed_votes
+             FROM ideas i
+             LEFT JOIN volume v ON v.idea_id = i.id
+             LEFT JOIN buyers b ON b.idea_id = i.id
+             LEFT JOIN recent_votes rv ON rv.idea_id = i.id
+             WHERE i.status = 'active' AND i.archived_at IS NULL",
+            &[&window_secs, &half_life_secs, &AMM_ACCOUNT_ID],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| TrendingSignals {
+            idea_id: row.get("id"),
+            title: row.get("title"),
+            category: row.get("category"),
+            current_price: row.get("current_price"),
+            window_start_price: row.get("window_start_price"),
+            decayed_volume: row.get("decayed_volume"),
+            decayed_buyers: row.get("decayed_buyers"),
+            decayed_votes: row.get("decayed_votes"),
+        })
+        .collect())
+}
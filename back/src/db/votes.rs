@@ -0,0 +1,131 @@
+use super::{get_idea, parse_timestamp, DbError, GenericClient};
+use crate::decimal::Decimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteDirection {
+    Up,
+    Down,
+}
+
+impl VoteDirection {
+    // Stored as 1 for up and -1 for down
+    fn to_sql(self) -> i16 {
+        match self {
+            VoteDirection::Up => 1,
+            VoteDirection::Down => -1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Vote {
+    pub id: Option<String>,
+    pub idea_id: String,
+    pub user_id: String,
+    pub direction: VoteDirection,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Votes on an idea, counted one per user and weighted by the tokens each voter holds now.
+#[derive(Debug, Clone, Serialize)]
+pub struct VoteTally {
+    pub idea_id: String,
+    pub up_votes: i64,
+    pub down_votes: i64,
+    /// Up votes minus down votes.
+    pub score: i64,
+    pub up_weight: Decimal,
+    pub down_weight: Decimal,
+    /// Up weight minus down weight.
+    pub weighted_score: Decimal,
+}
+
+/// Records a user's only vote on an open idea. Its weight is not fixed here but taken from the
+/// voter's holding whenever votes are tallied, so tokens moved after voting never count twice.
+pub async fn cast_vote(
+    client: &impl GenericClient,
+    idea_id: &str,
+    user_id: &str,
+    direction: VoteDirection,
+) -> Result<Vote, DbError> {
+    let idea = get_idea(client, idea_id).await?;
+    if !idea.is_tradable() {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} is closed to voting",
+            idea_id
+        )));
+    }
+    let row = client
+        .query_opt(
+            "INSERT INTO votes (idea_id, user_id, direction)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (idea_id, user_id) DO NOTHING
+             RETURNING id, created_at::text",
+            &[&idea_id, &user_id, &direction.to_sql()],
+        )
+        .await?
+        .ok_or_else(|| {
+            DbError::Conflict(format!(
+                "User {} has already voted on idea {}",
+                user_id, idea_id
+            ))
+        })?;
+
+    Ok(Vote {
+        id: Some(row.get("id")),
+        idea_id: idea_id.to_string(),
+        user_id: user_id.to_string(),
+        direction,
+        created_at: parse_timestamp(&row, "created_at"),
+    })
+}
+
+pub async fn get_vote_tally(
+    client: &impl GenericClient,
+    idea_id: &str,
+) -> Result<VoteTally, DbError> {
+    let row = client
+        .query_one(
+            "WITH holdings AS (
+                 SELECT user_id, SUM(amount) AS amount
+                 FROM (
+                     SELECT buyer_id AS user_id, amount
+                     FROM transactions WHERE idea_id = $1 AND status = 'completed'
+                     UNION ALL
+                     SELECT seller_id, -amount
+                     FROM transactions WHERE idea_id = $1 AND status = 'completed'
+                 ) legs
+                 GROUP BY user_id
+             ), weighted AS (
+                 -- Voters without a positive holding count with no weight
+                 SELECT v.direction, GREATEST(COALESCE(h.amount, 0), 0) AS weight
+                 FROM votes v
+                 LEFT JOIN holdings h ON h.user_id = v.user_id
+                 WHERE v.idea_id = $1
+             )
+             SELECT COUNT(*) FILTER (WHERE direction > 0) AS up_votes,
+                    COUNT(*) FILTER (WHERE direction < 0) AS down_votes,
+                    COALESCE(SUM(weight) FILTER (WHERE direction > 0), 0) AS up_weight,
+                    COALESCE(SUM(weight) FILTER (WHERE direction < 0), 0) AS down_weight
+             FROM weighted",
+            &[&idea_id],
+        )
+        .await?;
+
+    let up_votes: i64 = row.get("up_votes");
+    let down_votes: i64 = row.get("down_votes");
+    let up_weight: Decimal = row.get("up_weight");
+    let down_weight: Decimal = row.get("down_weight");
+    Ok(VoteTally {
+        idea_id: idea_id.to_string(),
+        up_votes,
+        down_votes,
+        score: up_votes - down_votes,
+        up_weight,
+        down_weight,
+        weighted_score: up_weight - down_weight,
+    })
+}
@@ -0,0 +1,421 @@
+//! Fixed-point quantities for prices, token amounts and cash values.
+//!
+//! Every value carries at most [`SCALE`] decimal places, matching the `NUMERIC(20, 8)` columns it
+//! is stored in. Addition and subtraction are exact; multiplication, division and conversions
+//! take an explicit [`Rounding`] so every place that loses precision says which way it goes.
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::RoundingStrategy;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+/// Decimal places kept by every [`Decimal`].
+pub const SCALE: u32 = 8;
+
+/// Integer digits accepted from clients, the rest of `NUMERIC(20, 8)`. Products of two such
+/// values always fit, so multiplication cannot overflow.
+const MAX_INTEGER_DIGITS: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// To the nearest value, ties to even (banker's rounding).
+    HalfEven,
+    /// Toward zero.
+    Down,
+    /// Away from zero.
+    Up,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::Down => RoundingStrategy::ToZero,
+            Rounding::Up => RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(rust_decimal::Decimal);
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal(rust_decimal::Decimal::ZERO);
+
+    /// `num * 10^-scale`, e.g. `Decimal::new(15, 1)` is 1.5. `scale` must not exceed [`SCALE`].
+    pub const fn new(num: i64, scale: u32) -> Self {
+        Decimal(rust_decimal::Decimal::from_parts(
+            num.unsigned_abs() as u32,
+            (num.unsigned_abs() >> 32) as u32,
+            0,
+            num < 0,
+            scale,
+        ))
+    }
+
+    fn from_inner(value: rust_decimal::Decimal, rounding: Rounding) -> Self {
+        Decimal(value.round_dp_with_strategy(SCALE, rounding.strategy()))
+    }
+
+    /// Rejects values a `NUMERIC(20, 8)` column cannot hold.
+    fn bounded(self) -> Option<Self> {
+        let limit = rust_decimal::Decimal::from(10i64.pow(MAX_INTEGER_DIGITS));
+        (self.0.abs() < limit).then_some(self)
+    }
+
+    /// Converts a float through its shortest decimal representation, so `0.1` becomes exactly
+    /// 0.1. Fails for NaN, infinities and values out of range.
+    pub fn from_f64(value: f64, rounding: Rounding) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        // `Display` for floats never uses exponents, and prints the shortest round-trip digits
+        rust_decimal::Decimal::from_str(&value.to_string())
+            .ok()
+            .and_then(|d| Self::from_inner(d, rounding).bounded())
+    }
+
+    /// Nearest float, for ratios and display only.
+    pub fn to_f64(self) -> f64 {
+        self.0.to_f64().unwrap_or(0.0)
+    }
+
+    /// Converts to smallest units with `decimals` places. `None` if it does not fit an `i64`.
+    pub fn to_units(self, decimals: u32, rounding: Rounding) -> Option<i64> {
+        let factor = rust_decimal::Decimal::from_i128_with_scale(10i128.checked_pow(decimals)?, 0);
+        self.0
+            .checked_mul(factor)?
+            .round_dp_with_strategy(0, rounding.strategy())
+            .to_i64()
+    }
+
+    /// Value of `units` smallest units with `decimals` places, the inverse of
+    /// [`Decimal::to_units`]. `None` if it does not fit a `NUMERIC(20, 8)` column.
+    pub fn from_units(units: i64, decimals: u32, rounding: Rounding) -> Option<Self> {
+        rust_decimal::Decimal::try_from_i128_with_scale(units as i128, decimals)
+            .ok()
+            .and_then(|d| Self::from_inner(d, rounding).bounded())
+    }
+
+    /// `self * rhs` rounded to [`SCALE`] places.
+    pub fn mul_rounded(self, rhs: Decimal, rounding: Rounding) -> Self {
+        Self::from_inner(self.0 * rhs.0, rounding)
+    }
+
+    /// `self / rhs` rounded to [`SCALE`] places; `None` when `rhs` is zero.
+    pub fn div_rounded(self, rhs: Decimal, rounding: Rounding) -> Option<Self> {
+        self.0
+            .checked_div(rhs.0)
+            .map(|d| Self::from_inner(d, rounding))
+    }
+
+    pub fn abs(self) -> Self {
+        Decimal(self.0.abs())
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Strictly greater than zero.
+    pub fn is_positive(self) -> bool {
+        self > Self::ZERO
+    }
+
+    /// Strictly less than zero.
+    pub fn is_negative(self) -> bool {
+        self < Self::ZERO
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Decimal(rust_decimal::Decimal::from(value))
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, rhs: Decimal) -> Decimal {
+        Decimal(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Decimal {
+    fn add_assign(&mut self, rhs: Decimal) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, rhs: Decimal) -> Decimal {
+        Decimal(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Decimal {
+    fn sub_assign(&mut self, rhs: Decimal) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal(-self.0)
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::ZERO, Add::add)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0.normalize(), f)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = rust_decimal::Error;
+
+    /// Parses plain or scientific notation, rounding half-even to [`SCALE`] places.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let value = rust_decimal::Decimal::from_str(s)
+            .or_else(|_| rust_decimal::Decimal::from_scientific(s))?;
+        Self::from_inner(value, Rounding::HalfEven)
+            .bounded()
+            .ok_or(rust_decimal::Error::ExceedsMaximumPossibleValue)
+    }
+}
+
+/// Serialized as a decimal string, e.g. `"1.5"`, so no digit is lost to a float on either side.
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Accepts JSON numbers and decimal strings; strings keep every digit.
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl de::Visitor<'_> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal number or string")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+                Decimal::from(v)
+                    .bounded()
+                    .ok_or_else(|| E::custom("number is out of range"))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+                i64::try_from(v)
+                    .ok()
+                    .and_then(|v| Decimal::from(v).bounded())
+                    .ok_or_else(|| E::custom("number is out of range"))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+                Decimal::from_f64(v, Rounding::HalfEven)
+                    .ok_or_else(|| E::custom("number must be finite and in range"))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+impl ToSql for Decimal {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut bytes::BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        self.0.to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <rust_decimal::Decimal as ToSql>::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+/// Reads `NUMERIC` values, rounding aggregates such as `AVG` half-even to [`SCALE`] places.
+impl<'a> FromSql<'a> for Decimal {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        rust_decimal::Decimal::from_sql(ty, raw).map(|d| Self::from_inner(d, Rounding::HalfEven))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <rust_decimal::Decimal as FromSql>::accepts(ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn products_round_to_scale() {
+        let half = dec("0.5");
+        let tiny = dec("0.00000001");
+        assert_eq!(tiny.mul_rounded(half, Rounding::Down), Decimal::ZERO);
+        assert_eq!(tiny.mul_rounded(half, Rounding::Up), tiny);
+        // 0.000000005 is a tie and goes to the even neighbour
+        assert_eq!(tiny.mul_rounded(half, Rounding::HalfEven), Decimal::ZERO);
+        assert_eq!(
+            dec("0.00000003").mul_rounded(half, Rounding::HalfEven),
+            dec("0.00000002")
+        );
+        // Down and Up are toward and away from zero, also for negatives
+        assert_eq!(
+            dec("-0.00000003").mul_rounded(half, Rounding::Down),
+            dec("-0.00000001")
+        );
+        assert_eq!(
+            dec("-0.00000003").mul_rounded(half, Rounding::Up),
+            dec("-0.00000002")
+        );
+    }
+
+    #[test]
+    fn quotients_round_to_scale() {
+        let one = Decimal::from(1);
+        let three = Decimal::from(3);
+        assert_eq!(
+            one.div_rounded(three, Rounding::Down),
+            Some(dec("0.33333333"))
+        );
+        assert_eq!(
+            one.div_rounded(three, Rounding::Up),
+            Some(dec("0.33333334"))
+        );
+        assert_eq!(
+            Decimal::from(2).div_rounded(three, Rounding::HalfEven),
+            Some(dec("0.66666667"))
+        );
+        assert_eq!(one.div_rounded(Decimal::ZERO, Rounding::Down), None);
+    }
+
+    #[test]
+    fn parsing_rounds_and_rejects_what_numeric_cannot_hold() {
+        assert_eq!(dec(" 2.5 "), Decimal::new(25, 1));
+        assert_eq!(dec("1.123456785"), dec("1.12345678"));
+        assert_eq!(dec("1.5e3"), Decimal::from(1_500));
+        assert_eq!(
+            dec("999999999999.99999999").to_string(),
+            "999999999999.99999999"
+        );
+        assert!("1000000000000".parse::<Decimal>().is_err());
+        assert!("-1000000000000".parse::<Decimal>().is_err());
+        assert!("1e12".parse::<Decimal>().is_err());
+        assert!("one".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn units_round_trip() {
+        for value in ["0", "1.234567", "-42.5", "999999.999999"] {
+            let units = dec(value).to_units(6, Rounding::Down).unwrap();
+            assert_eq!(
+                Decimal::from_units(units, 6, Rounding::Down),
+                Some(dec(value))
+            );
+        }
+        assert_eq!(
+            dec("1.2345675").to_units(6, Rounding::Down),
+            Some(1_234_567)
+        );
+        assert_eq!(dec("1.2345675").to_units(6, Rounding::Up), Some(1_234_568));
+        // Lamports have more places than SCALE keeps
+        assert_eq!(
+            Decimal::from_units(1_500_000_001, 9, Rounding::Down),
+            Some(dec("1.5"))
+        );
+        assert_eq!(
+            Decimal::from_units(1_500_000_001, 9, Rounding::Up),
+            Some(dec("1.50000001"))
+        );
+    }
+
+    #[test]
+    fn units_out_of_range_are_refused() {
+        let largest = dec("999999999999.99999999");
+        assert_eq!(largest.to_units(9, Rounding::Down), None);
+        assert_eq!(
+            largest.to_units(6, Rounding::Down),
+            Some(999_999_999_999_999_999)
+        );
+        assert_eq!(Decimal::from_units(i64::MAX, 6, Rounding::Down), None);
+        assert_eq!(Decimal::from_units(1, 29, Rounding::Down), None);
+    }
+
+    #[test]
+    fn serializes_as_a_string() {
+        assert_eq!(serde_json::to_string(&dec("1.50")).unwrap(), "\"1.5\"");
+        assert_eq!(serde_json::to_string(&Decimal::from(-3)).unwrap(), "\"-3\"");
+    }
+
+    #[test]
+    fn deserializes_strings_and_numbers() {
+        let parse = |json: &str| serde_json::from_str::<Decimal>(json);
+        assert_eq!(parse("\"0.1\"").unwrap(), dec("0.1"));
+        assert_eq!(parse("\"0.123456789012\"").unwrap(), dec("0.12345679"));
+        assert_eq!(parse("0.1").unwrap(), dec("0.1"));
+        assert_eq!(parse("3").unwrap(), Decimal::from(3));
+        assert_eq!(parse("-3").unwrap(), Decimal::from(-3));
+        assert!(parse("1e13").is_err());
+        assert!(parse("18446744073709551615").is_err());
+        assert!(parse("\"abc\"").is_err());
+        assert!(parse("null").is_err());
+    }
+
+    #[test]
+    fn reads_numeric_rounded_to_scale() {
+        let mut raw = bytes::BytesMut::new();
+        rust_decimal::Decimal::from_str("1.123456789")
+            .unwrap()
+            .to_sql(&Type::NUMERIC, &mut raw)
+            .unwrap();
+        assert_eq!(
+            Decimal::from_sql(&Type::NUMERIC, &raw).unwrap(),
+            dec("1.12345679")
+        );
+
+        let mut raw = bytes::BytesMut::new();
+        dec("-0.5").to_sql(&Type::NUMERIC, &mut raw).unwrap();
+        assert_eq!(
+            Decimal::from_sql(&Type::NUMERIC, &raw).unwrap(),
+            dec("-0.5")
+        );
+        assert!(!<Decimal as FromSql>::accepts(&Type::FLOAT8));
+    }
+}
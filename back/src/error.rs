@@ -0,0 +1,258 @@
+use crate::db::DbError;
+use crate::services::solana_service::SolanaServiceError;
+use crate::utils;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+/// Header carrying the request ID, accepted from clients and echoed on every response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Machine-readable error codes. Clients should branch on these; messages may change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The body, query or path could not be parsed.
+    InvalidRequest,
+    /// The request parsed but its values were rejected; see `details`.
+    ValidationFailed,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    /// The request duplicates something that already exists, e.g. a taken username, or refers
+    /// to something that no longer does.
+    Conflict,
+    /// An on-chain transaction has not confirmed yet; retry the same request later.
+    Unconfirmed,
+    PayloadTooLarge,
+    /// The database or a downstream service is unavailable; retry later.
+    ServiceUnavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest | ErrorCode::ValidationFailed => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict | ErrorCode::Unconfirmed => StatusCode::CONFLICT,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Why a single request field was rejected.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Error returned by every handler, rendered as
+/// `{"error": {"code", "message", "details", "request_id"}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Vec<FieldError>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: ErrorCode,
+    message: &'a str,
+    #[serde(skip_serializing_if = "<[FieldError]>::is_empty")]
+    details: &'a [FieldError],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    /// A `validation_failed` error listing every rejected field.
+    pub fn invalid_fields(details: Vec<FieldError>) -> Self {
+        Self {
+            code: ErrorCode::ValidationFailed,
+            message: "Request validation failed".into(),
+            details,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        match e {
+            DbError::ValidationError(e) => ApiError::new(ErrorCode::ValidationFailed, e),
+            DbError::NotFound(e) => ApiError::new(ErrorCode::NotFound, e),
+            DbError::Forbidden(e) => ApiError::new(ErrorCode::Forbidden, e),
+            DbError::Conflict(e) => ApiError::new(ErrorCode::Conflict, e),
+            DbError::Unconfirmed(e) => ApiError::new(ErrorCode::Unconfirmed, e),
+            // Includes an exhausted pool timing out
+            DbError::ConnectionError(e) => {
+                utils::log(&format!(
+                    "[{}] Database unavailable: {}",
+                    request_id_or_dash(),
+                    e
+                ));
+                ApiError::new(
+                    ErrorCode::ServiceUnavailable,
+                    "Database is unavailable, try again later",
+                )
+            }
+            // Query details stay in the logs
+            DbError::QueryError(e) => {
+                utils::log(&format!("[{}] Query failed: {}", request_id_or_dash(), e));
+                ApiError::new(ErrorCode::Internal, "Internal server error")
+            }
+        }
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for ApiError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        DbError::from(e).into()
+    }
+}
+
+impl From<SolanaServiceError> for ApiError {
+    fn from(e: SolanaServiceError) -> Self {
+        match e {
+            SolanaServiceError::InvalidData(e) => ApiError::new(ErrorCode::ValidationFailed, e),
+            SolanaServiceError::Unconfirmed(e) => ApiError::new(ErrorCode::Unconfirmed, e),
+            e => ApiError::new(ErrorCode::ServiceUnavailable, e.to_string()),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+                details: &self.details,
+                request_id: current_request_id(),
+            },
+        })
+    }
+}
+
+/// Renders `e` for handlers that build their `HttpResponse` by hand.
+pub fn error_response(e: impl Into<ApiError>) -> HttpResponse {
+    e.into().error_response()
+}
+
+/// ID of the request being handled, if running inside [`assign_request_id`].
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+fn request_id_or_dash() -> String {
+    current_request_id().unwrap_or_else(|| "-".into())
+}
+
+// Client IDs are echoed into headers and logs, so only short, plain ones are kept
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Tags each request with the client's `X-Request-Id` or a fresh one, makes it available to
+/// error responses and returns it in the `X-Request-Id` response header.
+pub async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid_request_id(id))
+        .map(String::from)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+
+    let mut res = REQUEST_ID.scope(request_id.clone(), next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(res)
+}
+
+/// Error handler for `web::JsonConfig`.
+pub fn json_error(e: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    let code = match e {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            ErrorCode::PayloadTooLarge
+        }
+        _ => ErrorCode::InvalidRequest,
+    };
+    ApiError::new(code, format!("Invalid JSON body: {}", e)).into()
+}
+
+/// Error handler for `web::QueryConfig`.
+pub fn query_error(e: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::new(ErrorCode::InvalidRequest, format!("Invalid query: {}", e)).into()
+}
+
+/// Error handler for `web::PathConfig`.
+pub fn path_error(e: PathError, _: &HttpRequest) -> actix_web::Error {
+    ApiError::new(ErrorCode::InvalidRequest, format!("Invalid path: {}", e)).into()
+}
+
+/// Fallback for requests no route matched.
+pub async fn not_found(req: HttpRequest) -> HttpResponse {
+    error_response(ApiError::new(
+        ErrorCode::NotFound,
+        format!("No route for {} {}", req.method(), req.path()),
+    ))
+}
@@ -16,7 +16,7 @@ use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
@@ -193,7 +193,7 @@ impl SolanaThread {
 }
 
 /// Gets the persistent keypair, creating it if it doesn't exist
-fn get_persistent_keypair() -> Result<Keypair, String> {
+pub fn get_persistent_keypair() -> Result<Keypair, String> {
     let keypair_path = Path::new(KEYPAIR_PATH);
 
     if keypair_path.exists() {
@@ -225,6 +225,14 @@ fn get_persistent_keypair() -> Result<Keypair, String> {
     }
 }
 
+/// Backend keypair shared by the services that sign on-chain, loaded on first use
+pub fn backend_keypair() -> Result<Arc<Keypair>, String> {
+    static BACKEND_KEYPAIR: OnceLock<Result<Arc<Keypair>, String>> = OnceLock::new();
+    BACKEND_KEYPAIR
+        .get_or_init(|| get_persistent_keypair().map(Arc::new))
+        .clone()
+}
+
 /// Ensures the account has enough SOL, requesting airdrop if needed
 async fn ensure_account_funded(rpc_client: &RpcClient, pubkey: &Pubkey) -> Result<(), String> {
     // Check current balance
@@ -8,10 +8,13 @@ use tokio::signal;
 mod config;
 mod controllers;
 mod db;
+mod decimal;
+mod error;
 mod handlers;
 mod routes;
 mod services;
 mod utils;
+mod validation;
 // Remove the actix_web::main attribute and implement our own main
 fn main() -> std::io::Result<()> {
     // Create a multi-threaded runtime
@@ -21,8 +24,35 @@ fn main() -> std::io::Result<()> {
         .build()
         .expect("Failed to create Tokio runtime");
 
-    // Run the async main function in the runtime
-    runtime.block_on(async_main())
+    // `idea_market migrate` only applies pending migrations
+    match std::env::args().nth(1).as_deref() {
+        None => runtime.block_on(async_main()),
+        Some("migrate") => runtime.block_on(migrate_main()),
+        Some(command) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unknown command: {} (expected `migrate`)", command),
+        )),
+    }
+}
+
+async fn migrate_main() -> std::io::Result<()> {
+    let pool = db::create_pool().map_err(|e| {
+        utils::log(&format!("Pool creation failed: {}", e));
+        std::io::Error::other("Pool creation failed")
+    })?;
+    migrate(&pool).await
+}
+
+async fn migrate(pool: &db::PgPool) -> std::io::Result<()> {
+    match db::migrate::run_migrations(pool).await {
+        Ok(versions) if versions.is_empty() => utils::log("[Migrations] Schema is up to date"),
+        Ok(versions) => utils::log(&format!("[Migrations] Applied {:?}", versions)),
+        Err(e) => {
+            utils::log(&format!("Migrations failed: {}", e));
+            return Err(std::io::Error::other("Migrations failed"));
+        }
+    }
+    Ok(())
 }
 
 async fn async_main() -> std::io::Result<()> {
@@ -35,8 +65,8 @@ async fn async_main() -> std::io::Result<()> {
 
     utils::log(&format!("[CORS] Allowed origins: {:?}", cors_origins));
 
-    // Checking for DB connection
-    match db::get_pool() {
+    // One pool shared by every worker, checked before serving
+    let pool = match db::create_pool() {
         Ok(pool) => {
             // Try to get a client and ping the DB
             match pool.get().await {
@@ -44,32 +74,28 @@ async fn async_main() -> std::io::Result<()> {
                     // Optionally, run a simple query to verify
                     if let Err(e) = client.simple_query("SELECT 1").await {
                         utils::log(&format!("DB ping failed: {}", e));
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            "DB ping failed",
-                        ));
+                        return Err(std::io::Error::other("DB ping failed"));
                     }
                     utils::log("[DB connected]");
                     pool
                 }
                 Err(e) => {
                     utils::log(&format!("Pool connection failed: {}", e));
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Pool connection failed",
-                    ));
+                    return Err(std::io::Error::other("Pool connection failed"));
                 }
             }
         }
         Err(e) => {
             utils::log(&format!("Pool creation failed: {}", e));
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Pool creation failed",
-            ));
+            return Err(std::io::Error::other("Pool creation failed"));
         }
     };
 
+    // Bring the schema up to date unless AUTO_MIGRATE=false, e.g. when `migrate` runs separately
+    if config.auto_migrate {
+        migrate(&pool).await?;
+    }
+
     // let solana_thread = match start_solana_thread().await {
     //     Ok(thread) => {
     //         utils::log("[Solana thread started successfully]");
@@ -87,6 +113,63 @@ async fn async_main() -> std::io::Result<()> {
     // // Clone the Arc for the server
     // let solana_thread_clone = solana_thread.clone();
 
+    // Committed trades, rates and book changes, relayed to WebSocket subscribers
+    let events = services::events::EventBus::new(services::events::EVENT_BUS_CAPACITY);
+    let ws_sessions = web::Data::new(routes::ws::WsSessions::new());
+
+    // Order books live in-process and are shared by every worker
+    let order_books = web::Data::new(services::order_book::OrderBookService::new(
+        pool.clone(),
+        events.clone(),
+        config.instance_id.clone(),
+    ));
+
+    // Session tokens are signed with JWT_SECRET; without it they only survive this process, which
+    // production cannot accept
+    let jwt_secret = match &config.jwt_secret {
+        Some(secret) => secret.clone().into_bytes(),
+        None if config.is_production() => {
+            utils::log("JWT_SECRET must be set in production");
+            return Err(std::io::Error::other("JWT_SECRET is not set"));
+        }
+        None => {
+            utils::info("JWT_SECRET is not set, using a random secret for this run");
+            rand::random::<[u8; 32]>().to_vec()
+        }
+    };
+    let auth = web::Data::new(services::auth::AuthService::new(
+        pool.clone(),
+        &jwt_secret,
+    ));
+
+    // Builds and confirms escrow deposits for market bets and pays out winnings
+    let solana = web::Data::new(services::solana_service::SolanaService::new());
+
+    // Mints idea tokens and mirrors settled trades on-chain
+    let tokens = web::Data::new(services::token::TokenService::new(pool.clone()));
+    tokens.start_syncer();
+
+    // Order books start empty, so whatever this instance's orders held before goes back to users
+    match order_books.release_stale_holds().await {
+        Ok(0) => {}
+        Ok(released) => utils::log(&format!("[Ledger] Released {} stale hold(s)", released)),
+        Err(e) => utils::log(&format!("[Ledger] Failed to release stale holds: {}", e)),
+    }
+
+    // Balances, withdrawals and the deposit reconciler
+    let ledger = web::Data::new(services::ledger::LedgerService::new(pool.clone()));
+    ledger.start_reconciler();
+
+    let trending = web::Data::new(services::trending::TrendingService::new(pool.clone()));
+    trending.start_refresher();
+
+    // Rates and trades from Postgres notifications, for the SSE streams
+    let idea_streams = web::Data::new(services::idea_stream::IdeaStreamService::new());
+    idea_streams.start_listener();
+
+    let db_pool = web::Data::new(pool);
+    let events = web::Data::new(events);
+
     // Start the HTTP server
     let server = HttpServer::new(move || {
         // Configure CORS
@@ -98,16 +181,65 @@ async fn async_main() -> std::io::Result<()> {
             .max_age(3600);
 
         App::new()
+            .app_data(db_pool.clone())
+            .app_data(order_books.clone())
+            .app_data(auth.clone())
+            .app_data(solana.clone())
+            .app_data(tokens.clone())
+            .app_data(trending.clone())
+            .app_data(ledger.clone())
+            .app_data(events.clone())
+            .app_data(ws_sessions.clone())
+            .app_data(idea_streams.clone())
+            .app_data(web::JsonConfig::default().error_handler(error::json_error))
+            .app_data(web::QueryConfig::default().error_handler(error::query_error))
+            .app_data(web::PathConfig::default().error_handler(error::path_error))
+            .wrap(middleware::from_fn(routes::auth::require_session))
+            .wrap(middleware::from_fn(error::assign_request_id))
             .wrap(cors)
             .wrap(middleware::Logger::default())
             .service(controllers::health_controller::health_check)
             .service(controllers::health_controller::index)
-            .service(routes::ideas::create_user)
-            .service(routes::ideas::list_users)
-            .service(routes::ideas::get_user_by_wallet_address)
+            .service(routes::auth::create_challenge)
+            .service(routes::auth::verify_signature)
+            .service(routes::users::create_user)
+            .service(routes::users::list_users)
+            .service(routes::users::get_user_by_wallet_address)
+            .service(routes::users::get_user)
+            .service(routes::users::update_user)
+            .service(routes::users::delete_user)
+            .service(routes::users::get_user_transactions)
+            .service(routes::users::get_user_holdings)
             .service(routes::staking::create_idea)
-            .service(routes::staking::get_all_ideas)
-        // .configure(controllers::market_controller::config)
+            .service(routes::ideas::list_ideas)
+            .service(routes::ideas::get_trending_ideas)
+            .service(routes::staking::quote_idea)
+            .service(routes::staking::trade_idea)
+            .service(routes::staking::stake_on_idea)
+            .service(routes::staking::unstake)
+            .service(routes::staking::get_stakes)
+            .service(routes::staking::fund_staking_rewards)
+            .service(routes::staking::get_staking_rewards)
+            .service(routes::ideas::update_idea)
+            .service(routes::ideas::archive_idea)
+            .service(routes::ideas::get_idea_history)
+            .service(routes::ideas::get_idea_candles)
+            .service(routes::ideas::stream_idea)
+            .service(routes::ideas::get_idea)
+            .service(routes::votes::vote_on_idea)
+            .service(routes::votes::get_votes)
+            .service(routes::orders::place_order)
+            .service(routes::orders::cancel_order)
+            .service(routes::orders::get_order_book)
+            .service(routes::resolution::close_idea)
+            .service(routes::resolution::resolve_idea)
+            .service(routes::ledger::get_balances)
+            .service(routes::ledger::get_deposit_addresses)
+            .service(routes::ledger::get_entries)
+            .service(routes::ledger::withdraw)
+            .service(routes::ws::connect)
+            .configure(controllers::market_controller::config)
+            .default_service(web::to(error::not_found))
     })
     .bind((config.server_host.as_str(), config.server_port))?
     .shutdown_timeout(30) // Set shutdown timeout to 30 seconds
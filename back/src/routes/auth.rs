@@ -0,0 +1,98 @@
+use crate::db::DbError;
+use crate::error::{self, ApiError, ErrorCode};
+use crate::services::auth::AuthService;
+use crate::utils;
+use crate::validation::{self, Valid};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{post, web, HttpMessage, HttpResponse, Responder, ResponseError};
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct ChallengeRequest {
+    #[validate(custom(function = validation::pubkey))]
+    pub wallet_address: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct VerifyRequest {
+    #[validate(custom(function = validation::pubkey))]
+    pub wallet_address: String,
+    /// Base58-encoded ed25519 signature of the challenge message.
+    #[validate(custom(function = validation::not_blank))]
+    pub signature: String,
+}
+
+// Failed sign-ins are unauthenticated rather than forbidden
+fn error_response(e: DbError) -> HttpResponse {
+    match e {
+        DbError::Forbidden(e) => ApiError::new(ErrorCode::Unauthorized, e).error_response(),
+        e => error::error_response(e),
+    }
+}
+
+#[post("/auth/challenge")]
+pub async fn create_challenge(
+    payload: Valid<web::Json<ChallengeRequest>>,
+    auth: web::Data<AuthService>,
+) -> impl Responder {
+    utils::route_log("POST", "/auth/challenge", Some(&payload.wallet_address));
+    match auth.challenge(&payload.wallet_address).await {
+        Ok(challenge) => HttpResponse::Created().json(challenge),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/auth/verify")]
+pub async fn verify_signature(
+    payload: Valid<web::Json<VerifyRequest>>,
+    auth: web::Data<AuthService>,
+) -> impl Responder {
+    utils::route_log("POST", "/auth/verify", Some(&payload.wallet_address));
+    match auth
+        .verify(&payload.wallet_address, &payload.signature)
+        .await
+    {
+        Ok(token) => HttpResponse::Ok().json(token),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Requires a valid `Authorization: Bearer` session on every mutating request outside `/auth/`
+/// and attaches the resulting `Session` to the request.
+pub async fn require_session(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let mutating = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if mutating && !req.path().starts_with("/auth/") {
+        let session = req
+            .app_data::<web::Data<AuthService>>()
+            .zip(
+                req.headers()
+                    .get(header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer ")),
+            )
+            .and_then(|(auth, token)| auth.session(token.trim()));
+
+        match session {
+            Some(session) => {
+                req.extensions_mut().insert(session);
+            }
+            None => {
+                let response =
+                    ApiError::new(ErrorCode::Unauthorized, "Missing or invalid session token")
+                        .error_response();
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
@@ -1,106 +1,371 @@
-use crate::db::User;
-use crate::db::{self, DbError};
+use crate::db::candles::{self, Interval, MAX_CANDLES};
+use crate::db::listing::{self, IdeaFilter};
+use crate::db::repo::{IdeaRepo, PgBacked, PgRepo, PgUnitOfWork, RateRepo, UnitOfWork, UserRepo};
+use crate::db::revisions::{self, IdeaChanges};
+use crate::db::{self, DbError, PgPool};
+use crate::decimal::Decimal;
+use crate::error::error_response;
+use crate::services::auth::Session;
+use crate::services::idea_stream::IdeaStreamService;
+use crate::services::order_book::OrderBookService;
+use crate::services::trending::{TrendingService, MAX_TRENDING};
 use crate::utils;
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use crate::validation::{self, Valid};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{delete, get, patch, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use validator::{Validate, ValidationError};
 
-#[derive(Deserialize)]
-pub struct CreateUserRequest {
-    pub wallet_address: String,
-    pub username: String,
-}
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
 
-#[derive(Deserialize)]
-pub struct CreateIdeaRequest {
-    pub title: String,
-    pub description: String,
-    pub image_url: String,
-    pub category: String,
-    pub initial_price: f64,
-    pub target_price: f64,
-    pub timeframe: String,
-    pub risk_level: i32,
-    pub market_size: String,
-    pub competitive_advantage: String,
+#[derive(Deserialize, Validate)]
+pub struct CursorQuery {
+    #[validate(length(max = 512))]
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
+    pub limit: Option<i64>,
 }
 
-#[post("/ideas")]
-pub async fn create_idea(payload: web::Json<CreateIdeaRequest>) -> impl Responder {
-    let idea = db::Idea::new(
-        payload.title.clone(),
-        payload.description.clone(),
-        payload.image_url.clone(),
-        payload.category.clone(),
-        payload.initial_price,
-        payload.target_price,
-        payload.timeframe.clone(),
-        payload.risk_level,
-        payload.market_size.clone(),
-        payload.competitive_advantage.clone(),
-    );
-    match db::createIdea(idea).await {
-        Ok(idea) => HttpResponse::Created().json(web::Json(idea)),
-        Err(DbError::ConnectionError(e)) => HttpResponse::ServiceUnavailable().json(e),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+/// Filters, sort and the cursor are all read from the query string.
+#[get("/ideas")]
+pub async fn list_ideas(
+    filter: Valid<web::Query<IdeaFilter>>,
+    page: Valid<web::Query<CursorQuery>>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log("GET", "/ideas", None);
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match listing::list_ideas(&client, &filter, page.cursor.as_deref(), limit).await {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(e) => error_response(e),
     }
 }
 
-#[get("/ideas")]
-pub async fn list_ideas() -> impl Responder {
-    HttpResponse::Ok().json(vec!["Mock Idea 1", "Mock Idea 2"])
+#[derive(Deserialize, Validate)]
+pub struct TrendingQuery {
+    #[validate(range(min = 1, max = MAX_TRENDING))]
+    pub limit: Option<usize>,
+}
+
+#[get("/ideas/trending")]
+pub async fn get_trending_ideas(
+    query: Valid<web::Query<TrendingQuery>>,
+    trending: web::Data<TrendingService>,
+) -> impl Responder {
+    utils::route_log("GET", "/ideas/trending", None);
+    let limit = query.limit.unwrap_or(10);
+    HttpResponse::Ok().json(trending.top(limit))
+}
+
+/// An idea with everything its detail view shows.
+#[derive(Serialize)]
+pub struct IdeaDetail {
+    #[serde(flatten)]
+    pub idea: db::Idea,
+    pub creator: db::User,
+    pub latest_rate: Option<db::Rate>,
+    /// Latest traded rate, or the initial price before the first trade.
+    pub current_price: Decimal,
+    /// Price movement since 24 hours ago, measured from the initial price for younger ideas.
+    pub change_24h: Decimal,
+    pub change_24h_percentage: f64,
+    pub volume_24h: Decimal,
+    pub trades_24h: i64,
+    pub holders: i64,
+    pub stats: serde_json::Value,
 }
 
 #[get("/ideas/{id}")]
-pub async fn get_idea(id: web::Path<u32>) -> impl Responder {
-    HttpResponse::Ok().json(format!("Mock idea {}", id))
+pub async fn get_idea(id: web::Path<String>, pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}", Some(&id));
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let repo = PgRepo::new(&client);
+    let result = async {
+        let idea = repo.idea_by_id(&id).await?;
+        let creator = repo.user_by_id(&idea.creator_id).await?;
+        let latest_rate = repo.latest_rate(&id).await?;
+        let summary = db::get_market_summary(&client, &id).await?;
+        let stats = db::get_idea_stats(&client, &id).await?;
+
+        let current_price = latest_rate
+            .as_ref()
+            .map_or(idea.initial_price, db::Rate::rate);
+        let reference = summary.price_24h_ago.unwrap_or(idea.initial_price);
+        let change_24h = current_price - reference;
+        let change_24h_percentage = if reference.is_zero() {
+            0.0
+        } else {
+            change_24h.to_f64() / reference.to_f64() * 100.0
+        };
+        Ok::<_, DbError>(IdeaDetail {
+            idea,
+            creator,
+            latest_rate,
+            current_price,
+            change_24h,
+            change_24h_percentage,
+            volume_24h: summary.volume_24h,
+            trades_24h: summary.trades_24h,
+            holders: summary.holders,
+            stats,
+        })
+    };
+    match result.await {
+        Ok(detail) => HttpResponse::Ok().json(detail),
+        Err(e) => error_response(e),
+    }
 }
 
-#[post("/user")]
-async fn create_user(payload: web::Json<CreateUserRequest>) -> impl Responder {
+#[patch("/ideas/{id}")]
+pub async fn update_idea(
+    id: web::Path<String>,
+    payload: Valid<web::Json<IdeaChanges>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
     utils::route_log(
-        "POST",
-        "/user",
-        Some(&format!("wallet: {}", payload.wallet_address)),
+        "PATCH",
+        "/ideas/{id}",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
     );
-    let user = User::new(payload.username.clone(), payload.wallet_address.clone());
-    match db::createUser(user).await {
-        Ok(user) => HttpResponse::Created().json(web::Json(user)),
-        Err(DbError::ValidationError(e)) => HttpResponse::BadRequest().json(e),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let result = async {
+        let uow = PgUnitOfWork::begin(&mut client).await?;
+        let idea = revisions::update_idea(
+            uow.client(),
+            &id,
+            &user_id,
+            payload.into_inner().into_inner(),
+        )
+        .await?;
+        uow.commit().await?;
+        Ok::<_, DbError>(idea)
+    };
+    match result.await {
+        Ok(idea) => HttpResponse::Ok().json(idea),
+        Err(e) => error_response(e),
     }
 }
 
-#[get("/users")]
-pub async fn list_users() -> impl Responder {
-    utils::route_log("GET", "/users", None);
-    match db::getAllUsers().await {
-        Ok(users) => HttpResponse::Ok().json(web::Json(users)),
-        Err(DbError::ConnectionError(e)) => HttpResponse::ServiceUnavailable().json(e),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+/// Archives the idea instead of deleting it, so its trades and history stay intact.
+#[delete("/ideas/{id}")]
+pub async fn archive_idea(
+    id: web::Path<String>,
+    pool: web::Data<PgPool>,
+    order_books: web::Data<OrderBookService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "DELETE",
+        "/ideas/{id}",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let result = async {
+        let uow = PgUnitOfWork::begin(&mut client).await?;
+        let idea = revisions::archive_idea(uow.client(), &id, &user_id).await?;
+        uow.commit().await?;
+        Ok::<_, DbError>(idea)
+    };
+    match result.await {
+        Ok(idea) => {
+            // Archived ideas cannot trade, so their resting orders give back what they hold
+            order_books.clear(&id).await;
+            HttpResponse::Ok().json(idea)
+        }
+        Err(e) => error_response(e),
     }
 }
 
-#[get("/user/{wallet_address}")]
-pub async fn get_user_by_wallet_address(wallet_address: web::Path<String>) -> impl Responder {
-    utils::route_log("GET", "/user/{wallet_address}", Some(&wallet_address));
-    match db::getUserByWalletAddress(wallet_address.into_inner()).await {
-        Ok(user) => HttpResponse::Ok().json(web::Json(user)),
-        Err(DbError::NotFound(e)) => HttpResponse::NotFound().json(e),
-        Err(DbError::ValidationError(e)) => HttpResponse::BadRequest().json(e),
-        Err(DbError::ConnectionError(e)) => HttpResponse::ServiceUnavailable().json(e),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+#[get("/ideas/{id}/history")]
+pub async fn get_idea_history(id: web::Path<String>, pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/history", Some(&id));
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    // Archived ideas keep their history, so look the idea up directly
+    if let Err(e) = client.idea_by_id(&id).await {
+        return error_response(e);
+    }
+    match revisions::get_idea_revisions(&client, &id).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => error_response(e),
     }
 }
 
-// #[put("/ideas/{id}")]
-// pub async fn update_idea(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock idea {} updated", id))
-// }
+/// Candles returned when the request gives no `from`.
+const DEFAULT_CANDLES: i32 = 200;
 
-// #[delete("/ideas/{id}")]
-// pub async fn delete_idea(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock idea {} deleted", id))
-// }
+#[derive(Deserialize, Validate)]
+#[validate(schema(function = candle_range_fits, skip_on_field_errors = false))]
+pub struct CandlesQuery {
+    pub interval: Option<Interval>,
+    /// RFC 3339 start of the range, `DEFAULT_CANDLES` intervals before `to` by default.
+    pub from: Option<DateTime<Utc>>,
+    /// RFC 3339 end of the range, now by default.
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl CandlesQuery {
+    fn interval(&self) -> Interval {
+        self.interval.unwrap_or(Interval::OneHour)
+    }
+
+    /// The requested range with its defaults filled in, or `None` when `to` is too early to
+    /// start the default range before it.
+    fn range(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = match self.from {
+            Some(from) => from,
+            None => to.checked_sub_signed(self.interval().duration() * DEFAULT_CANDLES)?,
+        };
+        Some((from, to))
+    }
+}
+
+fn candle_range_fits(query: &CandlesQuery) -> Result<(), ValidationError> {
+    let Some((from, to)) = query.range() else {
+        return Err(validation::field_error(
+            "to",
+            "Too early to start the default range before it",
+        ));
+    };
+    if from >= to {
+        return Err(validation::field_error("from", "Must be earlier than `to`"));
+    }
+    if (to - from).num_seconds() / query.interval().duration().num_seconds() > MAX_CANDLES {
+        return Err(validation::field_error(
+            "from",
+            "Range spans more candles than can be requested at once",
+        ));
+    }
+    Ok(())
+}
+
+#[get("/ideas/{id}/candles")]
+pub async fn get_idea_candles(
+    id: web::Path<String>,
+    query: Valid<web::Query<CandlesQuery>>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/candles", Some(&id));
+    let interval = query.interval();
+    let (from, to) = query.range().expect("range checked by validation");
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = client.idea_by_id(&id).await {
+        return error_response(e);
+    }
+    match candles::get_candles(&client, &id, interval, from, to).await {
+        Ok(candles) => HttpResponse::Ok().json(candles),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Comment sent on idle streams so proxies keep the connection open.
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// How long clients wait before reconnecting a dropped stream, in milliseconds.
+const STREAM_RETRY_MS: u64 = 3000;
+
+fn sse_event(event: &str, data: &impl Serialize) -> Bytes {
+    Bytes::from(format!(
+        "event: {}\ndata: {}\n\n",
+        event,
+        serde_json::to_string(data).unwrap_or_default()
+    ))
+}
+
+/// Server-sent events with the idea's `rate` updates and completed `trade`s, starting with the
+/// current rate. A `lagged` event means updates were skipped and state should be refetched.
+#[get("/ideas/{id}/stream")]
+pub async fn stream_idea(
+    id: web::Path<String>,
+    pool: web::Data<PgPool>,
+    streams: web::Data<IdeaStreamService>,
+) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/stream", Some(&id));
+    // Subscribed before reading the current rate so no update falls in between
+    let receiver = streams.subscribe();
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = client.idea_by_id(&id).await {
+        return error_response(e);
+    }
+    let mut opening = vec![Bytes::from(format!("retry: {}\n\n", STREAM_RETRY_MS))];
+    match PgRepo::new(&client).latest_rate(&id).await {
+        Ok(Some(rate)) => opening.push(sse_event("rate", &rate)),
+        Ok(None) => {}
+        Err(e) => return error_response(e),
+    }
+
+    let keep_alive = tokio::time::interval_at(
+        tokio::time::Instant::now() + STREAM_KEEP_ALIVE,
+        STREAM_KEEP_ALIVE,
+    );
+    let updates = stream::unfold(
+        (receiver, keep_alive, id.into_inner()),
+        |(mut receiver, mut keep_alive, idea_id)| async move {
+            let chunk = loop {
+                tokio::select! {
+                    _ = keep_alive.tick() => break Bytes::from_static(b": keep-alive\n\n"),
+                    event = receiver.recv() => match event {
+                        Ok(event) if event.idea_id == idea_id => {
+                            break sse_event(&event.event, &event.data)
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(missed)) => {
+                            break sse_event("lagged", &json!({ "missed": missed }))
+                        }
+                        Err(RecvError::Closed) => return None,
+                    },
+                }
+            };
+            Some((Ok(chunk), (receiver, keep_alive, idea_id)))
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Stops nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(stream::iter(opening.into_iter().map(Ok::<_, actix_web::Error>)).chain(updates))
+}
 
 // pub fn config(cfg: &mut web::ServiceConfig) {
 //     cfg.service(list_ideas)
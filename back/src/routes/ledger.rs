@@ -0,0 +1,125 @@
+use crate::db::ledger::Currency;
+use crate::db::PgPool;
+use crate::decimal::{Decimal, Rounding};
+use crate::error::{error_response, ApiError, FieldError};
+use crate::services::auth::Session;
+use crate::services::ledger::LedgerService;
+use crate::utils;
+use crate::validation::{self, Valid};
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct EntriesQuery {
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct WithdrawRequest {
+    pub currency: Currency,
+    /// Whole units, e.g. 1.5 USDC.
+    #[validate(custom(function = validation::positive))]
+    pub amount: Decimal,
+    /// Defaults to the session wallet.
+    #[validate(custom(function = validation::pubkey))]
+    pub destination: Option<String>,
+}
+
+#[get("/ledger/balances")]
+pub async fn get_balances(
+    pool: web::Data<PgPool>,
+    ledger: web::Data<LedgerService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("GET", "/ledger/balances", Some(&session.wallet_address));
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match ledger.balances(&user_id).await {
+        Ok(balances) => HttpResponse::Ok().json(balances),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/ledger/deposit-addresses")]
+pub async fn get_deposit_addresses(
+    ledger: web::Data<LedgerService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "GET",
+        "/ledger/deposit-addresses",
+        Some(&session.wallet_address),
+    );
+    match ledger.deposit_addresses() {
+        Ok(addresses) => HttpResponse::Ok().json(addresses),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/ledger/entries")]
+pub async fn get_entries(
+    query: Valid<web::Query<EntriesQuery>>,
+    pool: web::Data<PgPool>,
+    ledger: web::Data<LedgerService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("GET", "/ledger/entries", Some(&session.wallet_address));
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let limit = query.limit.unwrap_or(50);
+    match ledger.entries(&user_id, limit).await {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/ledger/withdrawals")]
+pub async fn withdraw(
+    payload: Valid<web::Json<WithdrawRequest>>,
+    pool: web::Data<PgPool>,
+    ledger: web::Data<LedgerService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ledger/withdrawals",
+        Some(&format!(
+            "wallet: {}, {} {}",
+            session.wallet_address,
+            payload.amount,
+            payload.currency.as_str()
+        )),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let units = match payload.currency.to_units(payload.amount, Rounding::Down) {
+        Ok(units) if units > 0 => units,
+        // Positive, but below the currency's smallest unit
+        Ok(_) => {
+            return error_response(ApiError::invalid_fields(vec![FieldError::new(
+                "amount",
+                "Must be at least one base unit of the currency",
+            )]))
+        }
+        Err(e) => return error_response(e),
+    };
+    let destination = payload
+        .destination
+        .as_deref()
+        .unwrap_or(&session.wallet_address);
+    match ledger
+        .withdraw(&user_id, payload.currency, units as u64, destination)
+        .await
+    {
+        Ok(withdrawal) => HttpResponse::Created().json(withdrawal),
+        Err(e) => error_response(e),
+    }
+}
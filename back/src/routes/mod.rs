@@ -1,3 +1,9 @@
+pub mod auth;
 pub mod ideas;
+pub mod ledger;
+pub mod orders;
+pub mod resolution;
 pub mod staking;
+pub mod users;
 pub mod votes;
+pub mod ws;
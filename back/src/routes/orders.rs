@@ -0,0 +1,82 @@
+use crate::db::PgPool;
+use crate::decimal::Decimal;
+use crate::error::error_response;
+use crate::services::auth::Session;
+use crate::services::order_book::{OrderBookService, Side};
+use crate::services::token::TokenService;
+use crate::utils;
+use crate::validation::{self, Valid};
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use validator::Validate;
+
+#[derive(Deserialize, Validate)]
+pub struct PlaceOrderRequest {
+    pub side: Side,
+    #[validate(custom(function = validation::positive))]
+    pub price: Decimal,
+    #[validate(custom(function = validation::positive))]
+    pub amount: Decimal,
+}
+
+#[post("/ideas/{id}/orders")]
+pub async fn place_order(
+    id: web::Path<String>,
+    payload: Valid<web::Json<PlaceOrderRequest>>,
+    pool: web::Data<PgPool>,
+    order_books: web::Data<OrderBookService>,
+    tokens: web::Data<TokenService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ideas/{id}/orders",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match order_books
+        .place_order(&id, &user_id, payload.side, payload.price, payload.amount)
+        .await
+    {
+        Ok(placed) => {
+            if !placed.trades.is_empty() {
+                tokens.notify_trades();
+            }
+            HttpResponse::Created().json(placed)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+#[delete("/orders/{id}")]
+pub async fn cancel_order(
+    id: web::Path<u64>,
+    pool: web::Data<PgPool>,
+    order_books: web::Data<OrderBookService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("DELETE", "/orders/{id}", Some(&id.to_string()));
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match order_books.cancel_order(id.into_inner(), &user_id).await {
+        Ok(order) => HttpResponse::Ok().json(order),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/ideas/{id}/orderbook")]
+pub async fn get_order_book(
+    id: web::Path<String>,
+    order_books: web::Data<OrderBookService>,
+) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/orderbook", Some(&id));
+    match order_books.snapshot(&id).await {
+        Ok(snapshot) => HttpResponse::Ok().json(snapshot),
+        Err(e) => error_response(e),
+    }
+}
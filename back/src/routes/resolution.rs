@@ -0,0 +1,96 @@
+use crate::db::PgPool;
+use crate::decimal::Decimal;
+use crate::error::error_response;
+use crate::services::auth::Session;
+use crate::services::order_book::OrderBookService;
+use crate::services::resolution::{self, Resolution};
+use crate::utils;
+use crate::validation::{self, Valid};
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use validator::{Validate, ValidationError};
+
+/// Outcome label that voids the market instead of settling it.
+const VOID_OUTCOME: &str = "void";
+
+#[derive(Deserialize, Validate)]
+#[validate(schema(function = settlement_price_given, skip_on_field_errors = false))]
+pub struct ResolveIdeaRequest {
+    #[validate(custom(function = validation::not_blank), length(max = 100))]
+    pub outcome: String,
+    #[validate(custom(function = validation::non_negative))]
+    pub settlement_price: Option<Decimal>,
+}
+
+impl ResolveIdeaRequest {
+    fn is_void(&self) -> bool {
+        self.outcome.eq_ignore_ascii_case(VOID_OUTCOME)
+    }
+}
+
+fn settlement_price_given(request: &ResolveIdeaRequest) -> Result<(), ValidationError> {
+    if request.is_void() || request.settlement_price.is_some() {
+        Ok(())
+    } else {
+        Err(validation::field_error(
+            "settlement_price",
+            "Required unless the outcome is void",
+        ))
+    }
+}
+
+#[post("/ideas/{id}/close")]
+pub async fn close_idea(
+    id: web::Path<String>,
+    pool: web::Data<PgPool>,
+    order_books: web::Data<OrderBookService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ideas/{id}/close",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match resolution::close_idea(&pool, &id, &user_id).await {
+        Ok(idea) => {
+            order_books.clear(&id).await;
+            HttpResponse::Ok().json(idea)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/ideas/{id}/resolve")]
+pub async fn resolve_idea(
+    id: web::Path<String>,
+    payload: Valid<web::Json<ResolveIdeaRequest>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ideas/{id}/resolve",
+        Some(&format!("idea: {}, outcome: {}", id, payload.outcome)),
+    );
+    let resolution = match payload.settlement_price {
+        Some(settlement_price) if !payload.is_void() => Resolution::Resolved {
+            outcome: payload.outcome.clone(),
+            settlement_price,
+        },
+        // Validation only lets the price be missing for void outcomes
+        _ => Resolution::Voided,
+    };
+
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match resolution::resolve_idea(&pool, &id, &user_id, resolution).await {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(e) => error_response(e),
+    }
+}
@@ -1,42 +1,181 @@
 use actix_web::{get, post, web, HttpResponse, Responder};
 
-use crate::db::{self, Idea};
+use crate::db::repo::{IdeaRepo, Store, UnitOfWork};
+use crate::db::{self, DbError, PgPool};
+use crate::decimal::Decimal;
+use crate::error::error_response;
+use crate::services::amm;
+use crate::services::auth::Session;
+use crate::services::events::EventBus;
+use crate::services::order_book::Side;
+use crate::services::staking;
+use crate::services::token::TokenService;
+use crate::utils;
+use crate::validation::{self, Valid};
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use validator::{Validate, ValidationError};
 
-// #[post("/ideas/{id}/stake")]
-// pub async fn stake_on_idea(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock stake on idea {}", id))
-// }
+#[derive(serde::Deserialize, Validate)]
+struct StakeRequest {
+    /// Idea tokens to lock.
+    #[validate(custom(function = validation::positive))]
+    amount: Decimal,
+    /// One of `staking::LOCKUP_TIERS`.
+    #[validate(custom(function = validation::lockup_tier))]
+    lockup_days: i32,
+}
+
+#[post("/ideas/{id}/stake")]
+pub async fn stake_on_idea(
+    id: web::Path<String>,
+    payload: Valid<web::Json<StakeRequest>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ideas/{id}/stake",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match staking::stake(&pool, &id, &user_id, payload.amount, payload.lockup_days).await {
+        Ok(stake) => HttpResponse::Created().json(stake),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/stakes/{id}/unstake")]
+pub async fn unstake(
+    id: web::Path<String>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/stakes/{id}/unstake",
+        Some(&format!(
+            "stake: {}, wallet: {}",
+            id, session.wallet_address
+        )),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match staking::unstake(&pool, &id, &user_id).await {
+        Ok(stake) => HttpResponse::Ok().json(stake),
+        Err(e) => error_response(e),
+    }
+}
 
-// #[get("/ideas/{id}/stakes")]
-// pub async fn get_stakes(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock stakes for idea {}", id))
-// }
+#[get("/ideas/{id}/stakes")]
+pub async fn get_stakes(id: web::Path<String>, pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/stakes", Some(&id));
+    match staking::idea_stakes(&pool, &id).await {
+        Ok(stakes) => HttpResponse::Ok().json(stakes),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(serde::Deserialize, Validate)]
+struct FundRewardsRequest {
+    /// Cash to add to the rewards pool.
+    #[validate(custom(function = validation::positive))]
+    amount: Decimal,
+}
+
+#[post("/staking/rewards")]
+pub async fn fund_staking_rewards(
+    payload: Valid<web::Json<FundRewardsRequest>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/staking/rewards",
+        Some(&format!("wallet: {}", session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match staking::fund_rewards(&pool, &user_id, payload.amount).await {
+        Ok(entry) => HttpResponse::Created().json(entry),
+        Err(e) => error_response(e),
+    }
+}
 
-// pub fn config(cfg: &mut web::ServiceConfig) {
-//     cfg.service(stake_on_idea).service(get_stakes);
-// }
+#[get("/staking/rewards")]
+pub async fn get_staking_rewards(pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/staking/rewards", None);
+    match staking::rewards_pool(&pool).await {
+        Ok(available) => HttpResponse::Ok().json(serde_json::json!({ "available": available })),
+        Err(e) => error_response(e),
+    }
+}
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Validate)]
+#[validate(schema(function = target_above_initial, skip_on_field_errors = false))]
 struct CreateIdeaRequest {
+    #[validate(custom(function = validation::not_blank), length(min = 3, max = 100))]
     title: String,
+    #[validate(custom(function = validation::not_blank), length(min = 10, max = 1000))]
     description: String,
-    creator_id: String,
+    #[validate(custom(function = validation::idea_category))]
     category: String,
-    initial_price: f64,
-    target_price: f64,
+    #[validate(custom(function = validation::positive))]
+    initial_price: Decimal,
+    target_price: Decimal,
+    #[validate(custom(function = validation::idea_timeframe))]
     timeframe: String,
+    #[validate(range(min = 1, max = 10))]
     risk_level: i32,
+    #[validate(custom(function = validation::not_blank), length(max = 100))]
     market_size: String,
+    #[validate(custom(function = validation::not_blank), length(min = 10, max = 1000))]
     competitive_advantage: String,
+    #[serde(default)]
+    #[validate(custom(function = validation::pricing_mode))]
+    pricing_mode: Option<String>,
+    #[serde(default)]
+    #[validate(custom(function = validation::not_blank))]
+    resolver_id: Option<String>,
+    /// Mint a dedicated SPL token mirroring holdings on-chain.
+    #[serde(default)]
+    mint_token: bool,
+}
+
+fn target_above_initial(idea: &CreateIdeaRequest) -> Result<(), ValidationError> {
+    validation::target_above_initial(idea.target_price, idea.initial_price)
 }
 
 #[post("/ideas/create")]
-pub async fn create_idea(payload: web::Json<CreateIdeaRequest>) -> impl Responder {
+pub async fn create_idea(
+    payload: Valid<web::Json<CreateIdeaRequest>>,
+    pool: web::Data<PgPool>,
+    tokens: web::Data<TokenService>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("POST", "/ideas/create", Some(&session.wallet_address));
+    // The creator is always the signed-in user
+    let creator_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+
+    // The mint's address is known up front, so the idea can be inserted first and rolled back
+    // if the mint cannot be created
+    let mint = payload.mint_token.then(Keypair::new);
+
     let idea = db::Idea {
         id: None,
         title: payload.title.clone(),
         description: payload.description.clone(),
-        creator_id: payload.creator_id.clone(),
+        creator_id,
         category: payload.category.clone(),
         initial_price: payload.initial_price,
         target_price: payload.target_price,
@@ -44,29 +183,103 @@ pub async fn create_idea(payload: web::Json<CreateIdeaRequest>) -> impl Responde
         risk_level: payload.risk_level,
         market_size: payload.market_size.clone(),
         competitive_advantage: payload.competitive_advantage.clone(),
+        pricing_mode: payload
+            .pricing_mode
+            .clone()
+            .unwrap_or_else(|| db::PRICING_ORDER_BOOK.to_string()),
+        resolver_id: payload.resolver_id.clone(),
+        token_mint: mint.as_ref().map(|mint| mint.pubkey().to_string()),
         status: "active".to_string(),
         resolution_outcome: None,
         created_at: None,
         updated_at: None,
         resolved_at: None,
+        archived_at: None,
     };
 
-    match db::createIdea(idea).await {
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let result = async {
+        let work = client.begin_work().await?;
+        let idea = work.create_idea(idea).await?;
+        if let Some(mint) = &mint {
+            tokens.create_mint(mint).await?;
+        }
+        work.commit().await?;
+        Ok::<_, DbError>(idea)
+    }
+    .await;
+    match result {
         Ok(created_idea) => HttpResponse::Created().json(created_idea),
-        Err(e) => match e {
-            db::DbError::ValidationError(msg) => HttpResponse::BadRequest().json(msg),
-            _ => HttpResponse::InternalServerError().json(e.to_string()),
-        },
+        Err(e) => error_response(e),
     }
 }
 
-// #[post("/ideas/{id}/trade")]
-// pub async fn trade_idea(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock trade idea {}", id))
-// }
+#[derive(serde::Deserialize, Validate)]
+struct QuoteQuery {
+    side: Side,
+    #[validate(custom(function = validation::positive))]
+    amount: Decimal,
+}
+
+#[get("/ideas/{id}/quote")]
+pub async fn quote_idea(
+    id: web::Path<String>,
+    query: Valid<web::Query<QuoteQuery>>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/quote", Some(&id));
+    match amm::quote(&pool, &id, query.side, query.amount).await {
+        Ok(quote) => HttpResponse::Ok().json(quote),
+        Err(e) => error_response(e),
+    }
+}
 
-#[get("/ideas")]
-pub async fn get_all_ideas() -> impl Responder {
-    let ideas = db::getAllIdeas().await.unwrap();
-    HttpResponse::Ok().json(ideas)
+#[derive(serde::Deserialize, Validate)]
+struct TradeRequest {
+    side: Side,
+    #[validate(custom(function = validation::positive))]
+    amount: Decimal,
+    /// Largest acceptable move from the spot price, as a fraction.
+    #[validate(custom(function = validation::non_negative))]
+    max_slippage: Option<Decimal>,
+}
+
+#[post("/ideas/{id}/trade")]
+pub async fn trade_idea(
+    id: web::Path<String>,
+    payload: Valid<web::Json<TradeRequest>>,
+    pool: web::Data<PgPool>,
+    tokens: web::Data<TokenService>,
+    events: web::Data<EventBus>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ideas/{id}/trade",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    match amm::execute_trade(
+        &pool,
+        &events,
+        &id,
+        &user_id,
+        payload.side,
+        payload.amount,
+        payload.max_slippage,
+    )
+    .await
+    {
+        Ok(trade) => {
+            tokens.notify_trades();
+            HttpResponse::Created().json(trade)
+        }
+        Err(e) => error_response(e),
+    }
 }
@@ -0,0 +1,225 @@
+use crate::db::repo::{PgRepo, UserRepo};
+use crate::db::{self, positions, DbError, PgPool, User};
+use crate::error::error_response;
+use crate::services::auth::Session;
+use crate::utils;
+use crate::validation::{self, Valid};
+use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use validator::Validate;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+// Highest page whose offset fits an i64 at any page size
+const MAX_PAGE: i64 = i64::MAX / MAX_PAGE_SIZE;
+
+/// The wallet address comes from the session.
+#[derive(Deserialize, Validate)]
+pub struct CreateUserRequest {
+    #[validate(custom(function = validation::not_blank), length(min = 3, max = 32))]
+    pub username: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct UpdateUserRequest {
+    #[validate(custom(function = validation::not_blank), length(min = 3, max = 32))]
+    pub username: Option<String>,
+    #[validate(length(max = 50))]
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PageQuery {
+    #[validate(range(min = 1, max = MAX_PAGE))]
+    pub page: Option<i64>,
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
+    pub per_page: Option<i64>,
+}
+
+/// Only the owner of a wallet may change or delete its user.
+fn authorize(session: &Session, wallet_address: &str) -> Result<(), DbError> {
+    if session.wallet_address == wallet_address {
+        Ok(())
+    } else {
+        Err(DbError::Forbidden(
+            "Session does not belong to this wallet".into(),
+        ))
+    }
+}
+
+#[post("/user")]
+async fn create_user(
+    payload: Valid<web::Json<CreateUserRequest>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/user",
+        Some(&format!("wallet: {}", session.wallet_address)),
+    );
+    let user = User::new(payload.username.clone(), session.wallet_address);
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match PgRepo::new(&client).create_user(user).await {
+        Ok(user) => HttpResponse::Created().json(web::Json(user)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/users")]
+pub async fn list_users(pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/users", None);
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match PgRepo::new(&client).list_users().await {
+        Ok(users) => HttpResponse::Ok().json(web::Json(users)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/user/{wallet_address}")]
+pub async fn get_user_by_wallet_address(
+    wallet_address: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log("GET", "/user/{wallet_address}", Some(&wallet_address));
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match PgRepo::new(&client).user_by_wallet(&wallet_address).await {
+        Ok(user) => HttpResponse::Ok().json(web::Json(user)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/users/{wallet_address}")]
+pub async fn get_user(
+    wallet_address: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log("GET", "/users/{wallet_address}", Some(&wallet_address));
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match PgRepo::new(&client).user_by_wallet(&wallet_address).await {
+        Ok(user) => HttpResponse::Ok().json(web::Json(user)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[patch("/users/{wallet_address}")]
+pub async fn update_user(
+    wallet_address: web::Path<String>,
+    payload: Valid<web::Json<UpdateUserRequest>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("PATCH", "/users/{wallet_address}", Some(&wallet_address));
+    if let Err(e) = authorize(&session, &wallet_address) {
+        return error_response(e);
+    }
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match db::update_user(
+        &client,
+        &wallet_address,
+        payload.username.as_deref(),
+        payload.category.as_deref(),
+    )
+    .await
+    {
+        Ok(user) => HttpResponse::Ok().json(web::Json(user)),
+        Err(e) => error_response(e),
+    }
+}
+
+#[delete("/users/{wallet_address}")]
+pub async fn delete_user(
+    wallet_address: web::Path<String>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log("DELETE", "/users/{wallet_address}", Some(&wallet_address));
+    if let Err(e) = authorize(&session, &wallet_address) {
+        return error_response(e);
+    }
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    match db::delete_user(&mut client, &wallet_address).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Pages are 1-based; the total count is returned in the `X-Total-Count` header.
+#[get("/users/{wallet_address}/transactions")]
+pub async fn get_user_transactions(
+    wallet_address: web::Path<String>,
+    query: Valid<web::Query<PageQuery>>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log(
+        "GET",
+        "/users/{wallet_address}/transactions",
+        Some(&wallet_address),
+    );
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = (query.page.unwrap_or(1) - 1) * per_page;
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let user = match client.user_by_wallet(&wallet_address).await {
+        Ok(user) => user,
+        Err(e) => return error_response(e),
+    };
+    match db::get_user_transactions(
+        &client,
+        user.id.as_deref().unwrap_or_default(),
+        per_page,
+        offset,
+    )
+    .await
+    {
+        Ok((transactions, total)) => HttpResponse::Ok()
+            .insert_header(("X-Total-Count", total.to_string()))
+            .json(transactions),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/users/{wallet_address}/holdings")]
+pub async fn get_user_holdings(
+    wallet_address: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> impl Responder {
+    utils::route_log(
+        "GET",
+        "/users/{wallet_address}/holdings",
+        Some(&wallet_address),
+    );
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let user = match client.user_by_wallet(&wallet_address).await {
+        Ok(user) => user,
+        Err(e) => return error_response(e),
+    };
+    match positions::get_user_positions(&client, user.id.as_deref().unwrap_or_default()).await {
+        Ok(holdings) => HttpResponse::Ok().json(holdings),
+        Err(e) => error_response(e),
+    }
+}
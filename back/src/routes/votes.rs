@@ -1,15 +1,68 @@
+use crate::db::repo::IdeaRepo;
+use crate::db::votes::{self, Vote, VoteDirection, VoteTally};
+use crate::db::PgPool;
+use crate::error::error_response;
+use crate::services::auth::Session;
+use crate::utils;
+use crate::validation::Valid;
 use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-// #[post("/ideas/{id}/vote")]
-// pub async fn vote_on_idea(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock vote on idea {}", id))
-// }
+#[derive(Deserialize, Validate)]
+pub struct VoteRequest {
+    pub direction: VoteDirection,
+}
 
-// #[get("/ideas/{id}/votes")]
-// pub async fn get_votes(web::Path(id): web::Path<u32>) -> impl Responder {
-//     HttpResponse::Ok().json(format!("Mock votes for idea {}", id))
-// }
+#[derive(Serialize)]
+pub struct VoteResponse {
+    pub vote: Vote,
+    pub tally: VoteTally,
+}
 
-// pub fn config(cfg: &mut web::ServiceConfig) {
-//     cfg.service(vote_on_idea).service(get_votes);
-// }
+/// Casts the signed-in user's vote. Each user votes once per idea.
+#[post("/ideas/{id}/vote")]
+pub async fn vote_on_idea(
+    id: web::Path<String>,
+    payload: Valid<web::Json<VoteRequest>>,
+    pool: web::Data<PgPool>,
+    session: Session,
+) -> impl Responder {
+    utils::route_log(
+        "POST",
+        "/ideas/{id}/vote",
+        Some(&format!("idea: {}, wallet: {}", id, session.wallet_address)),
+    );
+    let user_id = match session.user_id(&pool).await {
+        Ok(user_id) => user_id,
+        Err(e) => return error_response(e),
+    };
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    let vote = match votes::cast_vote(&client, &id, &user_id, payload.direction).await {
+        Ok(vote) => vote,
+        Err(e) => return error_response(e),
+    };
+    match votes::get_vote_tally(&client, &id).await {
+        Ok(tally) => HttpResponse::Created().json(VoteResponse { vote, tally }),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/ideas/{id}/votes")]
+pub async fn get_votes(id: web::Path<String>, pool: web::Data<PgPool>) -> impl Responder {
+    utils::route_log("GET", "/ideas/{id}/votes", Some(&id));
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => return error_response(e),
+    };
+    if let Err(e) = client.idea_by_id(&id).await {
+        return error_response(e);
+    }
+    match votes::get_vote_tally(&client, &id).await {
+        Ok(tally) => HttpResponse::Ok().json(tally),
+        Err(e) => error_response(e),
+    }
+}
@@ -0,0 +1,354 @@
+use crate::db::repo::{IdeaRepo, UserRepo};
+use crate::db::{DbError, PgPool};
+use crate::services::events::{Channel, Event, EventBus};
+use crate::utils;
+use crate::validation::Valid;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Closed, Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use validator::Validate;
+
+/// How often the server pings an idle client.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// Connections that send nothing, not even a pong, for this long are closed.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+/// A client that cannot take a message within this long is too slow and gets disconnected.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a closed connection's subscriptions can be resumed.
+const RESUME_TTL: Duration = Duration::from_secs(120);
+const MAX_SUBSCRIPTIONS: usize = 50;
+const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Subscriptions of recently closed connections, so a client reconnecting with
+/// `?resume={session_id}` picks up where it left off.
+#[derive(Default)]
+pub struct WsSessions {
+    closed: Mutex<HashMap<String, (Vec<Channel>, Instant)>>,
+}
+
+impl WsSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn keep(&self, session_id: String, channels: Vec<Channel>) {
+        let mut closed = self.closed.lock().unwrap_or_else(|e| e.into_inner());
+        closed.retain(|_, (_, closed_at)| closed_at.elapsed() < RESUME_TTL);
+        if !channels.is_empty() {
+            closed.insert(session_id, (channels, Instant::now()));
+        }
+    }
+
+    fn take(&self, session_id: &str) -> Option<Vec<Channel>> {
+        let mut closed = self.closed.lock().unwrap_or_else(|e| e.into_inner());
+        closed
+            .remove(session_id)
+            .filter(|(_, closed_at)| closed_at.elapsed() < RESUME_TTL)
+            .map(|(channels, _)| channels)
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct WsQuery {
+    /// Session ID of a previous connection whose subscriptions should be restored.
+    #[validate(length(equal = 32))]
+    pub resume: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe { channels: Vec<String> },
+    Unsubscribe { channels: Vec<String> },
+    Ping,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage<'a> {
+    /// First message on every connection; `session_id` is what to resume after a reconnect.
+    Welcome {
+        session_id: &'a str,
+        resumed: bool,
+        channels: Vec<String>,
+        errors: Vec<String>,
+    },
+    /// Reply to `subscribe` and `unsubscribe` with every channel now subscribed.
+    Subscribed {
+        channels: Vec<String>,
+        errors: Vec<String>,
+    },
+    Event {
+        channel: String,
+        data: &'a Event,
+    },
+    /// The connection fell behind and skipped `missed` events; refetch state over HTTP.
+    Lagged {
+        missed: u64,
+    },
+    Error {
+        message: String,
+    },
+    Pong,
+}
+
+struct Connection {
+    session: Session,
+    session_id: String,
+    pool: PgPool,
+    // channel -> the user a `user:{wallet}:fills` channel resolved to
+    subscriptions: BTreeMap<Channel, Option<String>>,
+}
+
+impl Connection {
+    fn channels(&self) -> Vec<String> {
+        self.subscriptions.keys().map(Channel::to_string).collect()
+    }
+
+    /// Sends a message, treating a client that stays full for `SEND_TIMEOUT` as gone.
+    async fn send(&mut self, message: &ServerMessage<'_>) -> Result<(), Closed> {
+        let text = serde_json::to_string(message).map_err(|_| Closed)?;
+        tokio::time::timeout(SEND_TIMEOUT, self.session.text(text))
+            .await
+            .map_err(|_| Closed)?
+    }
+
+    /// Checks the channel's idea or wallet exists and returns the user a fills channel
+    /// follows.
+    async fn resolve(&self, channel: &Channel) -> Result<Option<String>, DbError> {
+        let client = self.pool.get().await?;
+        match channel {
+            Channel::IdeaTrades(id) | Channel::IdeaRate(id) | Channel::IdeaBook(id) => {
+                client.idea_by_id(id).await?;
+                Ok(None)
+            }
+            Channel::UserFills(wallet) => {
+                let user = client.user_by_wallet(wallet).await?;
+                Ok(user.id)
+            }
+        }
+    }
+
+    /// Adds the channels, returning why any of them were refused.
+    async fn subscribe(&mut self, channels: Vec<Channel>) -> Vec<String> {
+        let mut errors = Vec::new();
+        for channel in channels {
+            if self.subscriptions.contains_key(&channel) {
+                continue;
+            }
+            if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                errors.push(format!(
+                    "{}: at most {} channels per connection",
+                    channel, MAX_SUBSCRIPTIONS
+                ));
+                continue;
+            }
+            match self.resolve(&channel).await {
+                Ok(user_id) => {
+                    self.subscriptions.insert(channel, user_id);
+                }
+                Err(e) => errors.push(format!("{}: {}", channel, e)),
+            }
+        }
+        errors
+    }
+
+    async fn handle_text(&mut self, text: &str) -> Result<(), Closed> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                return self
+                    .send(&ServerMessage::Error {
+                        message: format!("Invalid message: {}", e),
+                    })
+                    .await
+            }
+        };
+
+        let errors = match message {
+            ClientMessage::Ping => return self.send(&ServerMessage::Pong).await,
+            ClientMessage::Subscribe { channels } => {
+                let (channels, mut errors) = parse_channels(&channels);
+                errors.extend(self.subscribe(channels).await);
+                errors
+            }
+            ClientMessage::Unsubscribe { channels } => {
+                let (channels, errors) = parse_channels(&channels);
+                for channel in &channels {
+                    self.subscriptions.remove(channel);
+                }
+                errors
+            }
+        };
+        self.send(&ServerMessage::Subscribed {
+            channels: self.channels(),
+            errors,
+        })
+        .await
+    }
+
+    async fn dispatch(&mut self, event: &Event) -> Result<(), Closed> {
+        let channels: Vec<String> = self
+            .subscriptions
+            .iter()
+            .filter(|(channel, user_id)| channel.matches(event, user_id.as_deref()))
+            .map(|(channel, _)| channel.to_string())
+            .collect();
+        for channel in channels {
+            self.send(&ServerMessage::Event {
+                channel,
+                data: event,
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Relays events until the client leaves, misses its heartbeat or cannot keep up.
+    async fn run(
+        &mut self,
+        mut stream: MessageStream,
+        mut events: Receiver<Event>,
+    ) -> Option<CloseReason> {
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > CLIENT_TIMEOUT {
+                        return Some(CloseReason {
+                            code: CloseCode::Away,
+                            description: Some("Heartbeat timed out".into()),
+                        });
+                    }
+                    if self.session.ping(b"").await.is_err() {
+                        return None;
+                    }
+                }
+                message = stream.recv() => {
+                    let message = match message {
+                        Some(Ok(message)) => message,
+                        Some(Err(e)) => {
+                            return Some(CloseReason {
+                                code: CloseCode::Protocol,
+                                description: Some(e.to_string()),
+                            })
+                        }
+                        None => return None,
+                    };
+                    last_seen = Instant::now();
+                    let sent = match message {
+                        Message::Text(text) => self.handle_text(&text).await,
+                        Message::Ping(bytes) => self.session.pong(&bytes).await,
+                        Message::Close(reason) => return reason,
+                        Message::Binary(_) => {
+                            self.send(&ServerMessage::Error {
+                                message: "Binary messages are not supported".into(),
+                            })
+                            .await
+                        }
+                        _ => Ok(()),
+                    };
+                    if sent.is_err() {
+                        return None;
+                    }
+                }
+                event = events.recv() => {
+                    let sent = match event {
+                        Ok(event) => self.dispatch(&event).await,
+                        Err(RecvError::Lagged(missed)) => {
+                            self.send(&ServerMessage::Lagged { missed }).await
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+                    if sent.is_err() {
+                        return Some(CloseReason {
+                            code: CloseCode::Policy,
+                            description: Some("Client is not keeping up".into()),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_channels(names: &[String]) -> (Vec<Channel>, Vec<String>) {
+    let mut channels = Vec::new();
+    let mut errors = Vec::new();
+    for name in names {
+        match name.parse() {
+            Ok(channel) => channels.push(channel),
+            Err(e) => errors.push(e),
+        }
+    }
+    (channels, errors)
+}
+
+/// Live market data. Clients send `{"op": "subscribe", "channels": [...]}` with channels such as
+/// `idea:{id}:trades`, `idea:{id}:rate`, `idea:{id}:book` and `user:{wallet}:fills`, and
+/// reconnect with `?resume={session_id}` to get their subscriptions back.
+#[get("/ws")]
+pub async fn connect(
+    req: HttpRequest,
+    body: web::Payload,
+    query: Valid<web::Query<WsQuery>>,
+    pool: web::Data<PgPool>,
+    events: web::Data<EventBus>,
+    sessions: web::Data<WsSessions>,
+) -> actix_web::Result<HttpResponse> {
+    utils::route_log("GET", "/ws", query.resume.as_deref());
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    // Subscribed before anything else so no event published from here on is missed
+    let receiver = events.subscribe();
+
+    let query = query.into_inner().into_inner();
+    let restored = query.resume.as_deref().and_then(|id| sessions.take(id));
+    let resumed = restored.is_some();
+    let session_id = match query.resume {
+        Some(session_id) if resumed => session_id,
+        _ => format!("{:032x}", rand::random::<u128>()),
+    };
+
+    let mut connection = Connection {
+        session,
+        session_id,
+        pool: pool.get_ref().clone(),
+        subscriptions: BTreeMap::new(),
+    };
+    let sessions = sessions.into_inner();
+    actix_web::rt::spawn(async move {
+        let errors = connection.subscribe(restored.unwrap_or_default()).await;
+        let welcome = ServerMessage::Welcome {
+            session_id: &connection.session_id.clone(),
+            resumed,
+            channels: connection.channels(),
+            errors,
+        };
+        let reason = match connection.send(&welcome).await {
+            Ok(()) => {
+                connection
+                    .run(stream.max_frame_size(MAX_FRAME_SIZE), receiver)
+                    .await
+            }
+            Err(_) => None,
+        };
+
+        let Connection {
+            session,
+            session_id,
+            subscriptions,
+            ..
+        } = connection;
+        sessions.keep(session_id, subscriptions.into_keys().collect());
+        // A client that stopped reading may never drain the close frame
+        let _ = tokio::time::timeout(SEND_TIMEOUT, session.close(reason)).await;
+    });
+
+    Ok(response)
+}
@@ -0,0 +1,347 @@
+use crate::db::ledger::TRADE_CURRENCY;
+use crate::db::repo::{self, IdeaRepo, PgBacked, PgRepo, PgUnitOfWork, UnitOfWork};
+use crate::db::{self, AmmPool, DbError, PgPool, Rate, Transaction};
+use crate::decimal::{Decimal, Rounding};
+use crate::services::events::{Event, EventBus};
+use crate::services::order_book::Side;
+use crate::utils;
+use serde::Serialize;
+
+/// Price and slippage for trading `amount` tokens against a pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct Quote {
+    pub idea_id: String,
+    pub side: Side,
+    pub amount: Decimal,
+    /// Cash paid for a buy, or received for a sell.
+    pub total: Decimal,
+    pub average_price: Decimal,
+    pub spot_price_before: Decimal,
+    pub spot_price_after: Decimal,
+    /// Relative distance between the average price and the spot price before the trade,
+    /// rounded up.
+    pub slippage: Decimal,
+}
+
+impl Quote {
+    /// Rate recorded for the trade: the pool's spot price after it, where the next trade
+    /// starts, rather than the average this trade paid along the curve.
+    pub fn rate(&self) -> Rate {
+        Rate::new(self.idea_id.clone(), self.spot_price_after, self.amount)
+    }
+}
+
+/// Trade executed against a pool.
+#[derive(Debug, Serialize)]
+pub struct AmmTrade {
+    pub quote: Quote,
+    pub trade: Transaction,
+}
+
+impl AmmPool {
+    pub fn spot_price(&self) -> Decimal {
+        self.cash_reserve
+            .div_rounded(self.token_reserve, Rounding::HalfEven)
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Quotes a trade on the `x * y = k` curve without touching the reserves. Cash amounts are
+    /// rounded in the pool's favour, so `k` never shrinks, and to whole units of the trade
+    /// currency, so the cash reserve moves by exactly what the ledger posts to `system:amm`.
+    pub fn quote(&self, side: Side, amount: Decimal) -> Result<Quote, DbError> {
+        if !amount.is_positive() {
+            return Err(DbError::ValidationError("Amount must be positive".into()));
+        }
+
+        let k = self
+            .token_reserve
+            .mul_rounded(self.cash_reserve, Rounding::Up);
+        let token_after = match side {
+            Side::Buy => {
+                if amount >= self.token_reserve {
+                    return Err(DbError::ValidationError(format!(
+                        "Amount exceeds pool liquidity of {} tokens",
+                        self.token_reserve
+                    )));
+                }
+                self.token_reserve - amount
+            }
+            Side::Sell => self.token_reserve + amount,
+        };
+        let curve_cash = k
+            .div_rounded(token_after, Rounding::Up)
+            .ok_or_else(|| DbError::ValidationError("Pool has no token reserve".into()))?;
+        let (exact, rounding) = match side {
+            Side::Buy => (curve_cash - self.cash_reserve, Rounding::Up),
+            Side::Sell => (self.cash_reserve - curve_cash, Rounding::Down),
+        };
+        let total = TRADE_CURRENCY.to_amount(TRADE_CURRENCY.to_units(exact, rounding)?)?;
+        if !total.is_positive() {
+            return Err(DbError::ValidationError(
+                "Amount is too small to trade".into(),
+            ));
+        }
+        let cash_after = match side {
+            Side::Buy => self.cash_reserve + total,
+            Side::Sell => self.cash_reserve - total,
+        };
+
+        let spot_price_before = self.spot_price();
+        let average_price = total
+            .div_rounded(amount, Rounding::HalfEven)
+            .unwrap_or(Decimal::ZERO);
+        let distance = match side {
+            Side::Buy => average_price - spot_price_before,
+            Side::Sell => spot_price_before - average_price,
+        };
+
+        Ok(Quote {
+            idea_id: self.idea_id.clone(),
+            side,
+            amount,
+            total,
+            average_price,
+            spot_price_before,
+            spot_price_after: cash_after
+                .div_rounded(token_after, Rounding::HalfEven)
+                .unwrap_or(Decimal::ZERO),
+            slippage: distance
+                .div_rounded(spot_price_before, Rounding::Up)
+                .unwrap_or(Decimal::ZERO),
+        })
+    }
+
+    pub(crate) fn apply(&mut self, quote: &Quote) {
+        match quote.side {
+            Side::Buy => {
+                self.token_reserve -= quote.amount;
+                self.cash_reserve += quote.total;
+            }
+            Side::Sell => {
+                self.token_reserve += quote.amount;
+                self.cash_reserve -= quote.total;
+            }
+        }
+    }
+}
+
+pub async fn quote(
+    pool: &PgPool,
+    idea_id: &str,
+    side: Side,
+    amount: Decimal,
+) -> Result<Quote, DbError> {
+    let client = pool.get().await?;
+    db::get_amm_pool(&client, idea_id)
+        .await?
+        .quote(side, amount)
+}
+
+/// Executes a trade against the idea's pool, updating reserves, `transactions`, `rates` and the
+/// trader's ledger balance atomically.
+pub async fn execute_trade(
+    pool: &PgPool,
+    events: &EventBus,
+    idea_id: &str,
+    user_id: &str,
+    side: Side,
+    amount: Decimal,
+    max_slippage: Option<Decimal>,
+) -> Result<AmmTrade, DbError> {
+    if user_id.trim().is_empty() {
+        return Err(DbError::ValidationError("User ID cannot be empty".into()));
+    }
+
+    let mut client = pool.get().await?;
+    let idea = PgRepo::new(&client).idea_by_id(idea_id).await?;
+    if !idea.is_tradable() {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} is not open for trading",
+            idea_id
+        )));
+    }
+
+    let uow = PgUnitOfWork::begin(&mut client).await?;
+
+    let mut reserves = db::lock_amm_pool(uow.client(), idea_id).await?;
+    let quote = reserves.quote(side, amount)?;
+    // Tokens can only be sold by someone holding them
+    if side == Side::Sell {
+        db::lock_user_holding(uow.client(), idea_id, user_id).await?;
+        let holding = db::get_available_holding(uow.client(), idea_id, user_id).await?;
+        if holding < amount {
+            return Err(DbError::ValidationError(format!(
+                "Cannot sell {} tokens while holding {}",
+                amount, holding
+            )));
+        }
+    }
+    if let Some(max_slippage) = max_slippage {
+        if quote.slippage > max_slippage {
+            return Err(DbError::ValidationError(format!(
+                "Slippage {} exceeds the allowed {}",
+                quote.slippage, max_slippage
+            )));
+        }
+    }
+
+    reserves.apply(&quote);
+    db::update_amm_pool(uow.client(), &reserves).await?;
+
+    let (buyer_id, seller_id) = match side {
+        Side::Buy => (user_id.to_string(), db::AMM_ACCOUNT_ID.to_string()),
+        Side::Sell => (db::AMM_ACCOUNT_ID.to_string(), user_id.to_string()),
+    };
+    // The trade is worth exactly what moved through the pool
+    let trade = Transaction {
+        total_value: quote.total,
+        ..Transaction::completed(
+            idea_id.to_string(),
+            buyer_id,
+            seller_id,
+            amount,
+            quote.average_price,
+        )
+    };
+    let (trade, rate) = repo::record_trade(&uow, trade, quote.rate(), 0).await?;
+
+    uow.commit().await?;
+    events.publish(Event::Trade(trade.clone()));
+    events.publish(Event::Rate(rate));
+
+    utils::log(&format!(
+        "[AMM] {:?} {} tokens of idea {} at avg {}",
+        side, amount, idea_id, quote.average_price
+    ));
+
+    Ok(AmmTrade { quote, trade })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ledger::{self, AMM_LEDGER_ACCOUNT};
+    use crate::db::memory::MemoryStore;
+
+    // Smallest units in a whole USDC
+    const USDC: i64 = 1_000_000;
+
+    fn pool(tokens: Decimal, cash: Decimal) -> AmmPool {
+        AmmPool {
+            idea_id: "idea".into(),
+            token_reserve: tokens,
+            cash_reserve: cash,
+        }
+    }
+
+    /// Trades against the pool and records the trade as `execute_trade` does.
+    async fn trade(
+        store: &MemoryStore,
+        pool: &mut AmmPool,
+        user_id: &str,
+        side: Side,
+        amount: Decimal,
+    ) -> Quote {
+        let quote = pool.quote(side, amount).unwrap();
+        pool.apply(&quote);
+        let (buyer, seller) = match side {
+            Side::Buy => (user_id, db::AMM_ACCOUNT_ID),
+            Side::Sell => (db::AMM_ACCOUNT_ID, user_id),
+        };
+        let uow = store.begin();
+        repo::record_trade(
+            &uow,
+            Transaction {
+                total_value: quote.total,
+                ..Transaction::completed(
+                    "idea".into(),
+                    buyer.into(),
+                    seller.into(),
+                    quote.amount,
+                    quote.average_price,
+                )
+            },
+            quote.rate(),
+            0,
+        )
+        .await
+        .unwrap();
+        uow.commit().await.unwrap();
+        quote
+    }
+
+    #[tokio::test]
+    async fn amm_round_trip_keeps_the_pool_whole() {
+        let store = MemoryStore::new();
+        store.credit("trader", 1_000 * USDC).unwrap();
+        let mut pool = pool(Decimal::from(1_000), Decimal::from(1_000));
+        let k = pool
+            .token_reserve
+            .mul_rounded(pool.cash_reserve, Rounding::Down);
+
+        for side in [Side::Buy, Side::Sell] {
+            let quote = trade(&store, &mut pool, "trader", side, Decimal::from(100)).await;
+            // The next trade starts from the recorded rate
+            assert_eq!(quote.rate().rate(), pool.spot_price());
+        }
+
+        // Rounding favours the pool, so a round trip costs the trader and never shrinks k
+        assert_eq!(pool.token_reserve, Decimal::from(1_000));
+        assert!(pool.cash_reserve >= Decimal::from(1_000));
+        assert!(
+            pool.token_reserve
+                .mul_rounded(pool.cash_reserve, Rounding::Down)
+                >= k
+        );
+        assert!(store.balance("trader") <= 1_000 * USDC);
+        assert!(store.balance("trader") > 999 * USDC);
+        // Buying more than the pool holds is refused
+        assert!(pool.quote(Side::Buy, Decimal::from(1_000)).is_err());
+    }
+
+    #[tokio::test]
+    async fn pool_cash_reconciles_with_the_amm_ledger_account() {
+        let store = MemoryStore::new();
+        store.credit("a", 1_000 * USDC).unwrap();
+        store.credit("b", 1_000 * USDC).unwrap();
+        // Seeded like a new idea; the seed cash is virtual and never posted to the ledger
+        let seed_cash = Decimal::new(123_456_789, 5);
+        let mut pool = pool(Decimal::from(1_000), seed_cash);
+        let amm_account = ledger::system_account(AMM_LEDGER_ACCOUNT, TRADE_CURRENCY);
+
+        for (user_id, side, amount) in [
+            ("a", Side::Buy, Decimal::from(10)),
+            ("b", Side::Buy, Decimal::new(2_505, 1)),
+            ("a", Side::Sell, Decimal::from(10)),
+            ("b", Side::Sell, Decimal::new(10_025, 2)),
+            ("b", Side::Sell, Decimal::new(15_025, 2)),
+        ] {
+            trade(&store, &mut pool, user_id, side, amount).await;
+            // Buys pay into the market maker's account and sells out of it, unit for unit
+            let posted = store.system_balance(&amm_account);
+            assert!(posted >= 0);
+            assert_eq!(
+                TRADE_CURRENCY.to_amount(posted).unwrap(),
+                pool.cash_reserve - seed_cash
+            );
+        }
+        // Everything bought was sold back, leaving the pool's rounding gains
+        assert_eq!(pool.token_reserve, Decimal::from(1_000));
+        assert_eq!(
+            store.balance("a") + store.balance("b") + store.system_balance(&amm_account),
+            2_000 * USDC
+        );
+    }
+
+    #[test]
+    fn quote_measures_slippage_from_the_spot_price() {
+        let pool = pool(Decimal::from(1_000), Decimal::from(1_000));
+        // 100 of 1000 tokens average 1.11111112 against a spot price of 1, leaving 1.2345679;
+        // the total is rounded up to whole units
+        let quote = pool.quote(Side::Buy, Decimal::from(100)).unwrap();
+        assert_eq!(quote.total, Decimal::new(111_111_112, 6));
+        assert_eq!(quote.average_price, Decimal::new(111_111_112, 8));
+        assert_eq!(quote.slippage, Decimal::new(11_111_112, 8));
+        assert_eq!(quote.rate().rate(), Decimal::new(123_456_790, 8));
+    }
+}
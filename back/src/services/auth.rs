@@ -0,0 +1,191 @@
+use crate::config::CONFIG;
+use crate::db::repo::UserRepo;
+use crate::db::{challenges, DbError, PgPool, User};
+use crate::error::{ApiError, ErrorCode};
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::future::{ready, Ready};
+use std::str::FromStr;
+
+const NONCE_LENGTH: usize = 24;
+const CHALLENGE_TTL_SECS: i64 = 300;
+
+/// Sign-in challenge a wallet has to sign to obtain a session.
+#[derive(Debug, Clone, Serialize)]
+pub struct Challenge {
+    pub wallet_address: String,
+    pub nonce: String,
+    /// Exact text the wallet must sign.
+    pub message: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionToken {
+    pub token: String,
+    pub wallet_address: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Authenticated wallet attached to a request by the session middleware.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub wallet_address: String,
+}
+
+impl Session {
+    /// The user registered for this wallet.
+    pub async fn user(&self, pool: &PgPool) -> Result<User, DbError> {
+        let client = pool.get().await?;
+        client
+            .user_by_wallet(&self.wallet_address)
+            .await
+            .map_err(|e| match e {
+                DbError::NotFound(_) => DbError::Forbidden(format!(
+                    "No user registered for wallet {}",
+                    self.wallet_address
+                )),
+                e => e,
+            })
+    }
+
+    pub async fn user_id(&self, pool: &PgPool) -> Result<String, DbError> {
+        self.user(pool)
+            .await?
+            .id
+            .ok_or_else(|| DbError::QueryError("User row without an ID".into()))
+    }
+}
+
+impl FromRequest for Session {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Session>().cloned().ok_or_else(|| {
+            ApiError::new(ErrorCode::Unauthorized, "Missing or invalid session token").into()
+        }))
+    }
+}
+
+/// Issues sign-in challenges and verifies signed ones (Sign-In With Solana).
+///
+/// Pending challenges are kept in Postgres, so any instance can verify them.
+pub struct AuthService {
+    pool: PgPool,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+impl AuthService {
+    pub fn new(pool: PgPool, secret: &[u8]) -> Self {
+        Self {
+            pool,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+        }
+    }
+
+    /// Creates a fresh challenge for a wallet, replacing any earlier one.
+    pub async fn challenge(&self, wallet_address: &str) -> Result<Challenge, DbError> {
+        Pubkey::from_str(wallet_address)
+            .map_err(|_| DbError::ValidationError("Invalid wallet address".into()))?;
+
+        let nonce: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(NONCE_LENGTH)
+            .map(char::from)
+            .collect();
+        let issued_at = Utc::now();
+        let expires_at = issued_at + Duration::seconds(CHALLENGE_TTL_SECS);
+        let message = format!(
+            "{} wants you to sign in with your Solana account:\n{}\n\nSign in to Idea Market.\n\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+            CONFIG.auth_domain,
+            wallet_address,
+            nonce,
+            issued_at.to_rfc3339(),
+            expires_at.to_rfc3339()
+        );
+
+        let challenge = Challenge {
+            wallet_address: wallet_address.to_string(),
+            nonce,
+            message,
+            issued_at,
+            expires_at,
+        };
+
+        let client = self.pool.get().await?;
+        challenges::put_challenge(
+            &client,
+            wallet_address,
+            &challenge.message,
+            challenge.expires_at,
+        )
+        .await?;
+        Ok(challenge)
+    }
+
+    /// Checks a base58 ed25519 signature over the wallet's pending challenge and issues a session token.
+    /// Challenges are single use, whether or not verification succeeds.
+    pub async fn verify(
+        &self,
+        wallet_address: &str,
+        signature: &str,
+    ) -> Result<SessionToken, DbError> {
+        let client = self.pool.get().await?;
+        let (message, expires_at) = challenges::take_challenge(&client, wallet_address)
+            .await?
+            .ok_or_else(|| DbError::Forbidden("No pending challenge for this wallet".into()))?;
+        if expires_at <= Utc::now() {
+            return Err(DbError::Forbidden("Challenge has expired".into()));
+        }
+
+        let pubkey = Pubkey::from_str(wallet_address)
+            .map_err(|_| DbError::ValidationError("Invalid wallet address".into()))?;
+        let signature = Signature::from_str(signature)
+            .map_err(|_| DbError::ValidationError("Invalid signature encoding".into()))?;
+        if !signature.verify(pubkey.as_ref(), message.as_bytes()) {
+            return Err(DbError::Forbidden("Signature verification failed".into()));
+        }
+
+        let issued_at = Utc::now();
+        let expires_at = issued_at + Duration::seconds(CONFIG.session_ttl_secs);
+        let claims = Claims {
+            sub: wallet_address.to_string(),
+            iat: issued_at.timestamp(),
+            exp: expires_at.timestamp(),
+        };
+        let token = encode(&Header::default(), &claims, &self.encoding_key)
+            .map_err(|e| DbError::QueryError(format!("Failed to issue session token: {}", e)))?;
+
+        Ok(SessionToken {
+            token,
+            wallet_address: wallet_address.to_string(),
+            expires_at,
+        })
+    }
+
+    /// Validates a session token and returns the session it carries.
+    pub fn session(&self, token: &str) -> Option<Session> {
+        decode::<Claims>(token, &self.decoding_key, &Validation::default())
+            .ok()
+            .map(|data| Session {
+                wallet_address: data.claims.sub,
+            })
+    }
+}
@@ -0,0 +1,99 @@
+use crate::db::{Rate, Transaction};
+use crate::services::order_book::OrderBookSnapshot;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use tokio::sync::broadcast;
+
+/// Events a subscriber may fall behind by before it starts missing them.
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// Something that happened to the market, published once it is committed.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+    Trade(Transaction),
+    Rate(Rate),
+    OrderBook(OrderBookSnapshot),
+}
+
+/// A stream of events a client can subscribe to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Channel {
+    /// `idea:{id}:trades`
+    IdeaTrades(String),
+    /// `idea:{id}:rate`
+    IdeaRate(String),
+    /// `idea:{id}:book`
+    IdeaBook(String),
+    /// `user:{wallet}:fills`, trades on either side of the wallet's user
+    UserFills(String),
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts.as_slice() {
+            ["idea", id, "trades"] if !id.is_empty() => Ok(Channel::IdeaTrades(id.to_string())),
+            ["idea", id, "rate"] if !id.is_empty() => Ok(Channel::IdeaRate(id.to_string())),
+            ["idea", id, "book"] if !id.is_empty() => Ok(Channel::IdeaBook(id.to_string())),
+            ["user", wallet, "fills"] if !wallet.is_empty() => {
+                Ok(Channel::UserFills(wallet.to_string()))
+            }
+            _ => Err(format!("Unknown channel: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::IdeaTrades(id) => write!(f, "idea:{}:trades", id),
+            Channel::IdeaRate(id) => write!(f, "idea:{}:rate", id),
+            Channel::IdeaBook(id) => write!(f, "idea:{}:book", id),
+            Channel::UserFills(wallet) => write!(f, "user:{}:fills", wallet),
+        }
+    }
+}
+
+impl Channel {
+    /// Whether `event` belongs on this channel. `user_id` is the user a `UserFills` channel's
+    /// wallet resolved to.
+    pub fn matches(&self, event: &Event, user_id: Option<&str>) -> bool {
+        match (self, event) {
+            (Channel::IdeaTrades(id), Event::Trade(trade)) => trade.idea_id == *id,
+            (Channel::IdeaRate(id), Event::Rate(rate)) => rate.idea_id() == id,
+            (Channel::IdeaBook(id), Event::OrderBook(book)) => book.idea_id == *id,
+            (Channel::UserFills(_), Event::Trade(trade)) => user_id
+                .is_some_and(|user_id| trade.buyer_id == user_id || trade.seller_id == user_id),
+            _ => false,
+        }
+    }
+}
+
+/// In-process broadcast of committed trades, rates and order book changes.
+///
+/// Publishing never blocks: subscribers that fall more than [`EVENT_BUS_CAPACITY`] events behind
+/// skip ahead and are told how many they missed.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,85 @@
+use crate::db::notify::{self, IDEA_EVENTS_CHANNEL};
+use crate::utils;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Notifications a stream may fall behind by before it starts missing them.
+const STREAM_CAPACITY: usize = 1024;
+/// Delay before reconnecting after the listener connection drops, doubled up to the maximum.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A rate or completed trade as published by the `idea_events` triggers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdeaNotification {
+    /// `rate` or `trade`
+    pub event: String,
+    pub idea_id: String,
+    pub data: serde_json::Value,
+}
+
+/// Relays Postgres `idea_events` notifications to in-process subscribers, so every instance
+/// streams the rates and trades written by any of them.
+#[derive(Clone)]
+pub struct IdeaStreamService {
+    sender: broadcast::Sender<IdeaNotification>,
+}
+
+impl Default for IdeaStreamService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdeaStreamService {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(STREAM_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<IdeaNotification> {
+        self.sender.subscribe()
+    }
+
+    /// Listens for notifications in the background, reconnecting whenever the connection
+    /// drops. Events committed while reconnecting are not replayed.
+    pub fn start_listener(&self) -> JoinHandle<()> {
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            let mut delay = RECONNECT_DELAY;
+            loop {
+                let started = Instant::now();
+                let result = notify::listen(IDEA_EVENTS_CHANNEL, |notification| {
+                    match serde_json::from_str::<IdeaNotification>(notification.payload()) {
+                        // Nobody streaming is fine
+                        Ok(event) => {
+                            let _ = sender.send(event);
+                        }
+                        Err(e) => utils::log(&format!(
+                            "[IdeaStream] Ignoring malformed notification: {}",
+                            e
+                        )),
+                    }
+                })
+                .await;
+                let held = started.elapsed();
+                if let Err(e) = result {
+                    utils::log(&format!(
+                        "[IdeaStream] Listener stopped, reconnecting in {:?}: {}",
+                        delay, e
+                    ));
+                }
+
+                tokio::time::sleep(delay).await;
+                // A connection that held up for a while starts the backoff over
+                delay = if held > MAX_RECONNECT_DELAY {
+                    RECONNECT_DELAY
+                } else {
+                    (delay * 2).min(MAX_RECONNECT_DELAY)
+                };
+            }
+        })
+    }
+}
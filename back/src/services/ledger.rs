@@ -0,0 +1,519 @@
+use crate::config::CONFIG;
+use crate::db::ledger::{
+    self, Balance, Currency, JournalEntry, JournalLine, DEPOSITS_ACCOUNT, ENTRY_DEPOSIT,
+    ENTRY_WITHDRAWAL, ENTRY_WITHDRAWAL_REVERSAL, WITHDRAWALS_ACCOUNT,
+};
+use crate::db::repo::UserRepo;
+use crate::db::{DbError, PgPool};
+use crate::handlers::solana::backend_keypair;
+use crate::services::solana_service::SolanaServiceError;
+use crate::utils;
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature};
+use solana_sdk::signer::Signer;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction as SolanaTransaction;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, UiLoadedAddresses, UiTransactionEncoding,
+    UiTransactionTokenBalance,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Signatures fetched per request, the most the RPC returns at once.
+const SIGNATURE_PAGE: usize = 1000;
+
+/// Status checks, two seconds apart, before a failed payout is given up on.
+const PAYOUT_STATUS_CHECKS: usize = 90;
+
+/// Where a user sends funds; deposits are credited to the registered wallet that paid for them.
+#[derive(Debug, Clone, Serialize)]
+pub struct DepositAddress {
+    pub currency: Currency,
+    pub address: String,
+    /// SPL mint for token deposits.
+    pub mint: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Withdrawal {
+    pub entry: JournalEntry,
+    pub signature: String,
+}
+
+/// User funds: balances, deposits found on-chain and withdrawals paid from the treasury.
+///
+/// The treasury is the backend keypair. SOL is deposited to its address and USDC to its
+/// associated token account.
+#[derive(Clone)]
+pub struct LedgerService {
+    pool: PgPool,
+    rpc_client: Arc<RpcClient>,
+    usdc_mint: Pubkey,
+}
+
+impl LedgerService {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_rpc_client(
+            pool,
+            RpcClient::new_with_commitment(
+                CONFIG.solana_rpc_url.clone(),
+                CommitmentConfig::finalized(),
+            ),
+        )
+    }
+
+    pub fn with_rpc_client(pool: PgPool, rpc_client: RpcClient) -> Self {
+        Self {
+            pool,
+            rpc_client: Arc::new(rpc_client),
+            usdc_mint: Pubkey::from_str(&CONFIG.usdc_mint)
+                .expect("USDC_MINT must be a valid public key"),
+        }
+    }
+
+    fn treasury(&self) -> Result<Arc<Keypair>, SolanaServiceError> {
+        backend_keypair().map_err(SolanaServiceError::NotConfigured)
+    }
+
+    fn deposit_pubkey(&self, currency: Currency) -> Result<Pubkey, SolanaServiceError> {
+        let treasury = self.treasury()?.pubkey();
+        Ok(match currency {
+            Currency::Sol => treasury,
+            Currency::Usdc => get_associated_token_address(&treasury, &self.usdc_mint),
+        })
+    }
+
+    pub fn deposit_addresses(&self) -> Result<Vec<DepositAddress>, DbError> {
+        Currency::ALL
+            .iter()
+            .map(|currency| {
+                Ok(DepositAddress {
+                    currency: *currency,
+                    address: self.deposit_pubkey(*currency)?.to_string(),
+                    mint: match currency {
+                        Currency::Sol => None,
+                        Currency::Usdc => Some(self.usdc_mint.to_string()),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    pub async fn balances(&self, user_id: &str) -> Result<Vec<Balance>, DbError> {
+        let client = self.pool.get().await?;
+        ledger::get_user_balances(&client, user_id).await
+    }
+
+    pub async fn entries(&self, user_id: &str, limit: i64) -> Result<Vec<JournalEntry>, DbError> {
+        let client = self.pool.get().await?;
+        ledger::get_user_entries(&client, user_id, limit).await
+    }
+
+    /// Debits the user and pays `amount` smallest units from the treasury to `destination`.
+    /// The debit is reversed if the payout cannot be sent.
+    pub async fn withdraw(
+        &self,
+        user_id: &str,
+        currency: Currency,
+        amount: u64,
+        destination: &str,
+    ) -> Result<Withdrawal, DbError> {
+        let units = i64::try_from(amount)
+            .ok()
+            .filter(|units| *units > 0)
+            .ok_or_else(|| DbError::ValidationError("Amount must be positive".into()))?;
+        let destination = Pubkey::from_str(destination).map_err(|_| {
+            DbError::ValidationError(format!("Invalid destination: {}", destination))
+        })?;
+        let treasury = self.treasury()?;
+
+        let lines = |sign: i64| {
+            vec![
+                JournalLine {
+                    account_id: ledger::user_account(user_id, currency),
+                    amount: -units * sign,
+                },
+                JournalLine {
+                    account_id: ledger::system_account(WITHDRAWALS_ACCOUNT, currency),
+                    amount: units * sign,
+                },
+            ]
+        };
+
+        // Debit first so the balance cannot be spent twice while the payout is in flight
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let mut entry = ledger::post_entry(
+            &tx,
+            ENTRY_WITHDRAWAL,
+            currency,
+            None,
+            Some(&format!("to {}", destination)),
+            lines(1),
+        )
+        .await?;
+        tx.commit().await?;
+
+        match self
+            .send_payout(&treasury, currency, amount, &destination)
+            .await
+        {
+            Ok(signature) => {
+                let reference = format!("withdrawal:{}", signature);
+                ledger::set_entry_reference(&client, &entry.id, &reference).await?;
+                entry.reference = Some(reference);
+                utils::log(&format!(
+                    "[Ledger] Withdrew {} {} units for {} in {}",
+                    amount,
+                    currency.as_str(),
+                    user_id,
+                    signature
+                ));
+                Ok(Withdrawal {
+                    entry,
+                    signature: signature.to_string(),
+                })
+            }
+            // The payout may still land, so the debit stays in place for manual review
+            Err(SolanaServiceError::Unconfirmed(e)) => {
+                utils::log(&format!("[Ledger] Withdrawal {}: {}", entry.id, e));
+                Err(DbError::ConnectionError(e))
+            }
+            Err(e) => {
+                let tx = client.transaction().await?;
+                ledger::post_entry(
+                    &tx,
+                    ENTRY_WITHDRAWAL_REVERSAL,
+                    currency,
+                    Some(&format!("reversal:{}", entry.id)),
+                    Some(&e.to_string()),
+                    lines(-1),
+                )
+                .await?;
+                tx.commit().await?;
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn send_payout(
+        &self,
+        treasury: &Keypair,
+        currency: Currency,
+        amount: u64,
+        destination: &Pubkey,
+    ) -> Result<Signature, SolanaServiceError> {
+        let instructions: Vec<Instruction> = match currency {
+            Currency::Sol => vec![system_instruction::transfer(
+                &treasury.pubkey(),
+                destination,
+                amount,
+            )],
+            Currency::Usdc => vec![
+                create_associated_token_account_idempotent(
+                    &treasury.pubkey(),
+                    destination,
+                    &self.usdc_mint,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    &get_associated_token_address(&treasury.pubkey(), &self.usdc_mint),
+                    &get_associated_token_address(destination, &self.usdc_mint),
+                    &treasury.pubkey(),
+                    &[],
+                    amount,
+                )
+                .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?,
+            ],
+        };
+
+        let recent_blockhash =
+            self.rpc_client.get_latest_blockhash().await.map_err(|e| {
+                SolanaServiceError::RpcError(format!("Failed to get blockhash: {}", e))
+            })?;
+        let transaction = SolanaTransaction::new_signed_with_payer(
+            &instructions,
+            Some(&treasury.pubkey()),
+            &[treasury],
+            recent_blockhash,
+        );
+        let error = match self
+            .rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await
+        {
+            Ok(signature) => return Ok(signature),
+            Err(e) => SolanaServiceError::RpcError(format!("Payout failed: {}", e)),
+        };
+
+        // A timed out send may still land, so only report failure once it no longer can
+        let signature = transaction.signatures[0];
+        for _ in 0..PAYOUT_STATUS_CHECKS {
+            match self.rpc_client.get_signature_status(&signature).await {
+                Ok(Some(Ok(()))) => return Ok(signature),
+                Ok(Some(Err(_))) => return Err(error),
+                _ => {}
+            }
+            let valid = self
+                .rpc_client
+                .is_blockhash_valid(&recent_blockhash, CommitmentConfig::processed())
+                .await
+                .unwrap_or(true);
+            if !valid {
+                // One last look in case it landed just before the blockhash expired
+                return match self.rpc_client.get_signature_status(&signature).await {
+                    Ok(Some(Ok(()))) => Ok(signature),
+                    _ => Err(error),
+                };
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+        Err(SolanaServiceError::Unconfirmed(format!(
+            "Payout {} has an unknown status",
+            signature
+        )))
+    }
+
+    /// Runs `reconcile` every `RECONCILE_INTERVAL_SECS`.
+    pub fn start_reconciler(&self) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(CONFIG.reconcile_interval_secs));
+            loop {
+                interval.tick().await;
+                match service.reconcile().await {
+                    Ok(0) => {}
+                    Ok(credited) => {
+                        utils::log(&format!("[Ledger] Credited {} deposit(s)", credited))
+                    }
+                    Err(e) => utils::log(&format!("[Ledger] Reconciliation failed: {}", e)),
+                }
+            }
+        })
+    }
+
+    /// Credits finalized deposits to every deposit address since the last pass.
+    pub async fn reconcile(&self) -> Result<usize, DbError> {
+        let mut credited = 0;
+        for currency in Currency::ALL {
+            credited += self.reconcile_address(currency).await?;
+        }
+        Ok(credited)
+    }
+
+    async fn reconcile_address(&self, currency: Currency) -> Result<usize, DbError> {
+        let address = self.deposit_pubkey(currency)?;
+        let address_str = address.to_string();
+        let client = self.pool.get().await?;
+
+        // The first pass only records where the address stands, so history from before the
+        // ledger existed is not credited
+        let Some(cursor) = ledger::get_deposit_cursor(&client, &address_str).await? else {
+            let newest = self.signatures(&address, None, None, 1).await?;
+            let start = newest.first().map(|status| status.signature.as_str());
+            ledger::set_deposit_cursor(&client, &address_str, start).await?;
+            utils::log(&format!(
+                "[Ledger] Crediting {} deposits to {} after {}",
+                currency.as_str(),
+                address_str,
+                start.unwrap_or("its first transaction")
+            ));
+            return Ok(0);
+        };
+        let until = cursor
+            .last_signature
+            .map(|signature| Signature::from_str(&signature))
+            .transpose()
+            .map_err(|e| DbError::QueryError(format!("Invalid deposit cursor: {}", e)))?;
+
+        // Pages come newest first, so page back until the cursor is reached
+        let mut signatures = Vec::new();
+        let mut before = None;
+        loop {
+            let page = self
+                .signatures(&address, before, until, SIGNATURE_PAGE)
+                .await?;
+            let more = page.len() == SIGNATURE_PAGE;
+            signatures.extend(page);
+            if !more {
+                break;
+            }
+            before = signatures
+                .last()
+                .map(|status| Signature::from_str(&status.signature))
+                .transpose()
+                .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?;
+        }
+
+        // Walk them oldest first so the cursor only moves forward
+        let mut credited = 0;
+        for status in signatures.into_iter().rev() {
+            let reference = format!("deposit:{}:{}", address_str, status.signature);
+            if status.err.is_none()
+                && !ledger::entry_exists(&client, &reference).await?
+                && self
+                    .credit_deposit(&address, currency, &status.signature, &reference)
+                    .await?
+            {
+                credited += 1;
+            }
+            ledger::set_deposit_cursor(&client, &address_str, Some(&status.signature)).await?;
+        }
+        Ok(credited)
+    }
+
+    // Finalized signatures for `address` older than `before` and newer than `until`, newest first
+    async fn signatures(
+        &self,
+        address: &Pubkey,
+        before: Option<Signature>,
+        until: Option<Signature>,
+        limit: usize,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, DbError> {
+        Ok(self
+            .rpc_client
+            .get_signatures_for_address_with_config(
+                address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until,
+                    limit: Some(limit),
+                    commitment: Some(CommitmentConfig::finalized()),
+                },
+            )
+            .await
+            .map_err(|e| {
+                SolanaServiceError::RpcError(format!("Failed to get signatures: {}", e))
+            })?)
+    }
+
+    // Credits one deposit transaction; false if it paid nothing in or came from an unknown wallet
+    async fn credit_deposit(
+        &self,
+        address: &Pubkey,
+        currency: Currency,
+        signature: &str,
+        reference: &str,
+    ) -> Result<bool, DbError> {
+        let parsed_signature = Signature::from_str(signature)
+            .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?;
+        let transaction = self
+            .rpc_client
+            .get_transaction_with_config(
+                &parsed_signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::finalized()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await
+            .map_err(|e| {
+                SolanaServiceError::RpcError(format!("Failed to get transaction: {}", e))
+            })?;
+
+        let Some((payer, amount)) = deposit_from(&transaction, address, currency, &self.usdc_mint)
+        else {
+            return Ok(false);
+        };
+        let mut client = self.pool.get().await?;
+        let user = match client.user_by_wallet(&payer.to_string()).await {
+            Ok(user) => user,
+            Err(DbError::NotFound(_)) => {
+                utils::log(&format!(
+                    "[Ledger] Deposit {} from unregistered wallet {} left uncredited",
+                    signature, payer
+                ));
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(user_id) = user.id else {
+            return Ok(false);
+        };
+        let units = i64::try_from(amount)
+            .map_err(|_| DbError::ValidationError("Deposit amount is too large".into()))?;
+
+        let tx = client.transaction().await?;
+        ledger::post_entry(
+            &tx,
+            ENTRY_DEPOSIT,
+            currency,
+            Some(reference),
+            Some(&format!("from {}", payer)),
+            vec![
+                JournalLine {
+                    account_id: ledger::system_account(DEPOSITS_ACCOUNT, currency),
+                    amount: -units,
+                },
+                JournalLine {
+                    account_id: ledger::user_account(&user_id, currency),
+                    amount: units,
+                },
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+/// Fee payer and amount received by `address` in a successful transaction, if any.
+fn deposit_from(
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+    address: &Pubkey,
+    currency: Currency,
+    usdc_mint: &Pubkey,
+) -> Option<(Pubkey, u64)> {
+    let meta = transaction.transaction.meta.as_ref()?;
+    if meta.err.is_some() {
+        return None;
+    }
+    let decoded = transaction.transaction.transaction.decode()?;
+
+    // Account indexes cover the static keys followed by any addresses loaded from lookup tables
+    let mut keys: Vec<Pubkey> = decoded.message.static_account_keys().to_vec();
+    let loaded: Option<UiLoadedAddresses> = meta.loaded_addresses.clone().into();
+    if let Some(loaded) = loaded {
+        for key in loaded.writable.iter().chain(loaded.readonly.iter()) {
+            keys.push(Pubkey::from_str(key).ok()?);
+        }
+    }
+    let payer = *keys.first()?;
+    let index = keys.iter().position(|key| key == address)?;
+
+    let received = match currency {
+        Currency::Sol => meta
+            .post_balances
+            .get(index)?
+            .checked_sub(*meta.pre_balances.get(index)?)?,
+        Currency::Usdc => {
+            let balance = |balances: Option<Vec<UiTransactionTokenBalance>>| {
+                balances
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|b| b.account_index as usize == index && b.mint == usdc_mint.to_string())
+                    .and_then(|b| b.ui_token_amount.amount.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            let pre = balance(meta.pre_token_balances.clone().into());
+            let post = balance(meta.post_token_balances.clone().into());
+            post.checked_sub(pre)?
+        }
+    };
+    (received > 0).then_some((payer, received))
+}
@@ -0,0 +1,689 @@
+use crate::db::markets::{self, Bet, Market, MARKET_CLOSED, MARKET_OPEN, MARKET_RESOLVED};
+use crate::db::repo::{MarketRepo, Store, UnitOfWork};
+use crate::db::{DbError, PgPool};
+use crate::services::solana_service::{
+    SolanaRpc, SolanaService, SolanaServiceError, UnsignedTransaction,
+};
+use crate::utils;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+pub const MIN_OUTCOMES: usize = 2;
+pub const MAX_OUTCOMES: usize = 16;
+
+#[derive(Debug, Serialize)]
+pub struct MarketResolution {
+    pub market: Market,
+    pub bets: Vec<Bet>,
+}
+
+/// Winnings paid out of a market escrow.
+#[derive(Debug, Serialize)]
+pub struct Claim {
+    pub market_id: String,
+    /// Smallest units: lamports or raw token amount.
+    pub amount: i64,
+    pub signature: String,
+    pub bets: Vec<Bet>,
+}
+
+fn authorize(market: &Market, user_id: &str) -> Result<(), DbError> {
+    if market.creator_id == user_id {
+        Ok(())
+    } else {
+        Err(DbError::Forbidden(format!(
+            "User {} may not close or resolve this market",
+            user_id
+        )))
+    }
+}
+
+/// Parimutuel payout for every bet once `winning_outcome` is known.
+///
+/// Winners share the whole pot in proportion to their stake, rounded down to the lamport.
+/// If nobody backed the winning outcome every bet is refunded instead.
+pub fn compute_payouts(bets: &[Bet], winning_outcome: i16) -> Vec<i64> {
+    let total: i128 = bets.iter().map(|bet| bet.amount as i128).sum();
+    let winning_pool: i128 = bets
+        .iter()
+        .filter(|bet| bet.outcome_index == winning_outcome)
+        .map(|bet| bet.amount as i128)
+        .sum();
+
+    bets.iter()
+        .map(|bet| {
+            if winning_pool == 0 {
+                bet.amount
+            } else if bet.outcome_index == winning_outcome {
+                (bet.amount as i128 * total / winning_pool) as i64
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+pub async fn create_market(
+    store: &mut impl Store,
+    event_name: &str,
+    outcomes: &[String],
+    mint: Option<&str>,
+    creator_id: &str,
+) -> Result<Market, DbError> {
+    let event_name = event_name.trim();
+    if event_name.is_empty() {
+        return Err(DbError::ValidationError(
+            "Event name cannot be empty".into(),
+        ));
+    }
+    if outcomes.len() < MIN_OUTCOMES || outcomes.len() > MAX_OUTCOMES {
+        return Err(DbError::ValidationError(format!(
+            "A market needs between {} and {} outcomes",
+            MIN_OUTCOMES, MAX_OUTCOMES
+        )));
+    }
+    let outcomes: Vec<String> = outcomes.iter().map(|o| o.trim().to_string()).collect();
+    if outcomes.iter().any(|o| o.is_empty()) {
+        return Err(DbError::ValidationError("Outcomes cannot be empty".into()));
+    }
+    for (i, outcome) in outcomes.iter().enumerate() {
+        if outcomes[..i]
+            .iter()
+            .any(|o| o.eq_ignore_ascii_case(outcome))
+        {
+            return Err(DbError::ValidationError(format!(
+                "Duplicate outcome: {}",
+                outcome
+            )));
+        }
+    }
+
+    if let Some(mint) = mint {
+        Pubkey::from_str(mint)
+            .map_err(|_| DbError::ValidationError(format!("Invalid mint: {}", mint)))?;
+    }
+
+    let work = store.begin_work().await?;
+    let market = work
+        .insert_market(event_name, creator_id, mint, &outcomes)
+        .await?;
+    work.commit().await?;
+
+    utils::log(&format!(
+        "[Markets] Market {} created with {} outcomes",
+        market.id,
+        market.outcomes.len()
+    ));
+    Ok(market)
+}
+
+pub async fn list_markets(pool: &PgPool) -> Result<Vec<Market>, DbError> {
+    let client = pool.get().await?;
+    markets::get_markets(&client).await
+}
+
+pub async fn get_market(pool: &PgPool, market_id: &str) -> Result<Market, DbError> {
+    let client = pool.get().await?;
+    markets::get_market(&client, market_id).await
+}
+
+fn check_outcome(market: &Market, outcome_index: i16) -> Result<(), DbError> {
+    if market.outcomes.iter().any(|o| o.index == outcome_index) {
+        Ok(())
+    } else {
+        Err(DbError::ValidationError(format!(
+            "Market {} has no outcome {}",
+            market.id, outcome_index
+        )))
+    }
+}
+
+fn check_open(market: &Market) -> Result<(), DbError> {
+    if market.status == MARKET_OPEN {
+        Ok(())
+    } else {
+        Err(DbError::ValidationError(format!(
+            "Market {} is not open for bets",
+            market.id
+        )))
+    }
+}
+
+/// Builds the escrow deposit for a bet, to be signed and submitted by the bettor's wallet.
+pub async fn prepare_bet(
+    repo: &impl MarketRepo,
+    solana: &SolanaService<impl SolanaRpc>,
+    market_id: &str,
+    wallet_address: &str,
+    outcome_index: u8,
+    amount: u64,
+) -> Result<UnsignedTransaction, DbError> {
+    if amount == 0 {
+        return Err(DbError::ValidationError("Amount must be positive".into()));
+    }
+    if i64::try_from(amount).is_err() {
+        return Err(DbError::ValidationError("Amount is too large".into()));
+    }
+
+    let market = repo.market_by_id(market_id).await?;
+    check_open(&market)?;
+    check_outcome(&market, outcome_index as i16)?;
+
+    // Catch an underfunded wallet before it signs; fees are left for the wallet to check
+    if market.mint.is_none() && solana.get_balance(wallet_address).await? < amount {
+        return Err(DbError::ValidationError(format!(
+            "Wallet {} has fewer than {} lamports",
+            wallet_address, amount
+        )));
+    }
+
+    Ok(solana
+        .build_deposit_transaction(wallet_address, market.mint.as_deref(), amount)
+        .await?)
+}
+
+/// Records a bet once its escrow deposit is confirmed on-chain, staking the deposited amount.
+pub async fn confirm_bet(
+    store: &mut impl Store,
+    solana: &SolanaService<impl SolanaRpc>,
+    market_id: &str,
+    user_id: &str,
+    wallet_address: &str,
+    outcome_index: u8,
+    tx_signature: &str,
+) -> Result<Bet, DbError> {
+    let market = store.begin_work().await?.market_by_id(market_id).await?;
+    let deposit = solana
+        .confirm_deposit(
+            tx_signature,
+            wallet_address,
+            market_id,
+            market.mint.as_deref(),
+        )
+        .await?;
+    let amount = i64::try_from(deposit.amount)
+        .map_err(|_| DbError::ValidationError("Amount is too large".into()))?;
+
+    let work = store.begin_work().await?;
+
+    // Locking the market keeps a concurrent close from slipping in before the bet lands
+    let market = work.lock_market(market_id).await?;
+    check_open(&market)?;
+    let outcome_index = outcome_index as i16;
+    check_outcome(&market, outcome_index)?;
+    if work.bet_signature_exists(tx_signature).await? {
+        return Err(DbError::Conflict(format!(
+            "Transaction {} is already recorded as a bet",
+            tx_signature
+        )));
+    }
+
+    let bet = work
+        .insert_bet(Bet {
+            id: None,
+            market_id: market_id.to_string(),
+            user_id: user_id.to_string(),
+            outcome_index,
+            amount,
+            payout: None,
+            tx_signature: Some(tx_signature.to_string()),
+            created_at: None,
+            claimed_at: None,
+            claim_signature: None,
+        })
+        .await?;
+    work.commit().await?;
+
+    utils::log(&format!(
+        "[Markets] {} on outcome {} of market {} confirmed by {}",
+        amount, outcome_index, market_id, tx_signature
+    ));
+    Ok(bet)
+}
+
+/// Stops accepting bets on an open market.
+pub async fn close_market(
+    repo: &impl MarketRepo,
+    market_id: &str,
+    user_id: &str,
+) -> Result<Market, DbError> {
+    let market = repo.market_by_id(market_id).await?;
+    authorize(&market, user_id)?;
+
+    repo.transition_market(market_id, MARKET_OPEN, MARKET_CLOSED, None)
+        .await?;
+    utils::log(&format!(
+        "[Markets] Market {} closed by {}",
+        market_id, user_id
+    ));
+    repo.market_by_id(market_id).await
+}
+
+/// Settles a closed market on `outcome_index` and records the payout of every bet.
+pub async fn resolve_market(
+    store: &mut impl Store,
+    market_id: &str,
+    user_id: &str,
+    outcome_index: u8,
+) -> Result<MarketResolution, DbError> {
+    let work = store.begin_work().await?;
+
+    let market = work.lock_market(market_id).await?;
+    authorize(&market, user_id)?;
+    let outcome_index = outcome_index as i16;
+    check_outcome(&market, outcome_index)?;
+
+    work.transition_market(
+        market_id,
+        MARKET_CLOSED,
+        MARKET_RESOLVED,
+        Some(outcome_index),
+    )
+    .await?;
+
+    let mut bets = work.market_bets(market_id).await?;
+    let payouts = compute_payouts(&bets, outcome_index);
+    for (bet, payout) in bets.iter_mut().zip(payouts) {
+        if let Some(id) = &bet.id {
+            work.set_bet_payout(id, payout).await?;
+        }
+        bet.payout = Some(payout);
+    }
+
+    let market = work.market_by_id(market_id).await?;
+    work.commit().await?;
+
+    utils::log(&format!(
+        "[Markets] Market {} resolved on outcome {} across {} bet(s)",
+        market_id,
+        outcome_index,
+        bets.len()
+    ));
+    Ok(MarketResolution { market, bets })
+}
+
+/// Pays a user's unclaimed winnings on a resolved market out of its escrow to their wallet.
+///
+/// The bets are claimed before the withdrawal is sent so a second claim cannot pay them
+/// twice. A withdrawal that fails releases them again; one whose outcome is unknown keeps
+/// them claimed until it is looked into.
+pub async fn claim_winnings(
+    store: &mut impl Store,
+    solana: &SolanaService<impl SolanaRpc>,
+    market_id: &str,
+    user_id: &str,
+    wallet_address: &str,
+) -> Result<Claim, DbError> {
+    let work = store.begin_work().await?;
+    let market = work.lock_market(market_id).await?;
+    if market.status != MARKET_RESOLVED {
+        return Err(DbError::ValidationError(format!(
+            "Market {} is not resolved",
+            market_id
+        )));
+    }
+    let bets = work.claim_bets(market_id, user_id).await?;
+    let amount: i64 = bets.iter().filter_map(|bet| bet.payout).sum();
+    if amount <= 0 {
+        return Err(DbError::ValidationError(format!(
+            "Nothing to claim on market {}",
+            market_id
+        )));
+    }
+    work.commit().await?;
+
+    let bet_ids: Vec<String> = bets.iter().filter_map(|bet| bet.id.clone()).collect();
+    let withdrawal = solana
+        .withdraw_from_escrow(market.mint.as_deref(), wallet_address, amount as u64)
+        .await;
+    let withdrawal = match withdrawal {
+        Ok(withdrawal) => withdrawal,
+        Err(SolanaServiceError::Unconfirmed(e)) => {
+            utils::log(&format!(
+                "[Markets] Claim of {} on market {} by {} is unconfirmed: {}",
+                amount, market_id, user_id, e
+            ));
+            return Err(DbError::Unconfirmed(format!(
+                "Withdrawal of {} is unconfirmed; the claim is kept until it is checked",
+                amount
+            )));
+        }
+        Err(e) => {
+            let work = store.begin_work().await?;
+            work.release_claims(&bet_ids).await?;
+            work.commit().await?;
+            return Err(e.into());
+        }
+    };
+
+    let work = store.begin_work().await?;
+    work.set_claim_signature(&bet_ids, &withdrawal.signature)
+        .await?;
+    work.commit().await?;
+
+    utils::log(&format!(
+        "[Markets] {} claimed {} on market {} by {}",
+        user_id, amount, market_id, withdrawal.signature
+    ));
+    let bets = bets
+        .into_iter()
+        .map(|bet| Bet {
+            claim_signature: Some(withdrawal.signature.clone()),
+            ..bet
+        })
+        .collect();
+    Ok(Claim {
+        market_id: market_id.to_string(),
+        amount,
+        signature: withdrawal.signature,
+        bets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::MemoryStore;
+    use crate::services::mock_rpc::MockRpc;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use solana_sdk::signature::{Keypair, Signature};
+    use solana_sdk::system_instruction::SystemInstruction;
+    use solana_sdk::transaction::Transaction;
+    use std::sync::Arc;
+
+    const CREATOR: &str = "creator";
+
+    fn solana(rpc: &MockRpc) -> SolanaService<MockRpc> {
+        SolanaService::with_rpc_client(rpc.clone(), Some(Arc::new(Keypair::new())))
+    }
+
+    async fn market(store: &mut MemoryStore) -> Market {
+        create_market(
+            store,
+            "Will it rain?",
+            &["Yes".to_string(), "No".to_string()],
+            None,
+            CREATOR,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn bet(
+        store: &mut MemoryStore,
+        market_id: &str,
+        user_id: &str,
+        outcome: i16,
+        amount: i64,
+    ) {
+        let work = store.begin_work().await.unwrap();
+        work.insert_bet(Bet {
+            id: None,
+            market_id: market_id.to_string(),
+            user_id: user_id.to_string(),
+            outcome_index: outcome,
+            amount,
+            payout: None,
+            tx_signature: None,
+            created_at: None,
+            claimed_at: None,
+            claim_signature: None,
+        })
+        .await
+        .unwrap();
+        work.commit().await.unwrap();
+    }
+
+    /// Signs nothing: the mock confirms whatever it is handed, as the wallet would have sent it.
+    async fn deposit(
+        store: &MemoryStore,
+        solana: &SolanaService<MockRpc>,
+        rpc: &MockRpc,
+        market_id: &str,
+        wallet: &Pubkey,
+        amount: u64,
+    ) -> String {
+        rpc.set_balance(wallet, amount);
+        let unsigned = prepare_bet(store, solana, market_id, &wallet.to_string(), 0, amount)
+            .await
+            .unwrap();
+        let bytes = BASE64.decode(unsigned.transaction).unwrap();
+        let transaction: Transaction = bincode::deserialize(&bytes).unwrap();
+        rpc.land(transaction).to_string()
+    }
+
+    /// Winners 300 and 100 on outcome 1 share a 500 pot; the bet on 0 loses.
+    async fn resolved_market(store: &mut MemoryStore) -> Market {
+        let market = market(store).await;
+        bet(store, &market.id, "a", 0, 100).await;
+        bet(store, &market.id, "b", 1, 300).await;
+        bet(store, &market.id, "c", 1, 100).await;
+        close_market(store, &market.id, CREATOR).await.unwrap();
+        resolve_market(store, &market.id, CREATOR, 1).await.unwrap();
+        market
+    }
+
+    #[tokio::test]
+    async fn prepare_bet_builds_a_deposit_into_the_escrow() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = market(&mut store).await;
+        let wallet = Pubkey::new_unique();
+        rpc.set_balance(&wallet, 1_000);
+
+        let unsigned = prepare_bet(&store, &solana, &market.id, &wallet.to_string(), 1, 600)
+            .await
+            .unwrap();
+        assert_eq!(
+            unsigned.escrow,
+            solana.escrow_address(None).unwrap().to_string()
+        );
+        let transaction: Transaction =
+            bincode::deserialize(&BASE64.decode(unsigned.transaction).unwrap()).unwrap();
+        assert_eq!(transaction.message.account_keys[0], wallet);
+    }
+
+    #[tokio::test]
+    async fn prepare_bet_refuses_bets_that_cannot_land() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = market(&mut store).await;
+        let wallet = Pubkey::new_unique().to_string();
+        rpc.set_balance(&Pubkey::from_str(&wallet).unwrap(), 500);
+
+        let underfunded = prepare_bet(&store, &solana, &market.id, &wallet, 0, 501).await;
+        assert!(matches!(underfunded, Err(DbError::ValidationError(_))));
+        let unknown_outcome = prepare_bet(&store, &solana, &market.id, &wallet, 2, 100).await;
+        assert!(matches!(unknown_outcome, Err(DbError::ValidationError(_))));
+
+        close_market(&store, &market.id, CREATOR).await.unwrap();
+        let closed = prepare_bet(&store, &solana, &market.id, &wallet, 0, 100).await;
+        assert!(matches!(closed, Err(DbError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn confirm_bet_records_a_deposit_once() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = market(&mut store).await;
+        let wallet = Pubkey::new_unique();
+        let signature = deposit(&store, &solana, &rpc, &market.id, &wallet, 250).await;
+
+        let bet = confirm_bet(
+            &mut store,
+            &solana,
+            &market.id,
+            "bettor",
+            &wallet.to_string(),
+            0,
+            &signature,
+        )
+        .await
+        .unwrap();
+        assert_eq!(bet.amount, 250);
+        let market = store.market_by_id(&market.id).await.unwrap();
+        assert_eq!(market.outcomes[0].pool, 250);
+        assert_eq!(market.total_pot, 250);
+
+        let again = confirm_bet(
+            &mut store,
+            &solana,
+            &market.id,
+            "bettor",
+            &wallet.to_string(),
+            0,
+            &signature,
+        )
+        .await;
+        assert!(matches!(again, Err(DbError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn confirm_bet_refuses_what_is_not_a_deposit() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = market(&mut store).await;
+        let wallet = Pubkey::new_unique();
+        let signature = deposit(&store, &solana, &rpc, &market.id, &wallet, 250).await;
+
+        let unseen = Signature::new_unique().to_string();
+        let unconfirmed = confirm_bet(
+            &mut store,
+            &solana,
+            &market.id,
+            "bettor",
+            &wallet.to_string(),
+            0,
+            &unseen,
+        )
+        .await;
+        // Not seen yet is worth retrying, unlike a deposit that is wrong
+        assert!(matches!(unconfirmed, Err(DbError::Unconfirmed(_))));
+
+        // Another wallet's deposit does not fund this bettor's bet
+        let other_wallet = Pubkey::new_unique().to_string();
+        let stolen = confirm_bet(
+            &mut store,
+            &solana,
+            &market.id,
+            "thief",
+            &other_wallet,
+            0,
+            &signature,
+        )
+        .await;
+        assert!(matches!(stolen, Err(DbError::ValidationError(_))));
+        assert!(store.market_bets(&market.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolve_market_pays_winners_in_proportion() {
+        let mut store = MemoryStore::new();
+        let market = market(&mut store).await;
+        bet(&mut store, &market.id, "a", 0, 100).await;
+        bet(&mut store, &market.id, "b", 1, 300).await;
+        bet(&mut store, &market.id, "c", 1, 100).await;
+
+        let forbidden = resolve_market(&mut store, &market.id, "a", 1).await;
+        assert!(matches!(forbidden, Err(DbError::Forbidden(_))));
+        let still_open = resolve_market(&mut store, &market.id, CREATOR, 1).await;
+        assert!(matches!(still_open, Err(DbError::ValidationError(_))));
+
+        close_market(&store, &market.id, CREATOR).await.unwrap();
+        let resolution = resolve_market(&mut store, &market.id, CREATOR, 1)
+            .await
+            .unwrap();
+        assert_eq!(resolution.market.status, MARKET_RESOLVED);
+        assert_eq!(resolution.market.resolved_outcome, Some(1));
+        let payouts: Vec<Option<i64>> = store
+            .market_bets(&market.id)
+            .await
+            .unwrap()
+            .iter()
+            .map(|bet| bet.payout)
+            .collect();
+        assert_eq!(payouts, vec![Some(0), Some(375), Some(125)]);
+
+        let twice = resolve_market(&mut store, &market.id, CREATOR, 0).await;
+        assert!(matches!(twice, Err(DbError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn claim_withdraws_winnings_from_the_escrow_once() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = resolved_market(&mut store).await;
+        let wallet = Pubkey::new_unique();
+
+        let claim = claim_winnings(&mut store, &solana, &market.id, "b", &wallet.to_string())
+            .await
+            .unwrap();
+        assert_eq!(claim.amount, 375);
+        let sent = rpc.sent();
+        assert_eq!(sent.len(), 1);
+        // A plain transfer out of the escrow, signed by its authority
+        let instruction = &sent[0].message.instructions[0];
+        assert_eq!(
+            bincode::deserialize::<SystemInstruction>(&instruction.data).unwrap(),
+            SystemInstruction::Transfer { lamports: 375 }
+        );
+        let escrow = solana.escrow_address(None).unwrap();
+        assert_eq!(sent[0].message.account_keys[0], escrow);
+        assert_eq!(
+            sent[0].message.account_keys[instruction.accounts[0] as usize],
+            escrow
+        );
+        assert_eq!(
+            sent[0].message.account_keys[instruction.accounts[1] as usize],
+            wallet
+        );
+
+        let again = claim_winnings(&mut store, &solana, &market.id, "b", &wallet.to_string()).await;
+        assert!(matches!(again, Err(DbError::ValidationError(_))));
+        let loser = claim_winnings(&mut store, &solana, &market.id, "a", &wallet.to_string()).await;
+        assert!(matches!(loser, Err(DbError::ValidationError(_))));
+        assert_eq!(rpc.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn claim_is_released_only_when_the_withdrawal_failed() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = resolved_market(&mut store).await;
+        let wallet = Pubkey::new_unique().to_string();
+
+        rpc.fail_sends();
+        let failed = claim_winnings(&mut store, &solana, &market.id, "b", &wallet).await;
+        assert!(matches!(failed, Err(DbError::ConnectionError(_))));
+        let bets = store.market_bets(&market.id).await.unwrap();
+        assert!(bets.iter().all(|bet| bet.claimed_at.is_none()));
+
+        rpc.drop_sends();
+        let unknown = claim_winnings(&mut store, &solana, &market.id, "b", &wallet).await;
+        assert!(matches!(unknown, Err(DbError::Unconfirmed(_))));
+        let kept = claim_winnings(&mut store, &solana, &market.id, "b", &wallet).await;
+        assert!(matches!(kept, Err(DbError::ValidationError(_))));
+        assert_eq!(rpc.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn claim_requires_a_resolved_market() {
+        let mut store = MemoryStore::new();
+        let rpc = MockRpc::new();
+        let solana = solana(&rpc);
+        let market = market(&mut store).await;
+        bet(&mut store, &market.id, "a", 0, 100).await;
+
+        let open = claim_winnings(&mut store, &solana, &market.id, "a", "wallet").await;
+        assert!(matches!(open, Err(DbError::ValidationError(_))));
+        assert!(rpc.sent().is_empty());
+    }
+}
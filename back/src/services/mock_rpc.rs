@@ -0,0 +1,156 @@
+//! An in-memory cluster standing in for `RpcClient` in tests. Transactions are "landed" by the
+//! test, or by sending them, and are then confirmed at the finalized commitment. Clones share
+//! the cluster, so a test keeps one to inspect what a service sent.
+
+use crate::services::solana_service::{SolanaRpc, SolanaServiceError};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction,
+    EncodedTransactionWithStatusMeta, TransactionBinaryEncoding, TransactionConfirmationStatus,
+    TransactionStatus,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum SendOutcome {
+    #[default]
+    Land,
+    // Lands with an error
+    Fail,
+    // Never reaches the cluster
+    Drop,
+}
+
+#[derive(Default)]
+struct MockState {
+    balances: HashMap<Pubkey, u64>,
+    landed: HashMap<Signature, (Transaction, Option<TransactionError>)>,
+    sent: Vec<Transaction>,
+    sends: SendOutcome,
+}
+
+#[derive(Clone, Default)]
+pub struct MockRpc {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockRpc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_balance(&self, pubkey: &Pubkey, lamports: u64) {
+        self.lock().balances.insert(*pubkey, lamports);
+    }
+
+    /// Records a transaction as confirmed under a fresh signature, returned for lookups.
+    pub fn land(&self, transaction: Transaction) -> Signature {
+        let signature = Signature::new_unique();
+        self.lock().landed.insert(signature, (transaction, None));
+        signature
+    }
+
+    /// Makes every later send land with an error.
+    pub fn fail_sends(&self) {
+        self.lock().sends = SendOutcome::Fail;
+    }
+
+    /// Makes every later send time out without the cluster ever seeing it.
+    pub fn drop_sends(&self) {
+        self.lock().sends = SendOutcome::Drop;
+    }
+
+    /// Transactions sent so far, landed or not.
+    pub fn sent(&self) -> Vec<Transaction> {
+        self.lock().sent.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl SolanaRpc for MockRpc {
+    async fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, SolanaServiceError> {
+        Ok(self.lock().balances.get(pubkey).copied().unwrap_or(0))
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, SolanaServiceError> {
+        Ok(Hash::new_unique())
+    }
+
+    async fn get_signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<TransactionStatus>, SolanaServiceError> {
+        Ok(self
+            .lock()
+            .landed
+            .get(signature)
+            .map(|(_, err)| TransactionStatus {
+                slot: 1,
+                confirmations: None,
+                status: err.clone().map_or(Ok(()), Err),
+                err: err.clone(),
+                confirmation_status: Some(TransactionConfirmationStatus::Finalized),
+            }))
+    }
+
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, SolanaServiceError> {
+        let state = self.lock();
+        let (transaction, _) = state.landed.get(signature).ok_or_else(|| {
+            SolanaServiceError::RpcError(format!("Transaction {} not found", signature))
+        })?;
+        let bytes = bincode::serialize(transaction)
+            .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?;
+        Ok(EncodedConfirmedTransactionWithStatusMeta {
+            slot: 1,
+            transaction: EncodedTransactionWithStatusMeta {
+                transaction: EncodedTransaction::Binary(
+                    BASE64.encode(bytes),
+                    TransactionBinaryEncoding::Base64,
+                ),
+                meta: None,
+                version: None,
+            },
+            block_time: None,
+        })
+    }
+
+    async fn send_and_confirm_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Signature, SolanaServiceError> {
+        let mut state = self.lock();
+        state.sent.push(transaction.clone());
+        let signature = transaction.signatures[0];
+        match state.sends {
+            SendOutcome::Land => {
+                state.landed.insert(signature, (transaction.clone(), None));
+                Ok(signature)
+            }
+            SendOutcome::Fail => {
+                let err = TransactionError::InsufficientFundsForFee;
+                state
+                    .landed
+                    .insert(signature, (transaction.clone(), Some(err.clone())));
+                Err(SolanaServiceError::RpcError(format!(
+                    "Transaction failed: {}",
+                    err
+                )))
+            }
+            SendOutcome::Drop => Err(SolanaServiceError::RpcError(
+                "Transaction failed: timed out".into(),
+            )),
+        }
+    }
+}
@@ -1 +1,14 @@
-pub mod solana_service; 
\ No newline at end of file
+pub mod amm;
+pub mod auth;
+pub mod events;
+pub mod idea_stream;
+pub mod ledger;
+pub mod markets;
+#[cfg(test)]
+pub mod mock_rpc;
+pub mod order_book;
+pub mod resolution;
+pub mod solana_service;
+pub mod staking;
+pub mod token;
+pub mod trending;
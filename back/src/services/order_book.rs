@@ -0,0 +1,870 @@
+use crate::db::ledger::{self, TRADE_CURRENCY};
+use crate::db::repo::{self, Database, IdeaRepo, OrderRepo, Store, UnitOfWork};
+use crate::db::{self, DbError, Idea, OrderHold, PgPool, Rate, Transaction};
+use crate::decimal::{Decimal, Rounding};
+use crate::services::events::{Event, EventBus};
+use crate::utils;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Order {
+    pub id: u64,
+    pub idea_id: String,
+    pub user_id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub remaining: Decimal,
+    pub status: OrderStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Order {
+    fn fill(&mut self, amount: Decimal) {
+        self.remaining -= amount;
+        if self.remaining.is_zero() {
+            self.status = OrderStatus::Filled;
+        } else {
+            self.status = OrderStatus::PartiallyFilled;
+        }
+    }
+}
+
+/// A single match between an incoming order and a resting one, executed at the resting price.
+#[derive(Debug, Clone, Serialize)]
+pub struct Fill {
+    pub buy_order_id: u64,
+    pub sell_order_id: u64,
+    pub buyer_id: String,
+    pub seller_id: String,
+    pub price: Decimal,
+    pub amount: Decimal,
+}
+
+/// Result of submitting an order to a book.
+#[derive(Debug)]
+pub struct MatchResult {
+    pub order: Order,
+    pub fills: Vec<Fill>,
+    /// Resting orders that left the book, either fully filled or cancelled to prevent a self-trade.
+    pub closed: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceLevel {
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub orders: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderBookSnapshot {
+    pub idea_id: String,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+/// Limit order book for a single idea with price-time priority.
+#[derive(Clone)]
+pub struct OrderBook {
+    idea_id: String,
+    bids: BTreeMap<Decimal, VecDeque<Order>>,
+    asks: BTreeMap<Decimal, VecDeque<Order>>,
+}
+
+impl OrderBook {
+    pub fn new(idea_id: String) -> Self {
+        Self {
+            idea_id,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        }
+    }
+
+    /// Matches an incoming limit order against the opposite side and rests any remainder.
+    pub fn submit(&mut self, mut order: Order) -> MatchResult {
+        let mut fills = Vec::new();
+        let mut closed = Vec::new();
+
+        while !order.remaining.is_zero() {
+            let best = match order.side {
+                Side::Buy => self.asks.first_entry(),
+                Side::Sell => self.bids.last_entry(),
+            };
+            let Some(mut level) = best else {
+                break;
+            };
+
+            let level_price = *level.key();
+            let crosses = match order.side {
+                Side::Buy => level_price <= order.price,
+                Side::Sell => level_price >= order.price,
+            };
+            if !crosses {
+                break;
+            }
+
+            let queue = level.get_mut();
+            while !order.remaining.is_zero() {
+                let Some(resting) = queue.front_mut() else {
+                    break;
+                };
+
+                // Never let a user trade with themselves: drop their resting order instead.
+                if resting.user_id == order.user_id {
+                    resting.status = OrderStatus::Cancelled;
+                    closed.push(resting.id);
+                    queue.pop_front();
+                    continue;
+                }
+
+                let amount = order.remaining.min(resting.remaining);
+                order.fill(amount);
+                resting.fill(amount);
+
+                let (buy, sell) = match order.side {
+                    Side::Buy => (&order, &*resting),
+                    Side::Sell => (&*resting, &order),
+                };
+                fills.push(Fill {
+                    buy_order_id: buy.id,
+                    sell_order_id: sell.id,
+                    buyer_id: buy.user_id.clone(),
+                    seller_id: sell.user_id.clone(),
+                    price: level_price,
+                    amount,
+                });
+
+                if resting.status == OrderStatus::Filled {
+                    closed.push(resting.id);
+                    queue.pop_front();
+                }
+            }
+
+            if queue.is_empty() {
+                level.remove();
+            }
+        }
+
+        if !order.remaining.is_zero() {
+            let side = match order.side {
+                Side::Buy => &mut self.bids,
+                Side::Sell => &mut self.asks,
+            };
+            side.entry(order.price)
+                .or_default()
+                .push_back(order.clone());
+        }
+
+        MatchResult {
+            order,
+            fills,
+            closed,
+        }
+    }
+
+    /// Whether a resting order belongs to `user_id`; false for unknown orders.
+    pub fn is_owned_by(&self, order_id: u64, user_id: &str) -> bool {
+        self.bids
+            .values()
+            .chain(self.asks.values())
+            .flatten()
+            .any(|o| o.id == order_id && o.user_id == user_id)
+    }
+
+    /// Removes a resting order from the book.
+    pub fn cancel(&mut self, order_id: u64) -> Option<Order> {
+        for side in [&mut self.bids, &mut self.asks] {
+            let found = side.iter().find_map(|(price, queue)| {
+                queue
+                    .iter()
+                    .position(|o| o.id == order_id)
+                    .map(|index| (*price, index))
+            });
+
+            if let Some((price, index)) = found {
+                let queue = side.get_mut(&price)?;
+                let mut order = queue.remove(index)?;
+                if queue.is_empty() {
+                    side.remove(&price);
+                }
+                order.status = OrderStatus::Cancelled;
+                return Some(order);
+            }
+        }
+        None
+    }
+
+    /// Aggregated depth per price level, best prices first.
+    pub fn snapshot(&self) -> OrderBookSnapshot {
+        let level = |(price, queue): (&Decimal, &VecDeque<Order>)| PriceLevel {
+            price: *price,
+            amount: queue.iter().map(|o| o.remaining).sum(),
+            orders: queue.len(),
+        };
+
+        OrderBookSnapshot {
+            idea_id: self.idea_id.clone(),
+            bids: self.bids.iter().rev().map(level).collect(),
+            asks: self.asks.iter().map(level).collect(),
+        }
+    }
+}
+
+/// Order placed through the service, with the trades it produced.
+#[derive(Debug, Serialize)]
+pub struct PlacedOrder {
+    pub order: Order,
+    pub trades: Vec<Transaction>,
+}
+
+/// In-process registry of order books, one per idea.
+///
+/// Orders rest in this instance's memory only, while what they reserve is recorded in
+/// `order_holds` under the instance's ID, so a restart can give it back without touching the
+/// orders of other instances.
+pub struct OrderBookService<D: Database = PgPool> {
+    db: D,
+    instance_id: String,
+    books: Mutex<HashMap<String, Arc<Mutex<OrderBook>>>>,
+    // order id -> idea id for orders currently resting on a book
+    open_orders: Mutex<HashMap<u64, String>>,
+    events: EventBus,
+}
+
+impl<D: Database> OrderBookService<D> {
+    pub fn new(db: D, events: EventBus, instance_id: String) -> Self {
+        Self {
+            db,
+            instance_id,
+            books: Mutex::new(HashMap::new()),
+            open_orders: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    /// Gives back whatever this instance's orders held before a restart, since its books start
+    /// empty. Call it before the first order is placed.
+    pub async fn release_stale_holds(&self) -> Result<usize, DbError> {
+        let mut conn = self.db.connect().await?;
+        let work = conn.begin_work().await?;
+        let order_ids = work.instance_order_ids(&self.instance_id).await?;
+        for order_id in &order_ids {
+            work.release_order_hold(*order_id).await?;
+        }
+        work.commit().await?;
+        Ok(order_ids.len())
+    }
+
+    /// The idea's book. A new one opens with the market maker offering every token of
+    /// `ORDER_BOOK_SUPPLY` not issued yet at the initial price.
+    async fn book(&self, idea: &Idea) -> Result<Arc<Mutex<OrderBook>>, DbError> {
+        let idea_id = idea.id.clone().unwrap_or_default();
+        if let Some(book) = self.books.lock().await.get(&idea_id) {
+            return Ok(book.clone());
+        }
+
+        let conn = self.db.connect().await?;
+        // The market maker only ever sells on a book, so its holding is minus what it issued
+        let issued = -conn.user_holding(&idea_id, db::AMM_ACCOUNT_ID).await?;
+        let unissued = db::ORDER_BOOK_SUPPLY - issued;
+        let mut book = OrderBook::new(idea_id.clone());
+        if unissued.is_positive() {
+            book.submit(Order {
+                id: conn.next_order_id().await?,
+                idea_id: idea_id.clone(),
+                user_id: db::AMM_ACCOUNT_ID.to_string(),
+                side: Side::Sell,
+                price: idea.initial_price,
+                amount: unissued,
+                remaining: unissued,
+                status: OrderStatus::Open,
+                created_at: Utc::now(),
+            });
+        }
+
+        let mut books = self.books.lock().await;
+        Ok(books
+            .entry(idea_id)
+            .or_insert_with(|| Arc::new(Mutex::new(book)))
+            .clone())
+    }
+
+    /// Units a buy order reserves: its full value, rounded up so the hold always covers the
+    /// fills.
+    fn order_value(order: &Order) -> Result<i64, DbError> {
+        let units = TRADE_CURRENCY.to_units(
+            order.price.mul_rounded(order.amount, Rounding::Up),
+            Rounding::Up,
+        )?;
+        if units <= 0 {
+            return Err(DbError::ValidationError(
+                "Order value is below the smallest unit".into(),
+            ));
+        }
+        Ok(units)
+    }
+
+    /// Reserves the value of a buy order, or the tokens a sell order offers, which must not
+    /// exceed what the seller holds outside their other sell orders and stakes.
+    async fn reserve(&self, work: &impl OrderRepo, order: &Order) -> Result<(), DbError> {
+        let (cash, tokens) = match order.side {
+            Side::Buy => (Self::order_value(order)?, Decimal::ZERO),
+            Side::Sell => {
+                let available = work
+                    .lock_available_holding(&order.idea_id, &order.user_id)
+                    .await?;
+                if available < order.amount {
+                    return Err(DbError::ValidationError(format!(
+                        "Cannot offer {} tokens while holding {} available",
+                        order.amount, available
+                    )));
+                }
+                (0, order.amount)
+            }
+        };
+        work.insert_order_hold(&OrderHold {
+            order_id: order.id,
+            instance_id: self.instance_id.clone(),
+            idea_id: order.idea_id.clone(),
+            user_id: order.user_id.clone(),
+            cash,
+            tokens,
+        })
+        .await
+    }
+
+    /// Returns whatever an order still holds, cash or tokens, to its owner.
+    async fn release(&self, order_id: u64) -> Result<(), DbError> {
+        let mut conn = self.db.connect().await?;
+        let work = conn.begin_work().await?;
+        work.release_order_hold(order_id).await?;
+        work.commit().await
+    }
+
+    /// Places a limit order, matches it and records a `Transaction`, a `Rate` and the ledger
+    /// entry paying the seller for every fill. Buy orders reserve their value and sell orders
+    /// their tokens up front.
+    ///
+    /// The order is matched against a copy of the book, and every reservation, fill and release
+    /// is written in one transaction; the book only changes once it commits.
+    pub async fn place_order(
+        &self,
+        idea_id: &str,
+        user_id: &str,
+        side: Side,
+        price: Decimal,
+        amount: Decimal,
+    ) -> Result<PlacedOrder, DbError> {
+        if user_id.trim().is_empty() {
+            return Err(DbError::ValidationError("User ID cannot be empty".into()));
+        }
+        if !price.is_positive() {
+            return Err(DbError::ValidationError("Price must be positive".into()));
+        }
+        if !amount.is_positive() {
+            return Err(DbError::ValidationError("Amount must be positive".into()));
+        }
+
+        let mut conn = self.db.connect().await?;
+        let idea = conn.idea_by_id(idea_id).await?;
+        check_tradable(&idea)?;
+        if idea.pricing_mode != db::PRICING_ORDER_BOOK {
+            return Err(DbError::ValidationError(format!(
+                "Idea {} is priced by a market maker; trade through /ideas/{}/trade",
+                idea_id, idea_id
+            )));
+        }
+
+        // The book stays locked until the match is persisted, so rates are written in match order
+        let book = self.book(&idea).await?;
+        let mut book = book.lock().await;
+        let work = conn.begin_work().await?;
+        // Checked again under the row lock: closing or archiving the idea either waits for
+        // this order or is seen here
+        check_tradable(&work.lock_idea(idea_id).await?)?;
+
+        let order = Order {
+            id: work.next_order_id().await?,
+            idea_id: idea_id.to_string(),
+            user_id: user_id.to_string(),
+            side,
+            price,
+            amount,
+            remaining: amount,
+            status: OrderStatus::Open,
+            created_at: Utc::now(),
+        };
+        self.reserve(&work, &order).await?;
+        let mut matched = book.clone();
+        let result = matched.submit(order);
+
+        // What every order this match touches still holds
+        let mut touched: Vec<u64> = result
+            .fills
+            .iter()
+            .flat_map(|f| [f.buy_order_id, f.sell_order_id])
+            .chain(result.closed.iter().copied())
+            .collect();
+        touched.sort_unstable();
+        touched.dedup();
+        let mut held: HashMap<u64, OrderHold> = work
+            .lock_order_holds(&touched)
+            .await?
+            .into_iter()
+            .map(|hold| (hold.order_id, hold))
+            .collect();
+
+        let mut trades = Vec::with_capacity(result.fills.len());
+        let mut rates = Vec::with_capacity(result.fills.len());
+        for fill in &result.fills {
+            let trade = Transaction::completed(
+                idea_id.to_string(),
+                fill.buyer_id.clone(),
+                fill.seller_id.clone(),
+                fill.amount,
+                fill.price,
+            );
+            let cash = held.get(&fill.buy_order_id).map_or(0, |hold| hold.cash);
+            let from_hold = cash.min(ledger::trade_units(&trade)?);
+
+            let (trade, rate) = repo::record_trade(
+                &work,
+                trade,
+                Rate::new(idea_id.to_string(), fill.price, fill.amount),
+                from_hold,
+            )
+            .await?;
+            if let Some(hold) = held.get_mut(&fill.buy_order_id) {
+                hold.cash -= from_hold;
+                work.consume_order_hold(fill.buy_order_id, from_hold, Decimal::ZERO)
+                    .await?;
+            }
+            if let Some(hold) = held.get_mut(&fill.sell_order_id) {
+                hold.tokens -= fill.amount;
+                work.consume_order_hold(fill.sell_order_id, 0, fill.amount)
+                    .await?;
+            }
+            trades.push(trade);
+            rates.push(rate);
+        }
+
+        // Orders that left the book give back what they did not spend
+        let mut finished = result.closed.clone();
+        if result.order.remaining.is_zero() {
+            finished.push(result.order.id);
+        }
+        for order_id in &finished {
+            work.release_order_hold(*order_id).await?;
+        }
+
+        work.commit().await?;
+
+        // Everything is persisted, so the match can take effect
+        *book = matched;
+        {
+            let mut open_orders = self.open_orders.lock().await;
+            for order_id in &finished {
+                open_orders.remove(order_id);
+            }
+            if !result.order.remaining.is_zero() {
+                open_orders.insert(result.order.id, idea_id.to_string());
+            }
+        }
+        for (trade, rate) in trades.iter().zip(rates) {
+            self.events.publish(Event::Trade(trade.clone()));
+            self.events.publish(Event::Rate(rate));
+        }
+        self.events.publish(Event::OrderBook(book.snapshot()));
+
+        if !trades.is_empty() {
+            utils::log(&format!(
+                "[OrderBook] Order {} on idea {} produced {} trade(s)",
+                result.order.id,
+                idea_id,
+                trades.len()
+            ));
+        }
+
+        Ok(PlacedOrder {
+            order: result.order,
+            trades,
+        })
+    }
+
+    /// Cancels a resting order owned by `user_id`.
+    pub async fn cancel_order(&self, order_id: u64, user_id: &str) -> Result<Order, DbError> {
+        let not_found = || DbError::NotFound(format!("Open order {} not found", order_id));
+
+        let idea_id = self
+            .open_orders
+            .lock()
+            .await
+            .get(&order_id)
+            .cloned()
+            .ok_or_else(not_found)?;
+
+        let book = self.books.lock().await.get(&idea_id).cloned();
+        let book = book.ok_or_else(not_found)?;
+        let mut book = book.lock().await;
+        if !book.is_owned_by(order_id, user_id) {
+            return Err(DbError::Forbidden(format!(
+                "Order {} belongs to another user",
+                order_id
+            )));
+        }
+        // Released first, so a failed write leaves the order resting with its hold
+        self.release(order_id).await?;
+        let order = book.cancel(order_id).ok_or_else(not_found)?;
+        self.open_orders.lock().await.remove(&order_id);
+        self.events.publish(Event::OrderBook(book.snapshot()));
+        Ok(order)
+    }
+
+    /// Drops the idea's book and every order resting on it, releasing their holds, e.g. once
+    /// trading has closed.
+    pub async fn clear(&self, idea_id: &str) {
+        let Some(book) = self.books.lock().await.remove(idea_id) else {
+            return;
+        };
+        // Waits for an order being placed on the book to finish
+        let _book = book.lock().await;
+        self.events.publish(Event::OrderBook(
+            OrderBook::new(idea_id.to_string()).snapshot(),
+        ));
+
+        let mut dropped = Vec::new();
+        self.open_orders
+            .lock()
+            .await
+            .retain(|order_id, order_idea| {
+                let keep = order_idea != idea_id;
+                if !keep {
+                    dropped.push(*order_id);
+                }
+                keep
+            });
+        for order_id in dropped {
+            if let Err(e) = self.release(order_id).await {
+                utils::log(&format!(
+                    "[OrderBook] Failed to release hold of order {}: {}",
+                    order_id, e
+                ));
+            }
+        }
+    }
+
+    /// Current depth for an idea; ideas not trading on a book show an empty one.
+    pub async fn snapshot(&self, idea_id: &str) -> Result<OrderBookSnapshot, DbError> {
+        let book = self.books.lock().await.get(idea_id).cloned();
+        if let Some(book) = book {
+            return Ok(book.lock().await.snapshot());
+        }
+
+        let idea = self.db.connect().await?.idea_by_id(idea_id).await?;
+        if idea.is_tradable() && idea.pricing_mode == db::PRICING_ORDER_BOOK {
+            Ok(self.book(&idea).await?.lock().await.snapshot())
+        } else {
+            Ok(OrderBook::new(idea_id.to_string()).snapshot())
+        }
+    }
+}
+
+fn check_tradable(idea: &Idea) -> Result<(), DbError> {
+    if idea.is_tradable() {
+        Ok(())
+    } else {
+        Err(DbError::ValidationError(format!(
+            "Idea {} is not open for trading",
+            idea.id.as_deref().unwrap_or_default()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::memory::MemoryStore;
+    use crate::services::events::EVENT_BUS_CAPACITY;
+
+    // Smallest units in a whole USDC
+    const USDC: i64 = 1_000_000;
+
+    fn order(id: u64, user_id: &str, side: Side, price: Decimal, amount: Decimal) -> Order {
+        Order {
+            id,
+            idea_id: "idea".into(),
+            user_id: user_id.into(),
+            side,
+            price,
+            amount,
+            remaining: amount,
+            status: OrderStatus::Open,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn service(store: &MemoryStore, instance_id: &str) -> OrderBookService<MemoryStore> {
+        OrderBookService::new(
+            store.clone(),
+            EventBus::new(EVENT_BUS_CAPACITY),
+            instance_id.to_string(),
+        )
+    }
+
+    /// An order-book idea whose market maker offers its supply at 1.
+    async fn idea(store: &MemoryStore) -> String {
+        let idea = Idea::new(
+            "Idea".into(),
+            "Description".into(),
+            "creator".into(),
+            "Technology".into(),
+            Decimal::from(1),
+            Decimal::from(2),
+            "1y".into(),
+            3,
+            "Large".into(),
+            "None".into(),
+        );
+        store.create_idea(idea).await.unwrap().id.unwrap()
+    }
+
+    async fn available(store: &MemoryStore, idea_id: &str, user_id: &str) -> Decimal {
+        store
+            .lock_available_holding(idea_id, user_id)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn fills_execute_at_the_resting_price() {
+        let mut book = OrderBook::new("idea".into());
+        let ask = book.submit(order(1, "seller", Side::Sell, dec("2"), dec("5")));
+        assert!(ask.fills.is_empty());
+
+        let bid = book.submit(order(2, "buyer", Side::Buy, dec("3"), dec("8")));
+        assert_eq!(bid.fills.len(), 1);
+        assert_eq!(bid.fills[0].price, dec("2"));
+        assert_eq!(bid.fills[0].amount, dec("5"));
+        assert_eq!(bid.closed, vec![1]);
+        assert_eq!(bid.order.status, OrderStatus::PartiallyFilled);
+
+        // The rest of the bid waits on the book at its limit
+        let snapshot = book.snapshot();
+        assert!(snapshot.asks.is_empty());
+        assert_eq!(snapshot.bids[0].price, dec("3"));
+        assert_eq!(snapshot.bids[0].amount, dec("3"));
+    }
+
+    #[test]
+    fn book_cancels_a_resting_order_instead_of_a_self_trade() {
+        let mut book = OrderBook::new("idea".into());
+        book.submit(order(1, "trader", Side::Sell, dec("2"), dec("5")));
+        let bid = book.submit(order(2, "trader", Side::Buy, dec("2"), dec("5")));
+        assert!(bid.fills.is_empty());
+        assert_eq!(bid.closed, vec![1]);
+        assert!(book.snapshot().asks.is_empty());
+        assert_eq!(book.snapshot().bids[0].amount, dec("5"));
+    }
+
+    #[tokio::test]
+    async fn buy_order_holds_its_value_until_cancelled() {
+        let store = MemoryStore::new();
+        let books = service(&store, "a");
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+
+        let placed = books
+            .place_order(&idea_id, "alice", Side::Buy, dec("0.5"), dec("10"))
+            .await
+            .unwrap();
+        assert!(placed.trades.is_empty());
+        assert_eq!(store.balance("alice"), 95 * USDC);
+        assert_eq!(store.held("alice"), 5 * USDC);
+
+        let other = books.cancel_order(placed.order.id, "bob").await;
+        assert!(matches!(other, Err(DbError::Forbidden(_))));
+        let cancelled = books.cancel_order(placed.order.id, "alice").await.unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert_eq!(store.balance("alice"), 100 * USDC);
+        assert_eq!(store.held("alice"), 0);
+        let again = books.cancel_order(placed.order.id, "alice").await;
+        assert!(matches!(again, Err(DbError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn filled_buy_releases_what_it_did_not_spend() {
+        let store = MemoryStore::new();
+        let books = service(&store, "a");
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+
+        // Held at its limit of 2, filled against the market maker at 1
+        let placed = books
+            .place_order(&idea_id, "alice", Side::Buy, dec("2"), dec("10"))
+            .await
+            .unwrap();
+        assert_eq!(placed.order.status, OrderStatus::Filled);
+        assert_eq!(placed.trades.len(), 1);
+        assert_eq!(store.balance("alice"), 90 * USDC);
+        assert_eq!(store.held("alice"), 0);
+        assert_eq!(available(&store, &idea_id, "alice").await, dec("10"));
+    }
+
+    #[tokio::test]
+    async fn sell_orders_cannot_offer_tokens_twice() {
+        let store = MemoryStore::new();
+        let books = service(&store, "a");
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+        books
+            .place_order(&idea_id, "alice", Side::Buy, dec("1"), dec("10"))
+            .await
+            .unwrap();
+
+        let ask = books
+            .place_order(&idea_id, "alice", Side::Sell, dec("3"), dec("6"))
+            .await
+            .unwrap();
+        assert_eq!(available(&store, &idea_id, "alice").await, dec("4"));
+        let oversold = books
+            .place_order(&idea_id, "alice", Side::Sell, dec("3"), dec("5"))
+            .await;
+        assert!(matches!(oversold, Err(DbError::ValidationError(_))));
+
+        books.cancel_order(ask.order.id, "alice").await.unwrap();
+        assert_eq!(available(&store, &idea_id, "alice").await, dec("10"));
+        books
+            .place_order(&idea_id, "alice", Side::Sell, dec("3"), dec("5"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn fills_against_a_resting_ask_spend_its_tokens() {
+        let store = MemoryStore::new();
+        let books = service(&store, "a");
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+        store.credit("bob", 100 * USDC).unwrap();
+        books
+            .place_order(&idea_id, "alice", Side::Buy, dec("1"), dec("10"))
+            .await
+            .unwrap();
+        // Below the market maker's offer, so it is the best ask
+        books
+            .place_order(&idea_id, "alice", Side::Sell, dec("0.5"), dec("10"))
+            .await
+            .unwrap();
+
+        let placed = books
+            .place_order(&idea_id, "bob", Side::Buy, dec("0.5"), dec("4"))
+            .await
+            .unwrap();
+        assert_eq!(placed.trades.len(), 1);
+        assert_eq!(store.balance("alice"), 92 * USDC);
+        assert_eq!(store.balance("bob"), 98 * USDC);
+        assert_eq!(store.held("bob"), 0);
+        // Six tokens are still offered, so none are free
+        assert_eq!(
+            store.user_holding(&idea_id, "alice").await.unwrap(),
+            dec("6")
+        );
+        assert_eq!(available(&store, &idea_id, "alice").await, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn self_trade_cancels_the_resting_order_and_its_hold() {
+        let store = MemoryStore::new();
+        let books = service(&store, "a");
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+        books
+            .place_order(&idea_id, "alice", Side::Buy, dec("1"), dec("10"))
+            .await
+            .unwrap();
+        let ask = books
+            .place_order(&idea_id, "alice", Side::Sell, dec("0.5"), dec("5"))
+            .await
+            .unwrap();
+
+        let bid = books
+            .place_order(&idea_id, "alice", Side::Buy, dec("0.5"), dec("5"))
+            .await
+            .unwrap();
+        assert!(bid.trades.is_empty());
+        assert_eq!(bid.order.status, OrderStatus::Open);
+        assert_eq!(available(&store, &idea_id, "alice").await, dec("10"));
+        assert_eq!(store.held("alice"), 2_500_000);
+        let gone = books.cancel_order(ask.order.id, "alice").await;
+        assert!(matches!(gone, Err(DbError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn closed_idea_takes_no_orders() {
+        let store = MemoryStore::new();
+        let books = service(&store, "a");
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+        store
+            .transition_idea(&idea_id, "active", "closed", None)
+            .await
+            .unwrap();
+
+        let placed = books
+            .place_order(&idea_id, "alice", Side::Buy, dec("1"), dec("10"))
+            .await;
+        assert!(matches!(placed, Err(DbError::ValidationError(_))));
+        assert_eq!(store.balance("alice"), 100 * USDC);
+        assert_eq!(store.held("alice"), 0);
+    }
+
+    #[tokio::test]
+    async fn restart_releases_only_its_own_holds() {
+        let store = MemoryStore::new();
+        let idea_id = idea(&store).await;
+        store.credit("alice", 100 * USDC).unwrap();
+        store.credit("bob", 100 * USDC).unwrap();
+        let a = service(&store, "a");
+        let b = service(&store, "b");
+        let alice = a
+            .place_order(&idea_id, "alice", Side::Buy, dec("0.5"), dec("10"))
+            .await
+            .unwrap();
+        let bob = b
+            .place_order(&idea_id, "bob", Side::Buy, dec("0.5"), dec("10"))
+            .await
+            .unwrap();
+        assert_ne!(alice.order.id, bob.order.id);
+
+        let restarted = service(&store, "a");
+        assert_eq!(restarted.release_stale_holds().await.unwrap(), 1);
+        assert_eq!(store.held("alice"), 0);
+        assert_eq!(store.balance("alice"), 100 * USDC);
+        assert_eq!(store.held("bob"), 5 * USDC);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::db::ledger::{self, TRADE_CURRENCY};
+use crate::db::repo::IdeaRepo;
+use crate::db::{self, DbError, Holding, Idea, Payout, PgPool};
+use crate::decimal::{Decimal, Rounding};
+use crate::utils;
+use serde::Serialize;
+
+/// How a closed idea is settled.
+#[derive(Debug, Clone)]
+pub enum Resolution {
+    /// Every net token is paid `settlement_price`.
+    Resolved {
+        outcome: String,
+        settlement_price: Decimal,
+    },
+    /// The market is cancelled and holders get their net cost back.
+    Voided,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolutionResult {
+    pub idea: Idea,
+    pub payouts: Vec<Payout>,
+}
+
+fn authorize(idea: &Idea, user_id: &str) -> Result<(), DbError> {
+    if idea.creator_id == user_id || idea.resolver_id.as_deref() == Some(user_id) {
+        Ok(())
+    } else {
+        Err(DbError::Forbidden(format!(
+            "User {} may not close or resolve this idea",
+            user_id
+        )))
+    }
+}
+
+/// Payout per holder for a resolution, rounded down to whole units of the trade currency;
+/// holders that are owed nothing are skipped.
+///
+/// Payouts come out of `funds`, the market maker's proceeds for the idea. A resolution worth
+/// more, or one with short positions, is refused; refunds of a voided idea are scaled down to fit, since holders who sold at
+/// a profit keep it.
+pub fn compute_payouts(
+    holdings: &[Holding],
+    resolution: &Resolution,
+    funds: Decimal,
+) -> Result<Vec<Payout>, DbError> {
+    // Sells need the tokens, so only positions from before that rule can be short, and nothing
+    // backs what they would owe
+    if let (Resolution::Resolved { .. }, Some(short)) = (
+        resolution,
+        holdings.iter().find(|holding| holding.amount.is_negative()),
+    ) {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} has short positions that cannot be settled; void it instead",
+            short.idea_id
+        )));
+    }
+
+    let owed: Vec<(&Holding, Decimal)> = holdings
+        .iter()
+        .filter_map(|holding| {
+            let owed = match resolution {
+                Resolution::Resolved {
+                    settlement_price, ..
+                } if holding.amount.is_positive() => holding
+                    .amount
+                    .mul_rounded(*settlement_price, Rounding::Down),
+                Resolution::Voided => holding.net_cost,
+                _ => return None,
+            };
+            owed.is_positive().then_some((holding, owed))
+        })
+        .collect();
+
+    let total: Decimal = owed.iter().map(|(_, owed)| *owed).sum();
+    let underfunded = total > funds;
+    if underfunded && matches!(resolution, Resolution::Resolved { .. }) {
+        return Err(DbError::ValidationError(format!(
+            "Payouts of {} exceed the {} the idea's tokens were sold for",
+            total, funds
+        )));
+    }
+
+    let mut payouts = Vec::with_capacity(owed.len());
+    for (holding, owed) in owed {
+        let owed = if underfunded {
+            owed.mul_rounded(funds, Rounding::Down)
+                .div_rounded(total, Rounding::Down)
+                .unwrap_or(Decimal::ZERO)
+        } else {
+            owed
+        };
+        let units = TRADE_CURRENCY.to_units(owed, Rounding::Down)?;
+        if units <= 0 {
+            continue;
+        }
+        payouts.push(Payout {
+            id: None,
+            idea_id: holding.idea_id.clone(),
+            user_id: holding.user_id.clone(),
+            amount: holding.amount.max(Decimal::ZERO),
+            payout: TRADE_CURRENCY.to_amount(units)?,
+            created_at: None,
+        });
+    }
+    Ok(payouts)
+}
+
+/// Highest settlement price the idea's proceeds can pay on every outstanding token; `None`
+/// when no tokens are outstanding.
+pub fn max_settlement_price(holdings: &[Holding], funds: Decimal) -> Option<Decimal> {
+    let outstanding: Decimal = holdings
+        .iter()
+        .map(|holding| holding.amount.max(Decimal::ZERO))
+        .sum();
+    funds.div_rounded(outstanding, Rounding::Down)
+}
+
+/// Stops trading on an active idea. The status change waits for an order being placed on the
+/// idea, which holds its row, and every later order sees it.
+pub async fn close_idea(pool: &PgPool, idea_id: &str, user_id: &str) -> Result<Idea, DbError> {
+    let client = pool.get().await?;
+    let idea = client.idea_by_id(idea_id).await?;
+    authorize(&idea, user_id)?;
+
+    let idea = client
+        .transition_idea(idea_id, "active", "closed", None)
+        .await?;
+    utils::log(&format!(
+        "[Resolution] Idea {} closed by {}",
+        idea_id, user_id
+    ));
+    Ok(idea)
+}
+
+/// Settles a closed idea, recording and paying out every holder's share in one transaction.
+pub async fn resolve_idea(
+    pool: &PgPool,
+    idea_id: &str,
+    user_id: &str,
+    resolution: Resolution,
+) -> Result<ResolutionResult, DbError> {
+    if let Resolution::Resolved {
+        outcome,
+        settlement_price,
+    } = &resolution
+    {
+        if outcome.trim().is_empty() {
+            return Err(DbError::ValidationError("Outcome cannot be empty".into()));
+        }
+        if settlement_price.is_negative() {
+            return Err(DbError::ValidationError(
+                "Settlement price cannot be negative".into(),
+            ));
+        }
+    }
+
+    let mut client = pool.get().await?;
+    let idea = client.idea_by_id(idea_id).await?;
+    authorize(&idea, user_id)?;
+
+    let tx = client.transaction().await?;
+
+    let idea = match &resolution {
+        Resolution::Resolved { outcome, .. } => {
+            db::transition_idea_status(&tx, idea_id, "closed", "resolved", Some(outcome)).await?
+        }
+        Resolution::Voided => {
+            db::transition_idea_status(&tx, idea_id, "closed", "voided", Some("void")).await?
+        }
+    };
+
+    let holdings = db::get_idea_holdings(&tx, idea_id).await?;
+    let funds = db::get_market_maker_proceeds(&tx, idea_id).await?;
+    if let Resolution::Resolved {
+        settlement_price, ..
+    } = &resolution
+    {
+        if let Some(max_price) = max_settlement_price(&holdings, funds) {
+            if *settlement_price > max_price {
+                return Err(DbError::ValidationError(format!(
+                    "Settlement price cannot exceed {}, the proceeds per outstanding token",
+                    max_price
+                )));
+            }
+        }
+        db::set_settlement_price(&tx, idea_id, *settlement_price).await?;
+    }
+    let mut payouts = Vec::new();
+    for payout in compute_payouts(&holdings, &resolution, funds)? {
+        let payout = db::insert_payout(&tx, payout).await?;
+        ledger::post_payout(&tx, &payout).await?;
+        payouts.push(payout);
+    }
+
+    tx.commit().await?;
+
+    utils::log(&format!(
+        "[Resolution] Idea {} settled as {} with {} payout(s)",
+        idea_id,
+        idea.status,
+        payouts.len()
+    ));
+
+    Ok(ResolutionResult { idea, payouts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn holding(user_id: &str, amount: &str, net_cost: &str) -> Holding {
+        Holding {
+            user_id: user_id.to_string(),
+            idea_id: "idea".to_string(),
+            amount: dec(amount),
+            net_cost: dec(net_cost),
+        }
+    }
+
+    fn resolved(settlement_price: &str) -> Resolution {
+        Resolution::Resolved {
+            outcome: "shipped".into(),
+            settlement_price: dec(settlement_price),
+        }
+    }
+
+    fn paid(payouts: &[Payout]) -> Vec<(&str, Decimal)> {
+        payouts
+            .iter()
+            .map(|payout| (payout.user_id.as_str(), payout.payout))
+            .collect()
+    }
+
+    #[test]
+    fn resolution_pays_every_token_the_settlement_price() {
+        let holdings = [
+            holding("alice", "10", "12"),
+            holding("bob", "5", "4"),
+            holding("carol", "0", "-3"),
+        ];
+        let payouts = compute_payouts(&holdings, &resolved("2"), dec("100")).unwrap();
+        assert_eq!(paid(&payouts), [("alice", dec("20")), ("bob", dec("10"))]);
+        assert_eq!(payouts[0].amount, dec("10"));
+    }
+
+    #[test]
+    fn short_positions_cannot_be_resolved() {
+        let holdings = [holding("alice", "10", "10"), holding("bob", "-2", "-2")];
+        assert!(matches!(
+            compute_payouts(&holdings, &resolved("1"), dec("100")),
+            Err(DbError::ValidationError(_))
+        ));
+        // Voiding refunds net cost, so the short holder simply gets nothing back
+        let payouts = compute_payouts(&holdings, &Resolution::Voided, dec("100")).unwrap();
+        assert_eq!(paid(&payouts), [("alice", dec("10"))]);
+    }
+
+    #[test]
+    fn resolution_cannot_pay_more_than_the_proceeds() {
+        let holdings = [holding("alice", "10", "10")];
+        assert!(compute_payouts(&holdings, &resolved("2"), dec("19.99")).is_err());
+        assert!(compute_payouts(&holdings, &resolved("2"), dec("20")).is_ok());
+    }
+
+    #[test]
+    fn underfunded_refunds_are_scaled_down() {
+        let holdings = [holding("alice", "6", "60"), holding("bob", "4", "40")];
+        let payouts = compute_payouts(&holdings, &Resolution::Voided, dec("50")).unwrap();
+        assert_eq!(paid(&payouts), [("alice", dec("30")), ("bob", dec("20"))]);
+    }
+
+    #[test]
+    fn payouts_round_down_to_whole_units() {
+        // Three equal refunds of a third each leave dust in the pool rather than overpaying
+        let holdings = [
+            holding("alice", "1", "1"),
+            holding("bob", "1", "1"),
+            holding("carol", "1", "1"),
+        ];
+        let payouts = compute_payouts(&holdings, &Resolution::Voided, dec("1")).unwrap();
+        assert!(payouts
+            .iter()
+            .all(|payout| payout.payout == dec("0.333333")));
+
+        // Less than one unit is owed nothing
+        let holdings = [holding("alice", "0.0000001", "0"), holding("bob", "1", "0")];
+        let payouts = compute_payouts(&holdings, &resolved("0.3333333"), dec("1")).unwrap();
+        assert_eq!(paid(&payouts), [("bob", dec("0.333333"))]);
+    }
+
+    #[test]
+    fn settlement_price_is_bounded_by_proceeds_per_outstanding_token() {
+        let holdings = [
+            holding("alice", "10", "10"),
+            holding("bob", "5", "5"),
+            holding("carol", "-2", "-2"),
+        ];
+        assert_eq!(
+            max_settlement_price(&holdings, dec("100")),
+            Some(dec("6.66666666"))
+        );
+        assert_eq!(max_settlement_price(&[], dec("100")), None);
+    }
+}
@@ -1,58 +1,432 @@
-use solana_client::rpc_client::RpcClient;
+use crate::config::CONFIG;
+use crate::db::DbError;
+use crate::handlers::solana::backend_keypair;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::Message;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature};
 use solana_sdk::signer::Signer;
+use solana_sdk::system_instruction::{self, SystemInstruction};
+use solana_sdk::system_program;
+use solana_sdk::transaction::Transaction;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionStatus, UiTransactionEncoding,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use spl_token::instruction::TokenInstruction;
+use std::future::Future;
 use std::str::FromStr;
+use std::sync::Arc;
 
-const RPC_URL: &str = "https://api.devnet.solana.com"; // Or your preferred RPC endpoint
+/// The RPC calls made by [`SolanaService`], so tests can stand in for a cluster.
+pub trait SolanaRpc {
+    fn get_balance(&self, pubkey: &Pubkey)
+        -> impl Future<Output = Result<u64, SolanaServiceError>>;
+    fn get_latest_blockhash(&self) -> impl Future<Output = Result<Hash, SolanaServiceError>>;
+    /// Status of a transaction, `None` if the cluster has not seen it.
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+    ) -> impl Future<Output = Result<Option<TransactionStatus>, SolanaServiceError>>;
+    /// A confirmed transaction, base64 encoded.
+    fn get_transaction(
+        &self,
+        signature: &Signature,
+    ) -> impl Future<Output = Result<EncodedConfirmedTransactionWithStatusMeta, SolanaServiceError>>;
+    fn send_and_confirm_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> impl Future<Output = Result<Signature, SolanaServiceError>>;
+}
+
+impl SolanaRpc for RpcClient {
+    async fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, SolanaServiceError> {
+        RpcClient::get_balance(self, pubkey)
+            .await
+            .map_err(|e| SolanaServiceError::RpcError(format!("Failed to get balance: {}", e)))
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, SolanaServiceError> {
+        RpcClient::get_latest_blockhash(self)
+            .await
+            .map_err(|e| SolanaServiceError::RpcError(format!("Failed to get blockhash: {}", e)))
+    }
+
+    async fn get_signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<TransactionStatus>, SolanaServiceError> {
+        Ok(self
+            .get_signature_statuses(&[*signature])
+            .await
+            .map_err(|e| SolanaServiceError::RpcError(format!("Failed to get status: {}", e)))?
+            .value
+            .into_iter()
+            .next()
+            .flatten())
+    }
+
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, SolanaServiceError> {
+        self.get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await
+        .map_err(|e| SolanaServiceError::RpcError(format!("Failed to get transaction: {}", e)))
+    }
+
+    async fn send_and_confirm_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Signature, SolanaServiceError> {
+        RpcClient::send_and_confirm_transaction(self, transaction)
+            .await
+            .map_err(|e| SolanaServiceError::RpcError(format!("Transaction failed: {}", e)))
+    }
+}
+
+/// Transaction built by the backend for the user's wallet to sign and submit.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnsignedTransaction {
+    /// Bincode-serialized legacy transaction, base64 encoded.
+    pub transaction: String,
+    pub escrow: String,
+    pub recent_blockhash: String,
+}
+
+/// Deposit into an escrow found in a confirmed transaction.
+#[derive(Debug, Clone, Serialize)]
+pub struct EscrowDeposit {
+    pub signature: String,
+    pub payer: String,
+    pub escrow: String,
+    /// SPL mint of the deposit; `None` for SOL.
+    pub mint: Option<String>,
+    /// Smallest units: lamports or raw token amount.
+    pub amount: u64,
+}
 
-struct SolanaService {
-    rpc_client: RpcClient,
-    // You might store your program's public key here
-    // program_id: Pubkey,
-    // And potentially a keypair for backend-signed transactions (manage securely!)
-    // fee_payer: Keypair,
+/// Payout of winnings out of a market escrow.
+#[derive(Debug, Clone, Serialize)]
+pub struct EscrowWithdrawal {
+    pub signature: String,
+    pub escrow: String,
+    pub recipient: String,
+    /// Smallest units: lamports or raw token amount.
+    pub amount: u64,
+}
+
+/// Market escrows are held by the escrow authority, a server-held keypair: SOL at its own
+/// address and SPL tokens in its associated token account. What each market holds is tracked
+/// by its bets, and payouts are plain transfers the authority signs.
+pub struct SolanaService<R: SolanaRpc = RpcClient> {
+    rpc_client: R,
+    // Holds the escrowed funds and signs withdrawals
+    authority: Option<Arc<Keypair>>,
 }
 
 impl SolanaService {
+    /// Connects to `SOLANA_RPC_URL` with the backend keypair as the escrow authority.
     pub fn new() -> Self {
-        // In a real app, you'd likely get the RPC_URL and program_id from config
-        let rpc_client = RpcClient::new(RPC_URL.to_string());
-        // let program_id = Pubkey::from_str("YOUR_PROGRAM_ID_HERE").expect("Invalid program ID");
-        // TODO: Initialize fee_payer if needed, perhaps from a securely stored key
+        Self::with_rpc_client(
+            RpcClient::new_with_commitment(
+                CONFIG.solana_rpc_url.clone(),
+                CommitmentConfig::confirmed(),
+            ),
+            backend_keypair().ok(),
+        )
+    }
+}
+
+impl<R: SolanaRpc> SolanaService<R> {
+    /// Uses the given client, e.g. one pointed at `solana-test-validator` or a stub in tests.
+    pub fn with_rpc_client(rpc_client: R, authority: Option<Arc<Keypair>>) -> Self {
         Self {
             rpc_client,
-            // program_id,
+            authority,
         }
     }
 
-    // Example function to get balance (replace with actual contract interactions)
-    pub async fn get_balance(&self, account_pubkey_str: &str) -> Result<u64, String> {
-        match Pubkey::from_str(account_pubkey_str) {
-            Ok(account_pubkey) => {
-                self.rpc_client.get_balance(&account_pubkey)
-                    .map_err(|e| format!("Failed to get balance: {}", e))
-            }
-            Err(e) => Err(format!("Invalid public key string: {}", e)),
+    pub async fn get_balance(&self, account_pubkey_str: &str) -> Result<u64, SolanaServiceError> {
+        let account_pubkey = parse_pubkey(account_pubkey_str)?;
+        self.rpc_client.get_balance(&account_pubkey).await
+    }
+
+    fn authority(&self) -> Result<Arc<Keypair>, SolanaServiceError> {
+        self.authority.clone().ok_or_else(|| {
+            SolanaServiceError::NotConfigured("The escrow authority keypair is not loaded".into())
+        })
+    }
+
+    /// Account escrowed funds are held in: the authority itself for SOL, or its associated
+    /// token account for `mint`.
+    pub fn escrow_address(&self, mint: Option<&Pubkey>) -> Result<Pubkey, SolanaServiceError> {
+        let authority = self.authority()?.pubkey();
+        Ok(match mint {
+            None => authority,
+            Some(mint) => get_associated_token_address(&authority, mint),
+        })
+    }
+
+    /// Builds an unsigned transfer of `amount` from `payer` into the escrow. For SPL markets
+    /// the payer creates the escrow's token account on the fly if needed.
+    pub async fn build_deposit_transaction(
+        &self,
+        payer: &str,
+        mint: Option<&str>,
+        amount: u64,
+    ) -> Result<UnsignedTransaction, SolanaServiceError> {
+        if amount == 0 {
+            return Err(SolanaServiceError::InvalidData(
+                "Amount must be positive".into(),
+            ));
         }
+        let payer = parse_pubkey(payer)?;
+        let mint = mint.map(parse_pubkey).transpose()?;
+        let escrow = self.escrow_address(mint.as_ref())?;
+
+        let instructions: Vec<Instruction> = match mint {
+            None => vec![system_instruction::transfer(&payer, &escrow, amount)],
+            Some(mint) => vec![
+                create_associated_token_account_idempotent(
+                    &payer,
+                    &self.authority()?.pubkey(),
+                    &mint,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    &get_associated_token_address(&payer, &mint),
+                    &escrow,
+                    &payer,
+                    &[],
+                    amount,
+                )
+                .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?,
+            ],
+        };
+
+        let recent_blockhash = self.rpc_client.get_latest_blockhash().await?;
+        let message = Message::new_with_blockhash(&instructions, Some(&payer), &recent_blockhash);
+        let transaction = bincode::serialize(&Transaction::new_unsigned(message))
+            .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?;
+
+        Ok(UnsignedTransaction {
+            transaction: BASE64.encode(transaction),
+            escrow: escrow.to_string(),
+            recent_blockhash: recent_blockhash.to_string(),
+        })
     }
 
-    // Placeholder for creating a market
-    // pub async fn create_market(&self, /* market details */) -> Result<(), String> {
-    //     // 1. Construct transaction with instructions to call your on-chain program
-    //     // 2. Sign transaction (either by user on client-side, or backend if authorized)
-    //     // 3. Send and confirm transaction
-    //     Ok(())
-    // }
+    /// Checks that `signature` is a confirmed, successful transaction in which `payer`
+    /// deposited into the escrow for a bet on `market_id`, and returns the deposited amount.
+    pub async fn confirm_deposit(
+        &self,
+        signature: &str,
+        payer: &str,
+        market_id: &str,
+        mint: Option<&str>,
+    ) -> Result<EscrowDeposit, SolanaServiceError> {
+        let parsed_signature = Signature::from_str(signature)
+            .map_err(|e| SolanaServiceError::InvalidData(format!("Invalid signature: {}", e)))?;
+        let payer_key = parse_pubkey(payer)?;
+        let mint_key = mint.map(parse_pubkey).transpose()?;
+        let destination = self.escrow_address(mint_key.as_ref())?;
 
-    // Placeholder for placing a bet
-    // pub async fn place_bet(&self, /* bet details */) -> Result<(), String> {
-    //     Ok(())
-    // }
+        let status = self
+            .rpc_client
+            .get_signature_status(&parsed_signature)
+            .await?
+            .ok_or_else(|| {
+                SolanaServiceError::Unconfirmed(format!("Transaction {} not found yet", signature))
+            })?;
+        if let Some(err) = status.err {
+            return Err(SolanaServiceError::InvalidData(format!(
+                "Transaction {} failed: {}",
+                signature, err
+            )));
+        }
+        if !status.satisfies_commitment(CommitmentConfig::confirmed()) {
+            return Err(SolanaServiceError::Unconfirmed(format!(
+                "Transaction {} is not confirmed yet",
+                signature
+            )));
+        }
+
+        let confirmed = self.rpc_client.get_transaction(&parsed_signature).await?;
+        let transaction = confirmed.transaction.transaction.decode().ok_or_else(|| {
+            SolanaServiceError::InvalidData(format!("Cannot decode transaction {}", signature))
+        })?;
+
+        let keys = transaction.message.static_account_keys();
+        let key = |index: &u8| keys.get(*index as usize).copied();
+
+        let mut amount: u64 = 0;
+        for instruction in transaction.message.instructions() {
+            let Some(program_id) = key(&instruction.program_id_index) else {
+                continue;
+            };
+            let accounts: Vec<Option<Pubkey>> = instruction.accounts.iter().map(key).collect();
+            let account = |i: usize| accounts.get(i).copied().flatten();
+
+            let deposited = match mint_key {
+                None if program_id == system_program::id() => {
+                    match bincode::deserialize::<SystemInstruction>(&instruction.data) {
+                        Ok(SystemInstruction::Transfer { lamports })
+                            if account(0) == Some(payer_key) && account(1) == Some(destination) =>
+                        {
+                            lamports
+                        }
+                        _ => 0,
+                    }
+                }
+                Some(mint) if program_id == spl_token::id() => {
+                    match TokenInstruction::unpack(&instruction.data) {
+                        Ok(TokenInstruction::Transfer { amount })
+                            if account(1) == Some(destination) && account(2) == Some(payer_key) =>
+                        {
+                            amount
+                        }
+                        Ok(TokenInstruction::TransferChecked { amount, .. })
+                            if account(1) == Some(mint)
+                                && account(2) == Some(destination)
+                                && account(3) == Some(payer_key) =>
+                        {
+                            amount
+                        }
+                        _ => 0,
+                    }
+                }
+                _ => 0,
+            };
+            amount = amount.checked_add(deposited).ok_or_else(|| {
+                SolanaServiceError::InvalidData("Deposit amount overflows".into())
+            })?;
+        }
+
+        if amount == 0 {
+            return Err(SolanaServiceError::InvalidData(format!(
+                "Transaction {} does not deposit into the escrow of market {}",
+                signature, market_id
+            )));
+        }
 
-    // Placeholder for getting market details
-    // pub async fn get_market_details(&self, market_id: &str) -> Result</* MarketData struct */, String> {
-    //     Ok(())
-    // }
+        Ok(EscrowDeposit {
+            signature: signature.to_string(),
+            payer: payer.to_string(),
+            escrow: destination.to_string(),
+            mint: mint.map(str::to_string),
+            amount,
+        })
+    }
+
+    /// Pays `amount` out of the escrow to `recipient` with a transfer signed by the escrow
+    /// authority.
+    ///
+    /// A send that fails is looked up once more, since it may still have landed; if the
+    /// cluster has not seen it the outcome is `Unconfirmed` rather than a failure.
+    pub async fn withdraw_from_escrow(
+        &self,
+        mint: Option<&str>,
+        recipient: &str,
+        amount: u64,
+    ) -> Result<EscrowWithdrawal, SolanaServiceError> {
+        if amount == 0 {
+            return Err(SolanaServiceError::InvalidData(
+                "Amount must be positive".into(),
+            ));
+        }
+        let authority = self.authority()?;
+        let recipient_key = parse_pubkey(recipient)?;
+        let mint = mint.map(parse_pubkey).transpose()?;
+        let escrow = self.escrow_address(mint.as_ref())?;
+
+        let instructions = match mint {
+            None => vec![system_instruction::transfer(
+                &authority.pubkey(),
+                &recipient_key,
+                amount,
+            )],
+            Some(mint) => vec![
+                create_associated_token_account_idempotent(
+                    &authority.pubkey(),
+                    &recipient_key,
+                    &mint,
+                    &spl_token::id(),
+                ),
+                spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    &escrow,
+                    &get_associated_token_address(&recipient_key, &mint),
+                    &authority.pubkey(),
+                    &[],
+                    amount,
+                )
+                .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?,
+            ],
+        };
+
+        let recent_blockhash = self.rpc_client.get_latest_blockhash().await?;
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&authority.pubkey()),
+            &[authority.as_ref()],
+            recent_blockhash,
+        );
+        let signature = match self
+            .rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await
+        {
+            Ok(signature) => signature,
+            Err(error) => {
+                let signature = transaction.signatures[0];
+                match self.rpc_client.get_signature_status(&signature).await {
+                    Ok(Some(status)) if status.err.is_none() => signature,
+                    Ok(Some(_)) => return Err(error),
+                    _ => {
+                        return Err(SolanaServiceError::Unconfirmed(format!(
+                            "Withdrawal {} has an unknown status: {}",
+                            signature, error
+                        )))
+                    }
+                }
+            }
+        };
+
+        Ok(EscrowWithdrawal {
+            signature: signature.to_string(),
+            escrow: escrow.to_string(),
+            recipient: recipient.to_string(),
+            amount,
+        })
+    }
+}
+
+impl Default for SolanaService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_pubkey(value: &str) -> Result<Pubkey, SolanaServiceError> {
+    Pubkey::from_str(value)
+        .map_err(|e| SolanaServiceError::InvalidData(format!("Invalid public key string: {}", e)))
 }
 
 // Basic error type for the service
@@ -60,4 +434,30 @@ impl SolanaService {
 pub enum SolanaServiceError {
     RpcError(String),
     InvalidData(String),
-} 
\ No newline at end of file
+    /// The transaction is not (yet) confirmed; the caller may retry.
+    Unconfirmed(String),
+    NotConfigured(String),
+}
+
+impl std::fmt::Display for SolanaServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolanaServiceError::RpcError(e) => write!(f, "Solana RPC error: {}", e),
+            SolanaServiceError::InvalidData(e) => write!(f, "Invalid data: {}", e),
+            SolanaServiceError::Unconfirmed(e) => write!(f, "Unconfirmed: {}", e),
+            SolanaServiceError::NotConfigured(e) => write!(f, "Not configured: {}", e),
+        }
+    }
+}
+
+impl From<SolanaServiceError> for DbError {
+    fn from(error: SolanaServiceError) -> Self {
+        match error {
+            SolanaServiceError::InvalidData(e) => DbError::ValidationError(e),
+            SolanaServiceError::Unconfirmed(e) => DbError::Unconfirmed(e),
+            SolanaServiceError::RpcError(e) | SolanaServiceError::NotConfigured(e) => {
+                DbError::ConnectionError(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use crate::db::ledger::{
+    self, JournalEntry, JournalLine, ENTRY_STAKE, ENTRY_UNSTAKE, STAKING_ACCOUNT,
+    STAKING_REWARDS_ACCOUNT, TRADE_CURRENCY,
+};
+use crate::db::repo::{IdeaRepo, PgBacked, PgRepo, PgUnitOfWork, RateRepo, UnitOfWork};
+use crate::db::stakes::{self, Stake, STAKE_ACTIVE};
+use crate::db::{self, DbError, Idea, PgPool};
+use crate::decimal::{Decimal, Rounding};
+use crate::utils;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use serde::Serialize;
+
+/// Lockup periods a stake can choose, in days, with their yearly reward rate in basis points.
+/// Longer lockups earn more.
+pub const LOCKUP_TIERS: &[(i32, i64)] = &[(7, 200), (30, 500), (90, 1_000), (180, 1_500)];
+
+/// The performance multiplier is capped at this many times the base reward.
+const MAX_PERFORMANCE: Decimal = Decimal::new(3, 0);
+
+const SECONDS_PER_YEAR: i64 = 365 * 24 * 3600;
+
+/// A stake with the reward it has earned so far.
+#[derive(Debug, Clone, Serialize)]
+pub struct StakeView {
+    #[serde(flatten)]
+    pub stake: Stake,
+    /// The reward paid if unstaked now; the final reward once unstaked.
+    pub accrued_reward: Decimal,
+    pub unlocked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IdeaStakes {
+    pub idea_id: String,
+    /// Tokens locked by active stakes.
+    pub total_staked: Decimal,
+    pub stakes: Vec<StakeView>,
+}
+
+fn reward_rate(lockup_days: i32) -> Option<Decimal> {
+    LOCKUP_TIERS
+        .iter()
+        .find(|(days, _)| *days == lockup_days)
+        .map(|(_, bps)| Decimal::new(*bps, 4))
+}
+
+fn is_settled(idea: &Idea) -> bool {
+    idea.status == "resolved" || idea.status == "voided"
+}
+
+/// Stakes on settled ideas unlock right away.
+fn is_unlocked(stake: &Stake, idea: &Idea, now: DateTime<Utc>) -> bool {
+    is_settled(idea) || stake.unlock_at.is_some_and(|unlock_at| unlock_at <= now)
+}
+
+/// Price the idea trades at now, or the initial price before its first trade.
+async fn current_price(repo: &impl RateRepo, idea: &Idea) -> Result<Decimal, DbError> {
+    let idea_id = idea.id.as_deref().unwrap_or_default();
+    Ok(repo
+        .latest_rate(idea_id)
+        .await?
+        .map_or(idea.initial_price, |rate| rate.rate()))
+}
+
+/// When a stake stops accruing: at unlock or settlement, whichever comes first, or `now`.
+fn accrual_end(stake: &Stake, idea: &Idea, now: DateTime<Utc>) -> DateTime<Utc> {
+    let mut end = stake.unlock_at.map_or(now, |unlock_at| now.min(unlock_at));
+    if let Some(resolved_at) = idea.resolved_at.filter(|_| is_settled(idea)) {
+        end = end.min(resolved_at);
+    }
+    end
+}
+
+/// Price a stake's performance is measured at.
+///
+/// A resolved idea uses its settlement price. Otherwise it is the rate averaged over the time
+/// the stake accrued, so a single trade just before unstaking cannot move the reward.
+async fn exit_price(
+    client: &impl GenericClient,
+    stake: &Stake,
+    idea: &Idea,
+    now: DateTime<Utc>,
+) -> Result<Decimal, DbError> {
+    let settlement_price = if idea.status == "resolved" {
+        db::get_settlement_price(client, &stake.idea_id).await?
+    } else {
+        None
+    };
+    let average_price = match (settlement_price, stake.staked_at) {
+        (None, Some(staked_at)) => {
+            let end = accrual_end(stake, idea, now);
+            db::time_weighted_rate(client, &stake.idea_id, staked_at, end).await?
+        }
+        _ => None,
+    };
+    Ok(choose_exit_price(
+        stake,
+        idea,
+        settlement_price,
+        average_price,
+    ))
+}
+
+/// The settlement price of a resolved idea, else the average rate, else the entry price when
+/// nothing traded while the stake accrued.
+fn choose_exit_price(
+    stake: &Stake,
+    idea: &Idea,
+    settlement_price: Option<Decimal>,
+    average_price: Option<Decimal>,
+) -> Decimal {
+    settlement_price
+        .filter(|_| idea.status == "resolved")
+        .or(average_price)
+        .unwrap_or(stake.entry_price)
+}
+
+/// Cash value a stake's reward is a share of: the tokens at their entry price.
+fn staked_value(stake: &Stake) -> Decimal {
+    stake.amount.mul_rounded(stake.entry_price, Rounding::Down)
+}
+
+/// Largest reward `value` can earn over `lockup_days`, in smallest units, rounded up.
+fn max_reward_units(value: Decimal, lockup_days: i32) -> Result<i64, DbError> {
+    let rate = reward_rate(lockup_days).unwrap_or(Decimal::ZERO);
+    let reward = value
+        .mul_rounded(rate, Rounding::Up)
+        .mul_rounded(MAX_PERFORMANCE, Rounding::Up)
+        .mul_rounded(Decimal::from(lockup_days as i64), Rounding::Up)
+        .div_rounded(Decimal::from(365), Rounding::Up)
+        .unwrap_or(Decimal::ZERO);
+    TRADE_CURRENCY.to_units(reward, Rounding::Up)
+}
+
+/// Reward of a stake at `now`, rounded down.
+///
+/// The base reward accrues on the staked value at the lockup's yearly rate until the stake
+/// unlocks or the idea is settled, whichever comes first. It is scaled by the idea's price
+/// performance since staking, `exit_price / entry_price` capped at [`MAX_PERFORMANCE`], so
+/// backing an idea that falls earns less and a voided idea earns nothing. No stake earns more
+/// than the reward it reserved.
+pub fn accrued_reward(
+    stake: &Stake,
+    idea: &Idea,
+    exit_price: Decimal,
+    now: DateTime<Utc>,
+) -> Decimal {
+    let (Some(rate), Some(staked_at)) = (reward_rate(stake.lockup_days), stake.staked_at) else {
+        return Decimal::ZERO;
+    };
+    if idea.status == "voided" || !stake.entry_price.is_positive() {
+        return Decimal::ZERO;
+    }
+
+    let elapsed = (accrual_end(stake, idea, now) - staked_at)
+        .num_seconds()
+        .max(0);
+    let performance = exit_price
+        .div_rounded(stake.entry_price, Rounding::Down)
+        .unwrap_or(Decimal::ZERO)
+        .min(MAX_PERFORMANCE);
+
+    staked_value(stake)
+        .mul_rounded(rate, Rounding::Down)
+        .mul_rounded(performance, Rounding::Down)
+        .mul_rounded(Decimal::from(elapsed), Rounding::Down)
+        .div_rounded(Decimal::from(SECONDS_PER_YEAR), Rounding::Down)
+        .unwrap_or(Decimal::ZERO)
+        .min(stake.reserved_reward)
+}
+
+/// Locks `amount` of the user's tokens of an open idea for `lockup_days`.
+///
+/// The largest reward the stake can earn is moved from the rewards pool into the staking
+/// account, and the stake is refused if the pool cannot cover it.
+pub async fn stake(
+    pool: &PgPool,
+    idea_id: &str,
+    user_id: &str,
+    amount: Decimal,
+    lockup_days: i32,
+) -> Result<Stake, DbError> {
+    if reward_rate(lockup_days).is_none() {
+        let days: Vec<String> = LOCKUP_TIERS.iter().map(|(d, _)| d.to_string()).collect();
+        return Err(DbError::ValidationError(format!(
+            "Lockup must be one of {} days",
+            days.join(", ")
+        )));
+    }
+    if !amount.is_positive() {
+        return Err(DbError::ValidationError("Amount must be positive".into()));
+    }
+
+    let mut client = pool.get().await?;
+    let uow = PgUnitOfWork::begin(&mut client).await?;
+    let idea = uow.idea_by_id(idea_id).await?;
+    if !idea.is_tradable() {
+        return Err(DbError::ValidationError(format!(
+            "Idea {} is not open for staking",
+            idea_id
+        )));
+    }
+    db::lock_user_holding(uow.client(), idea_id, user_id).await?;
+    let available = db::get_available_holding(uow.client(), idea_id, user_id).await?;
+    if amount > available {
+        return Err(DbError::ValidationError(format!(
+            "Cannot stake {} tokens while holding {} available",
+            amount, available
+        )));
+    }
+    let entry_price = current_price(&uow, &idea).await?;
+
+    let rewards_account = ledger::system_account(STAKING_REWARDS_ACCOUNT, TRADE_CURRENCY);
+    let reserve_units =
+        max_reward_units(amount.mul_rounded(entry_price, Rounding::Down), lockup_days)?;
+    if reserve_units > ledger::get_account_balance(uow.client(), &rewards_account).await? {
+        return Err(DbError::ValidationError(
+            "The staking rewards pool cannot cover the reward of this stake".into(),
+        ));
+    }
+    let stake = stakes::insert_stake(
+        uow.client(),
+        idea_id,
+        user_id,
+        amount,
+        lockup_days,
+        entry_price,
+        TRADE_CURRENCY.to_amount(reserve_units)?,
+    )
+    .await?;
+    if reserve_units > 0 {
+        ledger::post_entry(
+            uow.client(),
+            ENTRY_STAKE,
+            TRADE_CURRENCY,
+            Some(&format!("stake:{}", stake.id)),
+            Some(&format!("reward reserved for a stake on idea {}", idea_id)),
+            vec![
+                JournalLine {
+                    account_id: rewards_account,
+                    amount: -reserve_units,
+                },
+                JournalLine {
+                    account_id: ledger::system_account(STAKING_ACCOUNT, TRADE_CURRENCY),
+                    amount: reserve_units,
+                },
+            ],
+        )
+        .await?;
+    }
+    uow.commit().await?;
+
+    utils::log(&format!(
+        "[Staking] {} staked {} on idea {} for {} days",
+        user_id, amount, idea_id, lockup_days
+    ));
+    Ok(stake)
+}
+
+/// Unlocks a stake's tokens and pays its reward, returning the unearned rest of its reserve to
+/// the rewards pool.
+pub async fn unstake(pool: &PgPool, stake_id: &str, user_id: &str) -> Result<StakeView, DbError> {
+    let mut client = pool.get().await?;
+    let uow = PgUnitOfWork::begin(&mut client).await?;
+    let stake = stakes::lock_stake(uow.client(), stake_id).await?;
+    if stake.user_id != user_id {
+        return Err(DbError::Forbidden(format!(
+            "Stake {} belongs to another user",
+            stake_id
+        )));
+    }
+    if stake.status != STAKE_ACTIVE {
+        return Err(DbError::ValidationError(format!(
+            "Stake {} is not active",
+            stake_id
+        )));
+    }
+
+    let idea = uow.idea_by_id(&stake.idea_id).await?;
+    let now = Utc::now();
+    if !is_unlocked(&stake, &idea, now) {
+        return Err(DbError::ValidationError(format!(
+            "Stake {} is locked until {}",
+            stake_id,
+            stake
+                .unlock_at
+                .map(|unlock_at| unlock_at.to_rfc3339())
+                .unwrap_or_default()
+        )));
+    }
+
+    let exit_price = exit_price(uow.client(), &stake, &idea, now).await?;
+    let reward = accrued_reward(&stake, &idea, exit_price, now);
+    let reward_units = TRADE_CURRENCY.to_units(reward, Rounding::Down)?;
+    let reserve_units = TRADE_CURRENCY.to_units(stake.reserved_reward, Rounding::Down)?;
+
+    let lines: Vec<JournalLine> = [
+        (STAKING_ACCOUNT, -reserve_units),
+        (STAKING_REWARDS_ACCOUNT, reserve_units - reward_units),
+    ]
+    .into_iter()
+    .map(|(account, amount)| JournalLine {
+        account_id: ledger::system_account(account, TRADE_CURRENCY),
+        amount,
+    })
+    .chain([JournalLine {
+        account_id: ledger::user_account(user_id, TRADE_CURRENCY),
+        amount: reward_units,
+    }])
+    .filter(|line| line.amount != 0)
+    .collect();
+    if !lines.is_empty() {
+        ledger::post_entry(
+            uow.client(),
+            ENTRY_UNSTAKE,
+            TRADE_CURRENCY,
+            Some(&format!("unstake:{}", stake_id)),
+            Some(&format!("unstake from idea {}", stake.idea_id)),
+            lines,
+        )
+        .await?;
+    }
+    // Record the reward actually paid, at the ledger's precision
+    let reward = TRADE_CURRENCY.to_amount(reward_units)?;
+    let stake = stakes::complete_unstake(uow.client(), stake_id, reward).await?;
+    uow.commit().await?;
+
+    utils::log(&format!(
+        "[Staking] {} unstaked {} with a reward of {}",
+        user_id, stake_id, reward
+    ));
+    Ok(StakeView {
+        stake,
+        accrued_reward: reward,
+        unlocked: true,
+    })
+}
+
+/// Every stake on an idea with its reward so far.
+pub async fn idea_stakes(pool: &PgPool, idea_id: &str) -> Result<IdeaStakes, DbError> {
+    let client = pool.get().await?;
+    let repo = PgRepo::new(&client);
+    let idea = repo.idea_by_id(idea_id).await?;
+    let now = Utc::now();
+
+    let stakes = stakes::get_idea_stakes(&client, idea_id).await?;
+    let total_staked = stakes
+        .iter()
+        .filter(|stake| stake.status == STAKE_ACTIVE)
+        .map(|stake| stake.amount)
+        .sum();
+    let mut views = Vec::with_capacity(stakes.len());
+    for stake in stakes {
+        let active = stake.status == STAKE_ACTIVE;
+        let accrued_reward = match stake.reward {
+            Some(reward) if !active => reward,
+            _ => {
+                let exit_price = exit_price(&client, &stake, &idea, now).await?;
+                accrued_reward(&stake, &idea, exit_price, now)
+            }
+        };
+        views.push(StakeView {
+            accrued_reward,
+            unlocked: !active || is_unlocked(&stake, &idea, now),
+            stake,
+        });
+    }
+
+    Ok(IdeaStakes {
+        idea_id: idea_id.to_string(),
+        total_staked,
+        stakes: views,
+    })
+}
+
+/// Moves `amount` of the user's cash into the pool staking rewards are paid from.
+pub async fn fund_rewards(
+    pool: &PgPool,
+    user_id: &str,
+    amount: Decimal,
+) -> Result<JournalEntry, DbError> {
+    let units = TRADE_CURRENCY.to_units(amount, Rounding::Down)?;
+    let mut client = pool.get().await?;
+    let tx = client.transaction().await?;
+    let entry = ledger::fund_staking_rewards(&tx, user_id, units).await?;
+    tx.commit().await?;
+
+    utils::log(&format!(
+        "[Staking] {} added {} to the rewards pool",
+        user_id,
+        TRADE_CURRENCY.to_amount(units)?
+    ));
+    Ok(entry)
+}
+
+/// Cash in the rewards pool not yet reserved by a stake.
+pub async fn rewards_pool(pool: &PgPool) -> Result<Decimal, DbError> {
+    let client = pool.get().await?;
+    let account = ledger::system_account(STAKING_REWARDS_ACCOUNT, TRADE_CURRENCY);
+    let units = ledger::get_account_balance(&client, &account).await?;
+    TRADE_CURRENCY.to_amount(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn idea(status: &str) -> Idea {
+        let mut idea = Idea::new(
+            "Idea".into(),
+            "Description".into(),
+            "creator".into(),
+            "Technology".into(),
+            Decimal::from(1),
+            Decimal::from(2),
+            "1y".into(),
+            3,
+            "Large".into(),
+            "None".into(),
+        );
+        idea.status = status.to_string();
+        idea
+    }
+
+    /// 1,000 tokens staked at 1 with a reserve that never binds.
+    fn stake(lockup_days: i32, staked_at: DateTime<Utc>) -> Stake {
+        Stake {
+            id: "stake".into(),
+            idea_id: "idea".into(),
+            user_id: "user".into(),
+            amount: Decimal::from(1_000),
+            reserved_reward: Decimal::from(1_000),
+            lockup_days,
+            entry_price: Decimal::from(1),
+            status: STAKE_ACTIVE.to_string(),
+            reward: None,
+            staked_at: Some(staked_at),
+            unlock_at: Some(staked_at + Duration::days(lockup_days as i64)),
+            unstaked_at: None,
+        }
+    }
+
+    #[test]
+    fn longer_lockups_earn_a_higher_rate() {
+        let staked_at = Utc::now() - Duration::days(7);
+        let now = staked_at + Duration::days(7);
+        let active = idea("active");
+        let one = Decimal::from(1);
+
+        // 1,000 * 2% a year for a week
+        assert_eq!(
+            accrued_reward(&stake(7, staked_at), &active, one, now),
+            dec("0.38356164")
+        );
+        // 1,000 * 15% a year for a week
+        assert_eq!(
+            accrued_reward(&stake(180, staked_at), &active, one, now),
+            dec("2.87671232")
+        );
+        assert_eq!(
+            accrued_reward(&stake(14, staked_at), &active, one, now),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn performance_scales_the_reward_up_to_the_cap() {
+        let staked_at = Utc::now() - Duration::days(7);
+        let now = staked_at + Duration::days(7);
+        let active = idea("active");
+        let week = stake(7, staked_at);
+
+        assert_eq!(
+            accrued_reward(&week, &active, dec("0.5"), now),
+            dec("0.19178082")
+        );
+        assert_eq!(
+            accrued_reward(&week, &active, Decimal::from(2), now),
+            dec("0.76712328")
+        );
+        // Ten times the entry price pays no more than three times the base reward
+        assert_eq!(
+            accrued_reward(&week, &active, Decimal::from(10), now),
+            dec("1.15068493")
+        );
+    }
+
+    #[test]
+    fn reward_never_exceeds_the_reserve() {
+        let staked_at = Utc::now() - Duration::days(7);
+        let mut week = stake(7, staked_at);
+        week.reserved_reward = dec("0.1");
+
+        let reward = accrued_reward(
+            &week,
+            &idea("active"),
+            Decimal::from(3),
+            staked_at + Duration::days(7),
+        );
+        assert_eq!(reward, dec("0.1"));
+    }
+
+    #[test]
+    fn accrual_stops_at_unlock_or_settlement() {
+        let staked_at = Utc::now() - Duration::days(30);
+        let week = stake(7, staked_at);
+        let one = Decimal::from(1);
+
+        // Unstaking late earns no more than the lockup
+        assert_eq!(
+            accrued_reward(&week, &idea("active"), one, staked_at + Duration::days(30)),
+            dec("0.38356164")
+        );
+
+        let mut resolved = idea("resolved");
+        resolved.resolved_at = Some(staked_at + Duration::days(3));
+        assert_eq!(
+            accrued_reward(&week, &resolved, one, staked_at + Duration::days(30)),
+            dec("0.16438356")
+        );
+
+        let mut voided = idea("voided");
+        voided.resolved_at = Some(staked_at + Duration::days(3));
+        assert_eq!(
+            accrued_reward(&week, &voided, one, staked_at + Duration::days(30)),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn exit_price_is_the_settlement_then_the_average_then_the_entry() {
+        let week = stake(7, Utc::now());
+        let settled = Some(Decimal::from(2));
+        let average = Some(dec("1.5"));
+
+        assert_eq!(
+            choose_exit_price(&week, &idea("resolved"), settled, average),
+            Decimal::from(2)
+        );
+        // A settlement price only counts once the idea is resolved
+        assert_eq!(
+            choose_exit_price(&week, &idea("active"), settled, average),
+            dec("1.5")
+        );
+        assert_eq!(
+            choose_exit_price(&week, &idea("active"), None, None),
+            week.entry_price
+        );
+    }
+}
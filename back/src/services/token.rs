@@ -0,0 +1,303 @@
+use crate::config::CONFIG;
+use crate::db::token_syncs;
+use crate::db::{self, DbError, PgPool};
+use crate::decimal::{Decimal, Rounding};
+use crate::handlers::solana::backend_keypair;
+use crate::services::solana_service::SolanaServiceError;
+use crate::utils;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::hash;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction as SolanaTransaction;
+use spl_token::state::{Account, Mint};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+
+/// Decimals of every idea mint; holdings are rounded to this precision on-chain.
+pub const TOKEN_DECIMALS: u8 = 6;
+
+/// Queued syncs claimed at a time.
+const SYNC_BATCH: i64 = 50;
+/// How long a claimed sync is held off from other instances before it may be retried.
+const SYNC_LEASE_SECS: f64 = 300.0;
+
+/// Mints one SPL token per idea and mirrors each holder's net position on-chain.
+///
+/// Trades queue the holdings they change in `token_syncs`, and the syncer mirrors them.
+///
+/// Holdings live in token accounts owned by the backend keypair, one per idea and user at an
+/// address derived from both, so they can be minted and burned without the user's signature.
+#[derive(Clone)]
+pub struct TokenService {
+    pool: PgPool,
+    rpc_client: Arc<RpcClient>,
+    // Syncs read then write a balance, so they run one at a time
+    sync_lock: Arc<Mutex<()>>,
+    wake: Arc<Notify>,
+}
+
+impl TokenService {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_rpc_client(
+            pool,
+            RpcClient::new_with_commitment(
+                CONFIG.solana_rpc_url.clone(),
+                CommitmentConfig::confirmed(),
+            ),
+        )
+    }
+
+    pub fn with_rpc_client(pool: PgPool, rpc_client: RpcClient) -> Self {
+        Self {
+            pool,
+            rpc_client: Arc::new(rpc_client),
+            sync_lock: Arc::new(Mutex::new(())),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    fn authority(&self) -> Result<Arc<Keypair>, SolanaServiceError> {
+        backend_keypair().map_err(SolanaServiceError::NotConfigured)
+    }
+
+    async fn send(
+        &self,
+        instructions: &[solana_sdk::instruction::Instruction],
+        signers: &[&Keypair],
+    ) -> Result<String, SolanaServiceError> {
+        let recent_blockhash =
+            self.rpc_client.get_latest_blockhash().await.map_err(|e| {
+                SolanaServiceError::RpcError(format!("Failed to get blockhash: {}", e))
+            })?;
+        let transaction = SolanaTransaction::new_signed_with_payer(
+            instructions,
+            Some(&signers[0].pubkey()),
+            signers,
+            recent_blockhash,
+        );
+        self.rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await
+            .map(|signature| signature.to_string())
+            .map_err(|e| SolanaServiceError::RpcError(format!("Transaction failed: {}", e)))
+    }
+
+    /// Creates the mint at `mint`'s address, with the backend keypair as its mint authority.
+    pub async fn create_mint(&self, mint: &Keypair) -> Result<(), SolanaServiceError> {
+        let authority = self.authority()?;
+        let rent = self
+            .rpc_client
+            .get_minimum_balance_for_rent_exemption(Mint::LEN)
+            .await
+            .map_err(|e| SolanaServiceError::RpcError(format!("Failed to get rent: {}", e)))?;
+
+        let instructions = [
+            system_instruction::create_account(
+                &authority.pubkey(),
+                &mint.pubkey(),
+                rent,
+                Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_mint2(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &authority.pubkey(),
+                None,
+                TOKEN_DECIMALS,
+            )
+            .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?,
+        ];
+        self.send(&instructions, &[&authority, mint]).await?;
+
+        utils::log(&format!("[Token] Created mint {}", mint.pubkey()));
+        Ok(())
+    }
+
+    // Seed of a holder's token account; seeds are limited to 32 characters
+    fn holding_seed(mint: &Pubkey, user_id: &str) -> String {
+        let digest = hash(format!("{}:{}", mint, user_id).as_bytes());
+        digest.to_string().chars().take(32).collect()
+    }
+
+    /// Token account holding `user_id`'s tokens of `mint`.
+    pub fn holding_account(
+        &self,
+        mint: &Pubkey,
+        user_id: &str,
+    ) -> Result<Pubkey, SolanaServiceError> {
+        let authority = self.authority()?;
+        Pubkey::create_with_seed(
+            &authority.pubkey(),
+            &Self::holding_seed(mint, user_id),
+            &spl_token::id(),
+        )
+        .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))
+    }
+
+    /// Mints or burns so the user's token account matches their net holding in the idea.
+    /// Short positions are mirrored as an empty account.
+    pub async fn sync_holding(
+        &self,
+        idea_id: &str,
+        mint: &str,
+        user_id: &str,
+    ) -> Result<(), DbError> {
+        let mint = Pubkey::from_str(mint)
+            .map_err(|_| DbError::ValidationError(format!("Invalid mint: {}", mint)))?;
+        let authority = self.authority()?;
+        let account = self.holding_account(&mint, user_id)?;
+
+        let _guard = self.sync_lock.lock().await;
+
+        let client = self.pool.get().await?;
+        let holding = db::get_user_holding(&client, idea_id, user_id).await?;
+        let desired = holding
+            .max(Decimal::ZERO)
+            .to_units(TOKEN_DECIMALS as u32, Rounding::Down)
+            .ok_or_else(|| DbError::ValidationError(format!("Holding {} is too large", holding)))?
+            as u64;
+
+        let existing = self
+            .rpc_client
+            .get_account_with_commitment(&account, CommitmentConfig::confirmed())
+            .await
+            .map_err(|e| SolanaServiceError::RpcError(format!("Failed to get account: {}", e)))?
+            .value;
+
+        let mut instructions = Vec::new();
+        let current = match existing {
+            Some(existing) => {
+                Account::unpack(&existing.data)
+                    .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?
+                    .amount
+            }
+            None => {
+                let rent = self
+                    .rpc_client
+                    .get_minimum_balance_for_rent_exemption(Account::LEN)
+                    .await
+                    .map_err(|e| {
+                        SolanaServiceError::RpcError(format!("Failed to get rent: {}", e))
+                    })?;
+                instructions.push(system_instruction::create_account_with_seed(
+                    &authority.pubkey(),
+                    &account,
+                    &authority.pubkey(),
+                    &Self::holding_seed(&mint, user_id),
+                    rent,
+                    Account::LEN as u64,
+                    &spl_token::id(),
+                ));
+                instructions.push(
+                    spl_token::instruction::initialize_account3(
+                        &spl_token::id(),
+                        &account,
+                        &mint,
+                        &authority.pubkey(),
+                    )
+                    .map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?,
+                );
+                0
+            }
+        };
+
+        let adjustment = if desired > current {
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &mint,
+                &account,
+                &authority.pubkey(),
+                &[],
+                desired - current,
+            )
+        } else {
+            spl_token::instruction::burn(
+                &spl_token::id(),
+                &account,
+                &mint,
+                &authority.pubkey(),
+                &[],
+                current - desired,
+            )
+        };
+        if desired != current {
+            instructions
+                .push(adjustment.map_err(|e| SolanaServiceError::InvalidData(e.to_string()))?);
+        }
+        if instructions.is_empty() {
+            return Ok(());
+        }
+
+        let signature = self.send(&instructions, &[&authority]).await?;
+        utils::log(&format!(
+            "[Token] Synced {} of idea {} to {} units in {}",
+            user_id, idea_id, desired, signature
+        ));
+        Ok(())
+    }
+
+    /// Wakes the syncer so trades just committed are mirrored without waiting for its timer.
+    pub fn notify_trades(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Runs `sync_pending` every `TOKEN_SYNC_INTERVAL_SECS` and whenever trades are committed.
+    pub fn start_syncer(&self) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(CONFIG.token_sync_interval_secs));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = service.wake.notified() => {}
+                }
+                match service.sync_pending().await {
+                    Ok(0) => {}
+                    Ok(synced) => utils::log(&format!("[Token] Synced {} holding(s)", synced)),
+                    Err(e) => utils::log(&format!("[Token] Sync pass failed: {}", e)),
+                }
+            }
+        })
+    }
+
+    /// Mirrors the holdings queued by committed trades, rescheduling those that fail.
+    /// Returns how many were synced.
+    pub async fn sync_pending(&self) -> Result<usize, DbError> {
+        let client = self.pool.get().await?;
+        let mut synced = 0;
+        loop {
+            let due = token_syncs::claim_due(&client, SYNC_BATCH, SYNC_LEASE_SECS).await?;
+            if due.is_empty() {
+                return Ok(synced);
+            }
+            for sync in &due {
+                match self
+                    .sync_holding(&sync.idea_id, &sync.mint, &sync.user_id)
+                    .await
+                {
+                    Ok(()) => {
+                        token_syncs::complete(&client, sync).await?;
+                        synced += 1;
+                    }
+                    Err(e) => {
+                        utils::log(&format!(
+                            "[Token] Failed to sync {} of idea {} (attempt {}): {}",
+                            sync.user_id, sync.idea_id, sync.attempts, e
+                        ));
+                        token_syncs::fail(&client, sync, &e.to_string()).await?;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::config::CONFIG;
+use crate::db::trending::{self, TrendingSignals};
+use crate::db::{DbError, PgPool};
+use crate::decimal::Decimal;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Activity older than this does not count.
+const WINDOW_SECS: f64 = 24.0 * 3600.0;
+/// A trade's weight halves every six hours, so recent activity dominates the window.
+const HALF_LIFE_SECS: f64 = 6.0 * 3600.0;
+
+// Score weights. Volume, buyers and votes are log-scaled so a single whale cannot dominate.
+const VOLUME_WEIGHT: f64 = 1.0;
+const BUYERS_WEIGHT: f64 = 2.0;
+const MOMENTUM_WEIGHT: f64 = 3.0;
+const VOTES_WEIGHT: f64 = 1.5;
+/// Price moves beyond ±100% over the window score the same.
+const MAX_MOMENTUM: f64 = 1.0;
+
+/// Ideas kept in the ranking.
+pub const MAX_TRENDING: usize = 50;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrendingIdea {
+    pub rank: usize,
+    pub idea_id: String,
+    pub title: String,
+    pub category: String,
+    pub current_price: Decimal,
+    /// Price change over the window, in percent.
+    pub change_percentage: f64,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrendingRanking {
+    pub ideas: Vec<TrendingIdea>,
+    /// `None` until the first refresh completes.
+    pub refreshed_at: Option<DateTime<Utc>>,
+}
+
+/// Ranks ideas by recent, time-decayed trading and voting activity and price momentum. The ranking is
+/// recomputed in the background and served from memory.
+#[derive(Clone)]
+pub struct TrendingService {
+    pool: PgPool,
+    ranking: Arc<RwLock<TrendingRanking>>,
+}
+
+fn momentum(signals: &TrendingSignals) -> f64 {
+    let start = signals.window_start_price.to_f64();
+    if start <= 0.0 {
+        return 0.0;
+    }
+    (signals.current_price.to_f64() - start) / start
+}
+
+fn score(signals: &TrendingSignals) -> f64 {
+    VOLUME_WEIGHT * signals.decayed_volume.ln_1p()
+        + BUYERS_WEIGHT * signals.decayed_buyers.ln_1p()
+        + MOMENTUM_WEIGHT * momentum(signals).clamp(-MAX_MOMENTUM, MAX_MOMENTUM)
+        + VOTES_WEIGHT * signals.decayed_votes.signum() * signals.decayed_votes.abs().ln_1p()
+}
+
+impl TrendingService {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            ranking: Arc::new(RwLock::new(TrendingRanking::default())),
+        }
+    }
+
+    /// The top `limit` ideas of the latest ranking.
+    pub fn top(&self, limit: usize) -> TrendingRanking {
+        let ranking = self.ranking.read().unwrap_or_else(|e| e.into_inner());
+        TrendingRanking {
+            ideas: ranking.ideas.iter().take(limit).cloned().collect(),
+            refreshed_at: ranking.refreshed_at,
+        }
+    }
+
+    /// Runs `refresh` every `TRENDING_REFRESH_SECS`, starting immediately.
+    pub fn start_refresher(&self) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(CONFIG.trending_refresh_secs));
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.refresh().await {
+                    utils::log(&format!("[Trending] Refresh failed: {}", e));
+                }
+            }
+        })
+    }
+
+    /// Recomputes the ranking, leaving out ideas that score zero or less.
+    pub async fn refresh(&self) -> Result<(), DbError> {
+        let client = self.pool.get().await?;
+        let signals = trending::get_trending_signals(&client, WINDOW_SECS, HALF_LIFE_SECS).await?;
+
+        let mut scored: Vec<(f64, TrendingSignals)> = signals
+            .into_iter()
+            .map(|signals| (score(&signals), signals))
+            .filter(|(score, _)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| a.1.idea_id.cmp(&b.1.idea_id))
+        });
+        scored.truncate(MAX_TRENDING);
+
+        let ideas = scored
+            .into_iter()
+            .enumerate()
+            .map(|(i, (score, signals))| TrendingIdea {
+                rank: i + 1,
+                change_percentage: momentum(&signals) * 100.0,
+                idea_id: signals.idea_id,
+                title: signals.title,
+                category: signals.category,
+                current_price: signals.current_price,
+                score,
+            })
+            .collect();
+
+        *self.ranking.write().unwrap_or_else(|e| e.into_inner()) = TrendingRanking {
+            ideas,
+            refreshed_at: Some(Utc::now()),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::db::{self, PRICING_CONSTANT_PRODUCT, PRICING_ORDER_BOOK};
+use crate::decimal::Decimal;
+use crate::error::{ApiError, FieldError};
+use crate::services::staking::LOCKUP_TIERS;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use solana_sdk::pubkey::Pubkey;
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::str::FromStr;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+/// Parameter naming the field a struct-level (`schema`) check rejects, since those are
+/// otherwise reported under `__all__`.
+const FIELD_PARAM: &str = "field";
+
+/// Runs the inner extractor, e.g. `web::Json<T>` or `web::Query<T>`, then validates the
+/// extracted `T`. Requests breaking any rule are rejected with every failure listed before
+/// the handler runs.
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Valid<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for Valid<T>
+where
+    T: FromRequest + Deref + 'static,
+    T::Target: Validate,
+    T::Future: 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let extract = T::from_request(req, payload);
+        Box::pin(async move {
+            let value = extract.await.map_err(Into::into)?;
+            value.validate().map_err(ApiError::from)?;
+            Ok(Valid(value))
+        })
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut details: Vec<FieldError> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |e| {
+                    let field = match e.params.get(FIELD_PARAM).and_then(|f| f.as_str()) {
+                        Some(named) => named.to_string(),
+                        None => field.to_string(),
+                    };
+                    FieldError::new(field, describe(e))
+                })
+            })
+            .collect();
+        // Errors come out of a map, so order them for stable responses
+        details.sort_by(|a, b| a.field.cmp(&b.field));
+        ApiError::invalid_fields(details)
+    }
+}
+
+fn describe(e: &ValidationError) -> String {
+    if let Some(message) = &e.message {
+        return message.to_string();
+    }
+    let min = e.params.get("min");
+    let max = e.params.get("max");
+    let unit = if e.code == "length" {
+        " characters"
+    } else {
+        ""
+    };
+    match (min, max) {
+        (Some(min), Some(max)) => format!("Must be between {} and {}{}", min, max, unit),
+        (Some(min), None) => format!("Must be at least {}{}", min, unit),
+        (None, Some(max)) => format!("Must be at most {}{}", max, unit),
+        (None, None) => format!("Failed the {} check", e.code),
+    }
+}
+
+fn invalid(code: &'static str, message: impl Into<Cow<'static, str>>) -> ValidationError {
+    ValidationError::new(code).with_message(message.into())
+}
+
+/// Rejects `field` from a struct-level check.
+pub fn field_error(field: &'static str, message: &'static str) -> ValidationError {
+    let mut error = invalid(field, message);
+    error.add_param(FIELD_PARAM.into(), &field);
+    error
+}
+
+fn one_of(code: &'static str, value: &str, allowed: &[&str]) -> Result<(), ValidationError> {
+    if allowed.contains(&value) {
+        Ok(())
+    } else {
+        Err(invalid(
+            code,
+            format!("Must be one of: {}", allowed.join(", ")),
+        ))
+    }
+}
+
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        Err(invalid("not_blank", "Cannot be blank"))
+    } else {
+        Ok(())
+    }
+}
+
+/// A base58-encoded Solana public key.
+pub fn pubkey(value: &str) -> Result<(), ValidationError> {
+    Pubkey::from_str(value)
+        .map(|_| ())
+        .map_err(|_| invalid("pubkey", "Must be a base58-encoded public key"))
+}
+
+pub fn positive(value: &Decimal) -> Result<(), ValidationError> {
+    if value.is_positive() {
+        Ok(())
+    } else {
+        Err(invalid("positive", "Must be positive"))
+    }
+}
+
+pub fn non_negative(value: &Decimal) -> Result<(), ValidationError> {
+    if value.is_negative() {
+        Err(invalid("non_negative", "Cannot be negative"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn idea_category(value: &str) -> Result<(), ValidationError> {
+    one_of("category", value, db::IDEA_CATEGORIES)
+}
+
+pub fn idea_status(value: &str) -> Result<(), ValidationError> {
+    one_of("status", value, db::IDEA_STATUSES)
+}
+
+pub fn idea_timeframe(value: &str) -> Result<(), ValidationError> {
+    one_of("timeframe", value, db::IDEA_TIMEFRAMES)
+}
+
+pub fn pricing_mode(value: &str) -> Result<(), ValidationError> {
+    one_of(
+        "pricing_mode",
+        value,
+        &[PRICING_ORDER_BOOK, PRICING_CONSTANT_PRODUCT],
+    )
+}
+
+/// One of the lockup periods in `staking::LOCKUP_TIERS`.
+pub fn lockup_tier(days: i32) -> Result<(), ValidationError> {
+    if LOCKUP_TIERS.iter().any(|(tier, _)| *tier == days) {
+        return Ok(());
+    }
+    let tiers: Vec<String> = LOCKUP_TIERS.iter().map(|(d, _)| d.to_string()).collect();
+    Err(invalid(
+        "lockup_tier",
+        format!("Must be one of {} days", tiers.join(", ")),
+    ))
+}
+
+/// Checks a new idea's target sits above its initial price.
+pub fn target_above_initial(target: Decimal, initial: Decimal) -> Result<(), ValidationError> {
+    if target > initial {
+        Ok(())
+    } else {
+        Err(field_error(
+            "target_price",
+            "Must be greater than the initial price",
+        ))
+    }
+}
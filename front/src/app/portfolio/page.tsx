@@ -67,7 +67,16 @@ async function fetchUserTransactions(walletAddress: string) {
     }
   );
   if (!response.ok) throw new Error("Failed to fetch transactions");
-  return response.json();
+  // Amounts and prices arrive as decimal strings
+  const transactions: Transaction[] = (await response.json()).map(
+    (tx: Record<string, unknown>) => ({
+      ...tx,
+      amount: Number(tx.amount),
+      rate: Number(tx.rate),
+      total_value: Number(tx.total_value),
+    })
+  );
+  return transactions;
 }
 
 async function updateUsername(walletAddress: string, newUsername: string) {